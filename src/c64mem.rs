@@ -1,4 +1,9 @@
+use crate::cia::*;
+use crate::keyboard::*;
 use crate::memory::*;
+use crate::model::*;
+use crate::sid::*;
+use crate::sidlog::*;
 
 const IO: u16 = 1;
 const DATA_DIR: u16 = 0;
@@ -7,11 +12,17 @@ const LOW: u8 = 1;
 const CHAR: u8 = 2;
 const HIGH: u8 = 4;
 
-#[derive(Clone, Copy)]
 pub struct C64Mem {
    pub ram: [u8; 0x10000],
    rom: [u8; 0x10000],
    io: [u8; 0x1000], 
+   raster_compare: u16,
+   // (base address, chip); the first is the mainboard SID at $D400
+   pub sids: Vec<(u16, Sid)>,
+   pub sid_log: Option<SidLog>,
+   pub cia1: Cia,
+   pub cia2: Cia,
+   pub keyboard: Keyboard,
 }
 
 impl C64Mem {
@@ -26,7 +37,13 @@ impl C64Mem {
       Self {
          ram: ram,
          rom: rom,
-         io: io
+         io: io,
+         raster_compare: 0,
+         sids: vec![(SID_ADDRESS, Sid::new(SidModel::Mos6581, VicModel::Mos6569.cpu_clock()))],
+         sid_log: None,
+         cia1: Cia::new(CiaModel::Mos6526),
+         cia2: Cia::new(CiaModel::Mos6526),
+         keyboard: Keyboard::new()
       }
    }
 
@@ -41,21 +58,117 @@ impl C64Mem {
          self.rom[address as usize + i] = data[i];
       }
    }
+
+   fn read_vic_register(&mut self, addr: u16) -> u8 {
+      let reg = addr & 0x3F;
+      match reg {
+         0x19        => self.io[0x19] | 0x70,
+         0x1A        => self.io[0x1A] | 0xF0,
+         0x1E | 0x1F => {
+            // collision registers are cleared by reading them
+            let value = self.io[reg as usize];
+            self.io[reg as usize] = 0;
+            value
+         },
+         0x20..=0x2E => self.io[reg as usize] | 0xF0,
+         0x2F..=0x3F => 0xFF,
+         _           => self.io[reg as usize]
+      }
+   }
+
+   fn write_vic_register(&mut self, addr: u16, value: u8) {
+      let reg = addr & 0x3F;
+      match reg {
+         0x11        => {
+            // bit 7 reads back the raster counter, writes go to the compare latch
+            self.raster_compare = (self.raster_compare & 0xFF) | ((value as u16 & 0x80) << 1);
+            self.io[0x11] = (self.io[0x11] & 0x80) | (value & 0x7F);
+         },
+         0x12        => self.raster_compare = (self.raster_compare & 0x100) | value as u16,
+         0x19        => {
+            self.io[0x19] &= !(value & 0x0F);
+            self.update_vic_irq();
+         },
+         0x1A        => {
+            self.io[0x1A] = value & 0x0F;
+            self.update_vic_irq();
+         },
+         0x13 | 0x14 | 0x1E | 0x1F | 0x2F..=0x3F => {},
+         _           => self.io[reg as usize] = value
+      }
+   }
+
+   // an expansion SID decoded at the address wins over the mirrors of the mainboard one
+   fn sid_index(&self, addr: u16) -> Option<usize> {
+      let base = addr & 0xFFE0;
+      match self.sids.iter().skip(1).position(|(address, _)| *address == base) {
+         Some(index)                               => Some(index + 1),
+         None if (0xD400..=0xD7FF).contains(&addr) => Some(0),
+         None                                      => None
+      }
+   }
+
+   fn read_sid(&mut self, addr: u16) -> u8 {
+      match self.sid_index(addr) {
+         Some(index) => self.sids[index].1.read(addr as u8),
+         None        => self.io[addr as usize - 0xD000]
+      }
+   }
+
+   fn write_sid(&mut self, addr: u16, value: u8) {
+      match self.sid_index(addr) {
+         Some(index) => {
+            let (base, sid) = &mut self.sids[index];
+            sid.write(addr as u8, value);
+            if let Some(log) = &mut self.sid_log {
+               log.record(*base, addr as u8 & 0x1F, value);
+            }
+         },
+         None        => self.io[addr as usize - 0xD000] = value
+      }
+   }
+
+   // the keyboard matrix sits between the two ports of CIA 1
+   fn read_cia1(&mut self, addr: u16) -> u8 {
+      if addr & 0x0F <= 1 {
+         let (port_a, port_b) = self.keyboard.scan(self.cia1.port_a_output(), self.cia1.port_b_output());
+         self.cia1.set_port_a_input(port_a);
+         self.cia1.set_port_b_input(port_b);
+      }
+      self.cia1.read(addr as u8)
+   }
+
+   // the VIC bank is selected by the inverted PA0/PA1 lines of CIA2
+   pub fn vic_bank(&self) -> u16 {
+      (!self.cia2.port_a_output() & 3) as u16 * 0x4000
+   }
+
+   fn update_vic_irq(&mut self) {
+      if self.io[0x19] & self.io[0x1A] & 0x0F != 0 {
+         self.io[0x19] |= 0x80;
+      } else {
+         self.io[0x19] &= 0x7F;
+      }
+   }
 }
 
 impl IOMem for C64Mem {
-   fn read_u8(&self, addr: u16) -> u8 {
+   fn read_u8(&mut self, addr: u16) -> u8 {
       match addr {
          DATA_DIR        => self.ram[DATA_DIR as usize],
          IO              => self.ram[IO as usize],
          0xA000..=0xBFFF => if (self.ram[IO as usize] & (1 << 0)) > 0 { self.rom[addr as usize] } else { self.ram[addr as usize] },
          0xE000..=0xFFFF => if (self.ram[IO as usize] & (1 << 1)) > 0 { self.rom[addr as usize] } else { self.ram[addr as usize] },
+         0xD000..=0xD3FF if (self.ram[IO as usize] & (1 << 2)) > 0 => self.read_vic_register(addr),
+         0xD400..=0xD7FF | 0xDE00..=0xDFFF if (self.ram[IO as usize] & (1 << 2)) > 0 => self.read_sid(addr),
+         0xDC00..=0xDCFF if (self.ram[IO as usize] & (1 << 2)) > 0 => self.read_cia1(addr),
+         0xDD00..=0xDDFF if (self.ram[IO as usize] & (1 << 2)) > 0 => self.cia2.read(addr as u8),
          0xD000..=0xDFFF => if (self.ram[IO as usize] & (1 << 2)) > 0 { self.io[addr as usize - 0xD000] } else { self.ram[addr as usize] },
          _               => self.ram[addr as usize]
       }
    }
 
-   fn read_u16(&self, addr: u16) -> u16 {
+   fn read_u16(&mut self, addr: u16) -> u16 {
       let low = self.read_u8(addr) as u16;
       let high = self.read_u8(addr+1) as u16;
       (high << 8) | low
@@ -65,28 +178,16 @@ impl IOMem for C64Mem {
       match addr {
          DATA_DIR        => self.ram[DATA_DIR as usize] = value,
          IO              => self.ram[IO as usize] = value,
+         0xD000..=0xD3FF if (self.ram[IO as usize] & (1 << 2)) > 0 => self.write_vic_register(addr, value),
+         0xD400..=0xD7FF | 0xDE00..=0xDFFF if (self.ram[IO as usize] & (1 << 2)) > 0 => self.write_sid(addr, value),
+         0xDC00..=0xDCFF if (self.ram[IO as usize] & (1 << 2)) > 0 => self.cia1.write(addr as u8, value),
+         0xDD00..=0xDDFF if (self.ram[IO as usize] & (1 << 2)) > 0 => self.cia2.write(addr as u8, value),
          0xD000..=0xDFFF => if (self.ram[IO as usize] & (1 << 2)) > 0 { self.io[addr as usize - 0xD000] = value } else { self.ram[addr as usize] = value },
          _               => self.ram[addr as usize] = value
       }
    }
 }
 
-impl IOCia for C64Mem {
-   fn read(&self, address: u16) -> u8 {
-      match address {
-         0xDC00..=0xDDFF => self.io[address as usize - 0xD000],
-         _               => panic!("CIA-II READ Out Of Range: {:#X}", address)
-      }
-   }
-
-   fn write(&mut self, address: u16, value: u8) {
-      match address {
-         0xDC00..=0xDDFF => self.io[address as usize - 0xD000] = value,
-         _               => panic!("CIA-II WRITE Out Of Range: {:#X}", address)
-      }
-   }
-}
-
 impl IOVic for C64Mem {
    fn read(&self, addr: u16) -> u8 {
       match addr {
@@ -101,4 +202,30 @@ impl IOVic for C64Mem {
          _               => panic!("VIC REGISTER WRITE Out Of Range: {:#X}", addr)  
       }
    }
+
+   fn fetch(&self, addr: u16) -> u8 {
+      self.fetch_bank(self.vic_bank(), addr)
+   }
+
+   fn fetch_bank(&self, bank: u16, addr: u16) -> u8 {
+      let bank = bank & 0xC000;
+      let addr = addr & 0x3FFF;
+      match (bank, addr) {
+         (0x0000, 0x1000..=0x1FFF) |
+         (0x8000, 0x1000..=0x1FFF) => self.rom[0xD000 + (addr as usize & 0x0FFF)],
+         _                         => self.ram[(bank | addr) as usize]
+      }
+   }
+
+   fn bank(&self) -> u16 {
+      self.vic_bank()
+   }
+
+   fn fetch_color(&self, index: u16) -> u8 {
+      self.io[0x800 + (index & 0x3FF) as usize] & 0x0F
+   }
+
+   fn raster_compare(&self) -> u16 {
+      self.raster_compare
+   }
 }
\ No newline at end of file
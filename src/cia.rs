@@ -0,0 +1,849 @@
+use crate::model::*;
+
+const PRA             : u8 = 0x00;
+const PRB             : u8 = 0x01;
+const DDRA            : u8 = 0x02;
+const DDRB            : u8 = 0x03;
+const TA_LO           : u8 = 0x04;
+const TA_HI           : u8 = 0x05;
+const TB_LO           : u8 = 0x06;
+const TB_HI           : u8 = 0x07;
+const TOD_10THS       : u8 = 0x08;
+const TOD_SEC         : u8 = 0x09;
+const TOD_MIN         : u8 = 0x0A;
+const TOD_HR          : u8 = 0x0B;
+const SDR             : u8 = 0x0C;
+const ICR             : u8 = 0x0D;
+const CRA             : u8 = 0x0E;
+
+// control register bits, CRA and CRB alike
+const START           : u8 = 0x01;
+const PB_ON           : u8 = 0x02;
+const TOGGLE          : u8 = 0x04;
+const ONE_SHOT        : u8 = 0x08;
+const FORCE_LOAD      : u8 = 0x10;
+// CRA bit 6: the serial port shifts out, clocked by timer A
+const SP_OUTPUT       : u8 = 0x40;
+// CRA bit 7: the TOD input is 50 Hz instead of 60 Hz
+const TOD_50HZ        : u8 = 0x80;
+// CRB bit 7: TOD writes set the alarm
+const TOD_ALARM       : u8 = 0x80;
+// CRA bit 5, CRB bits 5-6: what the timer counts
+const INMODE_A        : u8 = 0x20;
+const INMODE_B        : u8 = 0x60;
+const COUNT_CNT       : u8 = 0x20;
+const COUNT_TA        : u8 = 0x40;
+const COUNT_TA_CNT    : u8 = 0x60;
+
+// interrupt sources in ICR
+const INT_TA          : u8 = 0x01;
+const INT_TB          : u8 = 0x02;
+const INT_ALARM       : u8 = 0x04;
+const INT_SP          : u8 = 0x08;
+const INT_SET         : u8 = 0x80;
+
+// the timers and the interrupt output work through a pipeline that is shifted once per
+// cycle, which gives the 6526 its delays between a register write and the effect
+const COUNT_A0        : u32 = 1 << 0;
+const COUNT_A1        : u32 = 1 << 1;
+const COUNT_A2        : u32 = 1 << 2;
+const COUNT_A3        : u32 = 1 << 3;
+const COUNT_B0        : u32 = 1 << 4;
+const COUNT_B1        : u32 = 1 << 5;
+const COUNT_B2        : u32 = 1 << 6;
+const COUNT_B3        : u32 = 1 << 7;
+const LOAD_A0         : u32 = 1 << 8;
+const LOAD_A1         : u32 = 1 << 9;
+const LOAD_B0         : u32 = 1 << 10;
+const LOAD_B1         : u32 = 1 << 11;
+const INTERRUPT0      : u32 = 1 << 12;
+const INTERRUPT1      : u32 = 1 << 13;
+const ONE_SHOT_A0     : u32 = 1 << 14;
+const ONE_SHOT_B0     : u32 = 1 << 15;
+
+const PM              : u8 = 0x80;
+// the first stage of each chain is only ever set from the feed
+const DELAY_MASK      : u32 = !(COUNT_A0 | COUNT_B0 | LOAD_A0 | LOAD_B0 | INTERRUPT0 | ONE_SHOT_A0 | ONE_SHOT_B0)
+                              & ((1 << 16) - 1);
+
+// MOS 6526 Complex Interface Adapter
+pub struct Cia {
+   model: CiaModel,
+   pra: u8,
+   prb: u8,
+   ddra: u8,
+   ddrb: u8,
+   // levels driven onto the ports from outside, pulled up when nothing is connected
+   port_a_in: u8,
+   port_b_in: u8,
+   latch_a: u16,
+   latch_b: u16,
+   counter_a: u16,
+   counter_b: u16,
+   cra: u8,
+   crb: u8,
+   icr: u8,
+   imr: u8,
+   irq: bool,
+   delay: u32,
+   feed: u32,
+   // CNT and SP are open collector: a line is high unless the CIA or a device pulls it low
+   cnt: bool,
+   cnt_out: bool,
+   cnt_in: bool,
+   sp_out: bool,
+   sp_in: bool,
+   shift: u8,
+   shift_bits: u8,
+   sdr_loaded: bool,
+   // PB6 and PB7 timer outputs
+   pb_toggle: u8,
+   pb_pulse: u8,
+   // time of day as tenths, seconds, minutes, hours in BCD; bit 7 of the hours is PM
+   tod: [u8; 4],
+   tod_alarm: [u8; 4],
+   // reading the hours freezes what the registers show until the tenths are read
+   tod_latch: Option<[u8; 4]>,
+   // writing the hours stops the clock until the tenths are written
+   tod_stopped: bool,
+   tod_divider: u8,
+   sdr: u8,
+   // timer interrupts the old 6526 flags in the next cycle
+   late_interrupts: u8,
+   icr_read: bool,
+}
+
+impl Cia {
+   pub fn new(model: CiaModel) -> Self {
+      Self {
+         model: model,
+         pra: 0,
+         prb: 0,
+         ddra: 0,
+         ddrb: 0,
+         port_a_in: 0xFF,
+         port_b_in: 0xFF,
+         latch_a: 0xFFFF,
+         latch_b: 0xFFFF,
+         counter_a: 0,
+         counter_b: 0,
+         cra: 0,
+         crb: 0,
+         icr: 0,
+         imr: 0,
+         irq: false,
+         delay: 0,
+         feed: 0,
+         cnt: true,
+         cnt_out: true,
+         cnt_in: true,
+         sp_out: true,
+         sp_in: true,
+         shift: 0,
+         shift_bits: 0,
+         sdr_loaded: false,
+         pb_toggle: 0,
+         pb_pulse: 0,
+         tod: [0, 0, 0, 1],
+         tod_alarm: [0; 4],
+         tod_latch: None,
+         tod_stopped: false,
+         tod_divider: 0,
+         sdr: 0,
+         late_interrupts: 0,
+         icr_read: false,
+      }
+   }
+
+   // what the CIA itself drives onto port A: inputs float high
+   pub fn port_a_output(&self) -> u8 {
+      self.pra | !self.ddra
+   }
+
+   pub fn port_b_output(&self) -> u8 {
+      let mut value = self.prb | !self.ddrb;
+      if self.cra & PB_ON != 0 {
+         let level = if self.cra & TOGGLE != 0 { self.pb_toggle } else { self.pb_pulse };
+         value = (value & !0x40) | (level & 0x40);
+      }
+      if self.crb & PB_ON != 0 {
+         let level = if self.crb & TOGGLE != 0 { self.pb_toggle } else { self.pb_pulse };
+         value = (value & !0x80) | (level & 0x80);
+      }
+      value
+   }
+
+   pub fn set_port_a_input(&mut self, value: u8) {
+      self.port_a_in = value;
+   }
+
+   pub fn set_port_b_input(&mut self, value: u8) {
+      self.port_b_in = value;
+   }
+
+   // the IRQ output, wired to the CPU's IRQ on CIA 1 and to NMI on CIA 2
+   pub fn irq(&self) -> bool {
+      self.irq
+   }
+
+   pub fn read(&mut self, reg: u8) -> u8 {
+      let reg = reg & 0x0F;
+      match reg {
+         PRA                => self.port_a_output() & self.port_a_in,
+         PRB                => self.port_b_output() & self.port_b_in,
+         DDRA               => self.ddra,
+         DDRB               => self.ddrb,
+         TA_LO              => self.counter_a as u8,
+         TA_HI              => (self.counter_a >> 8) as u8,
+         TB_LO              => self.counter_b as u8,
+         TB_HI              => (self.counter_b >> 8) as u8,
+         TOD_10THS..=TOD_HR => self.read_tod(reg),
+         SDR                => self.sdr,
+         ICR                => {
+            // reading acknowledges all interrupts
+            let value = self.icr | if self.irq { INT_SET } else { 0 };
+            self.icr = 0;
+            self.irq = false;
+            self.delay &= !(INTERRUPT0 | INTERRUPT1);
+            self.icr_read = true;
+            value
+         },
+         CRA                => self.cra,
+         // CRB
+         _                  => self.crb
+      }
+   }
+
+   pub fn write(&mut self, reg: u8, value: u8) {
+      let reg = reg & 0x0F;
+      match reg {
+         PRA                => self.pra = value,
+         PRB                => self.prb = value,
+         DDRA               => self.ddra = value,
+         DDRB               => self.ddrb = value,
+         TA_LO              => self.latch_a = (self.latch_a & 0xFF00) | value as u16,
+         TA_HI              => {
+            self.latch_a = (self.latch_a & 0x00FF) | ((value as u16) << 8);
+            // a stopped timer is loaded along with the high byte of its latch
+            if self.cra & START == 0 {
+               self.delay |= LOAD_A0;
+            }
+         },
+         TB_LO              => self.latch_b = (self.latch_b & 0xFF00) | value as u16,
+         TB_HI              => {
+            self.latch_b = (self.latch_b & 0x00FF) | ((value as u16) << 8);
+            if self.crb & START == 0 {
+               self.delay |= LOAD_B0;
+            }
+         },
+         TOD_10THS..=TOD_HR => self.write_tod(reg, value),
+         SDR                => {
+            self.sdr = value;
+            if self.cra & SP_OUTPUT != 0 {
+               self.sdr_loaded = true;
+            }
+         },
+         ICR                => {
+            if value & INT_SET != 0 {
+               self.imr |= value & 0x1F;
+            } else {
+               self.imr &= !value;
+            }
+            if self.icr & self.imr != 0 && !self.irq {
+               self.delay |= INTERRUPT0;
+            }
+         },
+         CRA                => {
+            if value & START != 0 && self.cra & START == 0 {
+               // starting the timer sets the PB6 toggle
+               self.pb_toggle |= 0x40;
+            }
+            if value & FORCE_LOAD != 0 {
+               self.delay |= LOAD_A0;
+            }
+            if (value ^ self.cra) & SP_OUTPUT != 0 {
+               // switching direction abandons a byte in progress and releases the lines
+               self.shift_bits = 0;
+               self.sdr_loaded = false;
+               self.cnt_out = true;
+               self.sp_out = true;
+               self.update_cnt();
+            }
+            self.set_feed(COUNT_A0, value & (START | INMODE_A) == START);
+            self.set_feed(ONE_SHOT_A0, value & ONE_SHOT != 0);
+            self.cra = value & !FORCE_LOAD;
+         },
+         // CRB
+         _                  => {
+            if value & START != 0 && self.crb & START == 0 {
+               self.pb_toggle |= 0x80;
+            }
+            if value & FORCE_LOAD != 0 {
+               self.delay |= LOAD_B0;
+            }
+            self.set_feed(COUNT_B0, value & (START | INMODE_B) == START);
+            self.set_feed(ONE_SHOT_B0, value & ONE_SHOT != 0);
+            self.crb = value & !FORCE_LOAD;
+         }
+      }
+   }
+
+   fn read_tod(&mut self, reg: u8) -> u8 {
+      if reg == TOD_HR && self.tod_latch.is_none() {
+         self.tod_latch = Some(self.tod);
+      }
+      let time = self.tod_latch.unwrap_or(self.tod);
+      if reg == TOD_10THS {
+         self.tod_latch = None;
+      }
+      time[(reg - TOD_10THS) as usize]
+   }
+
+   fn write_tod(&mut self, reg: u8, value: u8) {
+      let value = match reg {
+         TOD_10THS => value & 0x0F,
+         TOD_SEC   => value & 0x7F,
+         TOD_MIN   => value & 0x7F,
+         _         => value & 0x9F
+      };
+      if self.crb & TOD_ALARM != 0 {
+         self.tod_alarm[(reg - TOD_10THS) as usize] = value;
+      } else {
+         self.tod[(reg - TOD_10THS) as usize] = value;
+         match reg {
+            TOD_HR    => self.tod_stopped = true,
+            TOD_10THS => {
+               self.tod_stopped = false;
+               self.tod_divider = 0;
+            },
+            _         => {}
+         }
+      }
+      if self.tod == self.tod_alarm {
+         self.set_interrupt(INT_ALARM);
+      }
+   }
+
+   // one cycle of the power line frequency on the TOD pin
+   pub fn tod_tick(&mut self) {
+      if self.tod_stopped {
+         return;
+      }
+      // the input is divided by 5 or 6 to get tenths, so the wrong setting runs fast or slow
+      self.tod_divider += 1;
+      let divider = if self.cra & TOD_50HZ != 0 { 5 } else { 6 };
+      if self.tod_divider < divider {
+         return;
+      }
+      self.tod_divider = 0;
+
+      let [tenths, seconds, minutes, hours] = &mut self.tod;
+      *tenths = (*tenths + 1) & 0x0F;
+      if *tenths == 10 {
+         *tenths = 0;
+         *seconds = bcd_increment(*seconds);
+         if *seconds == 0x60 {
+            *seconds = 0;
+            *minutes = bcd_increment(*minutes);
+            if *minutes == 0x60 {
+               *minutes = 0;
+               let pm = *hours & PM;
+               let hour = bcd_increment(*hours & 0x1F);
+               *hours = match hour {
+                  0x12 => hour | (pm ^ PM),
+                  0x13 => 0x01 | pm,
+                  _    => hour | pm
+               };
+            }
+         }
+      }
+
+      if self.tod == self.tod_alarm {
+         self.set_interrupt(INT_ALARM);
+      }
+   }
+
+   fn set_feed(&mut self, bit: u32, on: bool) {
+      if on {
+         self.feed |= bit;
+      } else {
+         self.feed &= !bit;
+      }
+   }
+
+   // the levels the CIA itself drives onto CNT and SP, high where it leaves them alone
+   pub fn cnt_output(&self) -> bool {
+      self.cnt_out
+   }
+
+   pub fn sp_output(&self) -> bool {
+      self.sp_out
+   }
+
+   fn sp(&self) -> bool {
+      self.sp_out && self.sp_in
+   }
+
+   // false while a device pulls CNT low
+   pub fn set_cnt_input(&mut self, level: bool) {
+      self.cnt_in = level;
+      self.update_cnt();
+   }
+
+   pub fn set_sp_input(&mut self, level: bool) {
+      self.sp_in = level;
+   }
+
+   // rising edges on CNT clock the timers in CNT mode and shift SP in
+   fn update_cnt(&mut self) {
+      let level = self.cnt_out && self.cnt_in;
+      if level && !self.cnt {
+         if self.cra & (START | INMODE_A) == START | COUNT_CNT {
+            self.delay |= COUNT_A1;
+         }
+         if self.crb & (START | INMODE_B) == START | COUNT_CNT {
+            self.delay |= COUNT_B1;
+         }
+         if self.cra & SP_OUTPUT == 0 {
+            self.shift = (self.shift << 1) | self.sp() as u8;
+            self.shift_bits += 1;
+            if self.shift_bits == 8 {
+               self.shift_bits = 0;
+               self.sdr = self.shift;
+               self.set_interrupt(INT_SP);
+            }
+         }
+      }
+      self.cnt = level;
+   }
+
+   // in output mode every timer A underflow is half a bit: SP changes while CNT is low
+   // and the receiver takes it on the rising edge
+   fn shift_out(&mut self) {
+      if self.shift_bits == 0 {
+         if !self.sdr_loaded {
+            return;
+         }
+         self.shift = self.sdr;
+         self.shift_bits = 8;
+         self.sdr_loaded = false;
+      }
+
+      self.cnt_out = !self.cnt_out;
+      if !self.cnt_out {
+         self.sp_out = self.shift & 0x80 != 0;
+      } else {
+         self.shift <<= 1;
+         self.shift_bits -= 1;
+         if self.shift_bits == 0 {
+            self.set_interrupt(INT_SP);
+         }
+      }
+      self.update_cnt();
+   }
+
+   // one φ2 cycle
+   pub fn clock(&mut self) {
+      let late = std::mem::take(&mut self.late_interrupts);
+      if late != 0 {
+         // the old 6526 drops a timer B interrupt that comes in the cycle ICR is read
+         let late = if self.icr_read { late & !INT_TB } else { late };
+         self.set_interrupt(late);
+      }
+
+      if self.delay & COUNT_A3 != 0 {
+         self.counter_a = self.counter_a.wrapping_sub(1);
+      }
+      let underflow_a = self.counter_a == 0 && self.delay & COUNT_A2 != 0;
+      if underflow_a {
+         if (self.delay | self.feed) & ONE_SHOT_A0 != 0 {
+            self.cra &= !START;
+            self.delay &= !(COUNT_A2 | COUNT_A1 | COUNT_A0);
+            self.feed &= !COUNT_A0;
+         }
+         let inmode = self.crb & (START | INMODE_B);
+         if inmode == START | COUNT_TA || (inmode == START | COUNT_TA_CNT && self.cnt) {
+            self.delay |= COUNT_B1;
+         }
+         self.delay |= LOAD_A1;
+      }
+      if self.delay & LOAD_A1 != 0 {
+         self.counter_a = self.latch_a;
+         self.delay &= !COUNT_A2;
+      }
+
+      if self.delay & COUNT_B3 != 0 {
+         self.counter_b = self.counter_b.wrapping_sub(1);
+      }
+      let underflow_b = self.counter_b == 0 && self.delay & COUNT_B2 != 0;
+      if underflow_b {
+         if (self.delay | self.feed) & ONE_SHOT_B0 != 0 {
+            self.crb &= !START;
+            self.delay &= !(COUNT_B2 | COUNT_B1 | COUNT_B0);
+            self.feed &= !COUNT_B0;
+         }
+         self.delay |= LOAD_B1;
+      }
+      if self.delay & LOAD_B1 != 0 {
+         self.counter_b = self.latch_b;
+         self.delay &= !COUNT_B2;
+      }
+
+      // PB6/PB7 either toggle or pulse high for a cycle on each underflow
+      self.pb_pulse = 0;
+      if underflow_a {
+         self.pb_toggle ^= 0x40;
+         self.pb_pulse |= 0x40;
+         self.timer_interrupt(INT_TA);
+         if self.cra & SP_OUTPUT != 0 {
+            self.shift_out();
+         }
+      }
+      if underflow_b {
+         self.pb_toggle ^= 0x80;
+         self.pb_pulse |= 0x80;
+         self.timer_interrupt(INT_TB);
+      }
+      if self.delay & INTERRUPT1 != 0 {
+         self.irq = true;
+      }
+      self.icr_read = false;
+
+      self.delay = ((self.delay << 1) & DELAY_MASK) | self.feed;
+   }
+
+   fn timer_interrupt(&mut self, source: u8) {
+      match self.model {
+         CiaModel::Mos6526  => self.late_interrupts |= source,
+         CiaModel::Mos6526A => self.set_interrupt(source),
+      }
+   }
+
+   // flags an interrupt source; the IRQ output follows a cycle later if it is enabled
+   fn set_interrupt(&mut self, source: u8) {
+      self.icr |= source;
+      if self.imr & source != 0 && !self.irq {
+         self.delay |= INTERRUPT0;
+      }
+   }
+}
+
+fn bcd_increment(value: u8) -> u8 {
+   if value & 0x0F == 9 {
+      (value & 0xF0) + 0x10
+   } else {
+      value + 1
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const CRB : u8 = 0x0F;
+
+   fn clock(cia: &mut Cia, cycles: usize) {
+      for _ in 0..cycles {
+         cia.clock();
+      }
+   }
+
+   // writes a register as the CPU does, in a cycle of its own
+   fn write(cia: &mut Cia, reg: u8, value: u8) {
+      cia.write(reg, value);
+      cia.clock();
+   }
+
+   fn timer_a(cia: &mut Cia) -> u16 {
+      cia.read(TA_LO) as u16 | (cia.read(TA_HI) as u16) << 8
+   }
+
+   fn timer_b(cia: &mut Cia) -> u16 {
+      cia.read(TB_LO) as u16 | (cia.read(TB_HI) as u16) << 8
+   }
+
+   // timer A as read in each of the next cycles
+   fn trace_a(cia: &mut Cia, cycles: usize) -> Vec<u16> {
+      (0..cycles).map(|_| {
+         let value = timer_a(cia);
+         cia.clock();
+         value
+      }).collect()
+   }
+
+   // a stopped timer A loaded with the latch
+   fn timer(model: CiaModel, latch: u16) -> Cia {
+      let mut cia = Cia::new(model);
+      write(&mut cia, TA_LO, latch as u8);
+      write(&mut cia, TA_HI, (latch >> 8) as u8);
+      cia
+   }
+
+   #[test]
+   fn stopped_timer_loads_with_the_latch_high_byte() {
+      let mut cia = timer(CiaModel::Mos6526A, 0x1234);
+      clock(&mut cia, 1);
+      assert_eq!(timer_a(&mut cia), 0x1234);
+      // the low byte only goes to the latch
+      write(&mut cia, TA_LO, 0x00);
+      clock(&mut cia, 1);
+      assert_eq!(timer_a(&mut cia), 0x1234);
+   }
+
+   #[test]
+   fn started_timer_counts_after_the_pipeline_delay() {
+      let mut cia = timer(CiaModel::Mos6526A, 3);
+      write(&mut cia, CRA, START);
+      assert_eq!(trace_a(&mut cia, 6), vec![3, 3, 3, 3, 2, 1]);
+   }
+
+   #[test]
+   fn continuous_timer_reloads_and_keeps_counting() {
+      let mut cia = timer(CiaModel::Mos6526A, 3);
+      write(&mut cia, CRA, START);
+      clock(&mut cia, 4);
+      // the reload shows as the latch for two cycles, a period of latch + 1
+      assert_eq!(trace_a(&mut cia, 10), vec![2, 1, 3, 3, 2, 1, 3, 3, 2, 1]);
+      assert_eq!(cia.read(CRA) & START, START);
+   }
+
+   #[test]
+   fn one_shot_timer_reloads_and_stops() {
+      let mut cia = timer(CiaModel::Mos6526A, 3);
+      write(&mut cia, CRA, START | ONE_SHOT);
+      clock(&mut cia, 4);
+      assert_eq!(trace_a(&mut cia, 8), vec![2, 1, 3, 3, 3, 3, 3, 3]);
+      assert_eq!(cia.read(CRA) & START, 0);
+      assert_eq!(cia.read(ICR), INT_TA);
+   }
+
+   #[test]
+   fn force_load_reloads_a_running_timer() {
+      let mut cia = timer(CiaModel::Mos6526A, 0x100);
+      write(&mut cia, CRA, START);
+      clock(&mut cia, 8);
+      // a new latch value waits for the next reload
+      write(&mut cia, TA_LO, 0x10);
+      write(&mut cia, TA_HI, 0x00);
+      assert!(timer_a(&mut cia) > 0x10);
+      // loaded a cycle after the write, and the count skips a cycle
+      write(&mut cia, CRA, START | FORCE_LOAD);
+      clock(&mut cia, 1);
+      assert_eq!(trace_a(&mut cia, 4), vec![0x10, 0x10, 0x0F, 0x0E]);
+      // the strobe bit does not stick
+      assert_eq!(cia.read(CRA), START);
+   }
+
+   #[test]
+   fn timer_b_counts_timer_a_underflows() {
+      let mut cia = timer(CiaModel::Mos6526A, 3);
+      write(&mut cia, TB_LO, 2);
+      write(&mut cia, TB_HI, 0);
+      write(&mut cia, CRB, START | COUNT_TA);
+      write(&mut cia, CRA, START);
+      // timer A underflows every 4 cycles, timer B counts two cycles after each
+      let trace: Vec<u16> = (0..16).map(|_| {
+         let value = timer_b(&mut cia);
+         cia.clock();
+         value
+      }).collect();
+      assert_eq!(trace, vec![2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 0, 0, 0, 2]);
+      assert_eq!(cia.read(ICR), INT_TA | INT_TB);
+   }
+
+   // hours, minutes, seconds and tenths, written in the order that stops and restarts the clock
+   fn set_tod(cia: &mut Cia, time: [u8; 4]) {
+      for (reg, value) in [TOD_HR, TOD_MIN, TOD_SEC, TOD_10THS].into_iter().zip(time) {
+         cia.write(reg, value);
+      }
+   }
+
+   fn read_tod(cia: &mut Cia) -> [u8; 4] {
+      [TOD_HR, TOD_MIN, TOD_SEC, TOD_10THS].map(|reg| cia.read(reg))
+   }
+
+   // tenths of a second of 60 Hz input
+   fn run_tod(cia: &mut Cia, tenths: usize) {
+      for _ in 0..tenths * 6 {
+         cia.tod_tick();
+      }
+   }
+
+   #[test]
+   fn tod_counts_in_bcd() {
+      let mut cia = Cia::new(CiaModel::Mos6526A);
+      set_tod(&mut cia, [0x01, 0x59, 0x59, 0x09]);
+      run_tod(&mut cia, 1);
+      assert_eq!(read_tod(&mut cia), [0x02, 0x00, 0x00, 0x00]);
+      run_tod(&mut cia, 105);
+      assert_eq!(read_tod(&mut cia), [0x02, 0x00, 0x10, 0x05]);
+   }
+
+   #[test]
+   fn tod_50hz_input_divides_by_five() {
+      let mut cia = Cia::new(CiaModel::Mos6526A);
+      write(&mut cia, CRA, TOD_50HZ);
+      set_tod(&mut cia, [0x01, 0x00, 0x00, 0x00]);
+      for _ in 0..50 {
+         cia.tod_tick();
+      }
+      assert_eq!(read_tod(&mut cia), [0x01, 0x00, 0x01, 0x00]);
+   }
+
+   #[test]
+   fn tod_hours_flip_am_pm_at_twelve() {
+      let mut cia = Cia::new(CiaModel::Mos6526A);
+      set_tod(&mut cia, [PM | 0x11, 0x59, 0x59, 0x09]);
+      run_tod(&mut cia, 1);
+      assert_eq!(read_tod(&mut cia), [0x12, 0x00, 0x00, 0x00]);
+      set_tod(&mut cia, [0x11, 0x59, 0x59, 0x09]);
+      run_tod(&mut cia, 1);
+      assert_eq!(read_tod(&mut cia), [PM | 0x12, 0x00, 0x00, 0x00]);
+      // 12 is followed by 1 of the same half of the day
+      set_tod(&mut cia, [PM | 0x12, 0x59, 0x59, 0x09]);
+      run_tod(&mut cia, 1);
+      assert_eq!(read_tod(&mut cia), [PM | 0x01, 0x00, 0x00, 0x00]);
+   }
+
+   #[test]
+   fn reading_hours_latches_until_tenths_are_read() {
+      let mut cia = Cia::new(CiaModel::Mos6526A);
+      set_tod(&mut cia, [0x01, 0x00, 0x00, 0x09]);
+      assert_eq!(cia.read(TOD_HR), 0x01);
+      run_tod(&mut cia, 1);
+      assert_eq!(cia.read(TOD_SEC), 0x00);
+      assert_eq!(cia.read(TOD_10THS), 0x09);
+      // the tenths read released the latch
+      assert_eq!(cia.read(TOD_SEC), 0x01);
+      assert_eq!(cia.read(TOD_10THS), 0x00);
+   }
+
+   #[test]
+   fn writing_hours_stops_the_clock_until_tenths_are_written() {
+      let mut cia = Cia::new(CiaModel::Mos6526A);
+      set_tod(&mut cia, [0x01, 0x00, 0x00, 0x00]);
+      cia.write(TOD_HR, 0x02);
+      run_tod(&mut cia, 10);
+      assert_eq!(read_tod(&mut cia), [0x02, 0x00, 0x00, 0x00]);
+      cia.write(TOD_10THS, 0x05);
+      run_tod(&mut cia, 10);
+      assert_eq!(read_tod(&mut cia), [0x02, 0x00, 0x01, 0x05]);
+   }
+
+   #[test]
+   fn tod_alarm_raises_an_interrupt() {
+      let mut cia = Cia::new(CiaModel::Mos6526A);
+      write(&mut cia, CRB, TOD_ALARM);
+      set_tod(&mut cia, [0x01, 0x00, 0x01, 0x00]);
+      write(&mut cia, CRB, 0);
+      // the alarm matched the time while its hours were set
+      cia.read(ICR);
+      write(&mut cia, ICR, INT_SET | INT_ALARM);
+      set_tod(&mut cia, [0x01, 0x00, 0x00, 0x00]);
+      // setting the alarm did not change the time
+      assert_eq!(read_tod(&mut cia), [0x01, 0x00, 0x00, 0x00]);
+      run_tod(&mut cia, 9);
+      clock(&mut cia, 2);
+      assert!(!cia.irq());
+      run_tod(&mut cia, 1);
+      clock(&mut cia, 2);
+      assert!(cia.irq());
+      assert_eq!(cia.read(ICR), INT_SET | INT_ALARM);
+      assert!(!cia.irq());
+   }
+
+   #[test]
+   fn sdr_output_shifts_eight_bits_on_cnt_then_interrupts() {
+      let mut cia = timer(CiaModel::Mos6526A, 2);
+      write(&mut cia, CRA, START | SP_OUTPUT);
+      write(&mut cia, SDR, 0xA5);
+      // the receiver takes SP on each rising edge of CNT
+      let (mut byte, mut pulses, mut cnt) = (0u8, 0, cia.cnt_output());
+      for _ in 0..100 {
+         cia.clock();
+         if cia.cnt_output() && !cnt {
+            byte = (byte << 1) | cia.sp_output() as u8;
+            pulses += 1;
+            if pulses < 8 {
+               assert_eq!(cia.read(ICR) & INT_SP, 0);
+            }
+         }
+         cnt = cia.cnt_output();
+      }
+      assert_eq!((pulses, byte), (8, 0xA5));
+      assert_eq!(cia.read(ICR) & INT_SP, INT_SP);
+      assert!(cia.cnt_output());
+   }
+
+   #[test]
+   fn sdr_input_shifts_sp_in_on_rising_cnt() {
+      let mut cia = Cia::new(CiaModel::Mos6526A);
+      write(&mut cia, ICR, INT_SET | INT_SP);
+      for bit in (0..8).rev() {
+         assert!(!cia.irq());
+         cia.set_sp_input(0x3C & (1 << bit) != 0);
+         cia.set_cnt_input(false);
+         clock(&mut cia, 2);
+         cia.set_cnt_input(true);
+         clock(&mut cia, 2);
+      }
+      assert!(cia.irq());
+      assert_eq!(cia.read(ICR), INT_SET | INT_SP);
+      assert_eq!(cia.read(SDR), 0x3C);
+   }
+
+   // the IRQ output in each cycle of a timer running from 3 with its interrupt enabled
+   fn irq_trace(model: CiaModel) -> Vec<bool> {
+      let mut cia = timer(model, 3);
+      write(&mut cia, ICR, INT_SET | INT_TA);
+      write(&mut cia, CRA, START);
+      (0..8).map(|_| {
+         cia.clock();
+         cia.irq()
+      }).collect()
+   }
+
+   #[test]
+   fn old_cia_asserts_irq_a_cycle_later() {
+      let new = irq_trace(CiaModel::Mos6526A);
+      let old = irq_trace(CiaModel::Mos6526);
+      assert_eq!(new, vec![false, false, false, false, false, false, true, true]);
+      assert_eq!(old, vec![false, false, false, false, false, false, false, true]);
+   }
+
+   // timer B started from 3, stopped right after the cycle it underflows in
+   fn timer_b_expiring(model: CiaModel) -> Cia {
+      let mut cia = Cia::new(model);
+      write(&mut cia, TB_LO, 3);
+      write(&mut cia, TB_HI, 0);
+      write(&mut cia, ICR, INT_SET | INT_TB);
+      write(&mut cia, CRB, START | ONE_SHOT);
+      clock(&mut cia, 6);
+      cia
+   }
+
+   #[test]
+   fn old_cia_drops_timer_b_interrupt_when_icr_is_read_as_it_arrives() {
+      let mut cia = timer_b_expiring(CiaModel::Mos6526);
+      assert_eq!(cia.read(ICR), 0);
+      clock(&mut cia, 4);
+      assert!(!cia.irq());
+      assert_eq!(cia.read(ICR), 0);
+   }
+
+   #[test]
+   fn old_cia_keeps_timer_b_interrupt_when_icr_is_read_later() {
+      let mut cia = timer_b_expiring(CiaModel::Mos6526);
+      clock(&mut cia, 2);
+      assert!(cia.irq());
+      assert_eq!(cia.read(ICR), INT_SET | INT_TB);
+   }
+
+   #[test]
+   fn new_cia_flags_timer_b_before_the_read() {
+      let mut cia = timer_b_expiring(CiaModel::Mos6526A);
+      assert_eq!(cia.read(ICR), INT_TB);
+      clock(&mut cia, 4);
+      assert!(!cia.irq());
+   }
+}
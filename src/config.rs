@@ -0,0 +1,270 @@
+use crate::controlport::*;
+use crate::crt::*;
+use crate::gfxview::*;
+use crate::keyboard::*;
+use crate::model::*;
+use crate::palette::*;
+use crate::sid::*;
+use crate::userport::*;
+
+pub struct Config {
+   pub model: VicModel,
+   pub sid_model: SidModel,
+   pub cia_models: [CiaModel; 2],
+   pub sid_pan: SidPan,
+   pub extra_sids: Vec<(u16, SidPan)>,
+   pub sound: bool,
+   pub sample_rate: u32,
+   pub record: Option<String>,
+   pub record_seconds: Option<f64>,
+   pub sid_file: Option<String>,
+   pub sid_log: Option<String>,
+   pub sid_dump: Option<String>,
+   pub song: Option<u16>,
+   pub palette: Palette,
+   pub key_mapping: KeyMapping,
+   // SDL key names for up, down, left, right and fire of control port 1 and 2
+   pub joy_keys: [Vec<String>; 2],
+   pub autofire: Option<f32>,
+   pub control_devices: [ControlDevice; 2],
+   pub user_port: UserPortDevice,
+   pub headless: bool,
+   pub frames: u32,
+   pub screenshot: Option<String>,
+   pub crop_border: bool,
+   pub golden: Option<String>,
+   pub update_goldens: bool,
+   pub crt: CrtFilter,
+   pub breakpoints: Vec<u16>,
+   pub rip: Vec<(GfxView, String)>,
+}
+
+impl Config {
+   pub fn new() -> Self {
+      Self {
+         model: VicModel::Mos6569,
+         sid_model: SidModel::Mos6581,
+         cia_models: [CiaModel::Mos6526; 2],
+         sid_pan: SidPan::Center,
+         extra_sids: Vec::new(),
+         sound: true,
+         sample_rate: 48000,
+         record: None,
+         record_seconds: None,
+         sid_file: None,
+         sid_log: None,
+         sid_dump: None,
+         song: None,
+         palette: Palette::new(PaletteKind::Pepto),
+         key_mapping: KeyMapping::Symbolic,
+         joy_keys: [
+            Vec::new(),
+            ["Keypad 8", "Keypad 2", "Keypad 4", "Keypad 6", "Keypad 0"].iter().map(|key| key.to_string()).collect()
+         ],
+         autofire: None,
+         control_devices: [ControlDevice::Joystick; 2],
+         user_port: UserPortDevice::None,
+         headless: false,
+         frames: 1,
+         screenshot: None,
+         crop_border: false,
+         golden: None,
+         update_goldens: false,
+         crt: CrtFilter::new(),
+         breakpoints: Vec::new(),
+         rip: Vec::new(),
+      }
+   }
+
+   pub fn from_args(args: &[String]) -> Result<Self, String> {
+      let mut config = Config::new();
+      let mut iter = args.iter();
+
+      while let Some(arg) = iter.next() {
+         match arg.as_str() {
+            "--pal"            => config.model = VicModel::Mos6569,
+            "--ntsc"           => config.model = VicModel::Mos6567R8,
+            "--model"          => {
+               let name = iter.next().ok_or("--model needs a value")?;
+               config.model = VicModel::from_name(name)?;
+            },
+            "--cia-model"      => {
+               let name = iter.next().ok_or("--cia-model needs a value")?;
+               config.cia_models = [CiaModel::from_name(name)?; 2];
+            },
+            "--cia1-model"     => {
+               let name = iter.next().ok_or("--cia1-model needs a value")?;
+               config.cia_models[0] = CiaModel::from_name(name)?;
+            },
+            "--cia2-model"     => {
+               let name = iter.next().ok_or("--cia2-model needs a value")?;
+               config.cia_models[1] = CiaModel::from_name(name)?;
+            },
+            "--sid-model"      => {
+               let name = iter.next().ok_or("--sid-model needs a value")?;
+               config.sid_model = SidModel::from_name(name)?;
+            },
+            "--sid-pan"        => {
+               let name = iter.next().ok_or("--sid-pan needs a value")?;
+               config.sid_pan = SidPan::from_name(name)?;
+            },
+            "--extra-sid"      => {
+               let value = iter.next().ok_or("--extra-sid needs an address")?;
+               let (address, pan) = parse_extra_sid(value)?;
+               if config.extra_sids.iter().any(|sid| sid.0 == address) {
+                  return Err(format!("Two SIDs At {:#06X}", address));
+               }
+               if config.extra_sids.len() == EXTRA_SIDS {
+                  return Err(format!("At Most {} Extra SIDs", EXTRA_SIDS));
+               }
+               config.extra_sids.push((address, pan));
+            },
+            "--no-sound"       => config.sound = false,
+            "--sample-rate"    => {
+               let value = iter.next().ok_or("--sample-rate needs a value")?;
+               config.sample_rate = match value.parse() {
+                  Ok(rate) if (8000..=192000).contains(&rate) => rate,
+                  _                                            => return Err(format!("Bad Sample Rate: {}", value))
+               };
+            },
+            "--record"         => {
+               let file_name = iter.next().ok_or("--record needs a file name")?;
+               config.record = Some(file_name.clone());
+            },
+            "--record-seconds" => {
+               let value = iter.next().ok_or("--record-seconds needs a value")?;
+               config.record_seconds = match value.parse::<f64>() {
+                  Ok(seconds) if seconds > 0.0 => Some(seconds),
+                  _                            => return Err(format!("Bad Recording Length: {}", value))
+               };
+            },
+            "--sid"            => {
+               let file_name = iter.next().ok_or("--sid needs a file name")?;
+               config.sid_file = Some(file_name.clone());
+            },
+            "--song"           => {
+               let value = iter.next().ok_or("--song needs a number")?;
+               config.song = Some(value.parse().map_err(|_| format!("Bad Song Number: {}", value))?);
+            },
+            "--sid-log"        => {
+               let file_name = iter.next().ok_or("--sid-log needs a file name")?;
+               config.sid_log = Some(file_name.clone());
+            },
+            "--sid-dump"       => {
+               let file_name = iter.next().ok_or("--sid-dump needs a file name")?;
+               config.sid_dump = Some(file_name.clone());
+            },
+            "--palette"        => {
+               let name = iter.next().ok_or("--palette needs a value")?;
+               config.palette = Palette::from_name(name)?;
+            },
+            "--keymap"         => {
+               let name = iter.next().ok_or("--keymap needs a value")?;
+               config.key_mapping = KeyMapping::from_name(name)?;
+            },
+            "--joy-keys"       => {
+               let value = iter.next().ok_or("--joy-keys needs a value")?;
+               let (port, keys) = parse_joy_keys(value)?;
+               config.joy_keys[port] = keys;
+            },
+            "--port1"          => {
+               let name = iter.next().ok_or("--port1 needs a device")?;
+               config.control_devices[0] = ControlDevice::from_name(name)?;
+            },
+            "--port2"          => {
+               let name = iter.next().ok_or("--port2 needs a device")?;
+               config.control_devices[1] = ControlDevice::from_name(name)?;
+            },
+            "--user-port"      => {
+               let name = iter.next().ok_or("--user-port needs a device")?;
+               config.user_port = UserPortDevice::from_name(name)?;
+            },
+            "--autofire"       => {
+               let value = iter.next().ok_or("--autofire needs a value")?;
+               config.autofire = match value.parse::<f32>() {
+                  Ok(rate) if rate > 0.0 && rate <= 25.0 => Some(rate),
+                  _                                      => return Err(format!("Bad Autofire Rate: {}", value))
+               };
+            },
+            "--headless"       => config.headless = true,
+            "--frames"         => {
+               let value = iter.next().ok_or("--frames needs a value")?;
+               config.frames = value.parse().map_err(|_| format!("Bad Frame Count: {}", value))?;
+            },
+            "--screenshot"     => {
+               let file_name = iter.next().ok_or("--screenshot needs a file name")?;
+               config.screenshot = Some(file_name.clone());
+            },
+            "--crop-border"    => config.crop_border = true,
+            "--golden"         => {
+               let manifest = iter.next().ok_or("--golden needs a manifest file")?;
+               config.golden = Some(manifest.clone());
+            },
+            "--update-goldens" => config.update_goldens = true,
+            "--crt"            => config.crt.enabled = true,
+            "--scanlines"      => {
+               let value = iter.next().ok_or("--scanlines needs a value")?;
+               config.crt.scanlines = parse_amount(value)?;
+            },
+            "--blur"           => {
+               let value = iter.next().ok_or("--blur needs a value")?;
+               config.crt.blur = parse_amount(value)?;
+            },
+            "--no-pal-blend"   => config.crt.pal_blend = false,
+            "--no-aspect"      => config.crt.aspect = false,
+            "--break"          => {
+               let value = iter.next().ok_or("--break needs an address")?;
+               let address = u16::from_str_radix(value.trim_start_matches('$'), 16)
+                  .map_err(|_| format!("Bad Breakpoint Address: {}", value))?;
+               config.breakpoints.push(address);
+            },
+            "--rip"            => {
+               let spec = iter.next().ok_or("--rip needs a view and a file name")?;
+               let file_name = iter.next().ok_or("--rip needs a view and a file name")?;
+               config.rip.push((GfxView::from_spec(spec)?, file_name.clone()));
+            },
+            _                  => return Err(format!("Unknown Option: {}", arg))
+         }
+      }
+
+      Ok(config)
+   }
+}
+
+// <hex address>[:<left|center|right>]
+fn parse_extra_sid(value: &str) -> Result<(u16, SidPan), String> {
+   let (address, pan) = match value.split_once(':') {
+      Some((address, pan)) => (address, SidPan::from_name(pan)?),
+      None                 => (value, SidPan::Center)
+   };
+   let address = match u16::from_str_radix(address.trim_start_matches('$'), 16) {
+      Ok(address) if extra_sid_address(address) => address,
+      _                                         => return Err(format!("Bad SID Address: {}", value))
+   };
+   Ok((address, pan))
+}
+
+// "<port>:<up>,<down>,<left>,<right>,<fire>" or "<port>:none"
+fn parse_joy_keys(value: &str) -> Result<(usize, Vec<String>), String> {
+   let (port, keys) = value.split_once(':').ok_or(format!("Bad Joystick Keys: {}", value))?;
+   let port = match port {
+      "1" => 0,
+      "2" => 1,
+      _   => return Err(format!("Bad Joystick Port: {}", port))
+   };
+   if keys.eq_ignore_ascii_case("none") {
+      return Ok((port, Vec::new()));
+   }
+   let keys: Vec<String> = keys.split(',').map(|key| key.trim().to_string()).collect();
+   if keys.len() != 5 {
+      return Err(format!("Joystick Keys Need Up, Down, Left, Right And Fire: {}", value));
+   }
+   Ok((port, keys))
+}
+
+fn parse_amount(value: &str) -> Result<f32, String> {
+   match value.parse::<f32>() {
+      Ok(amount) if (0.0..=1.0).contains(&amount) => Ok(amount),
+      _                                           => Err(format!("Expected A Value From 0 To 1: {}", value))
+   }
+}
@@ -0,0 +1,146 @@
+use crate::joystick::*;
+
+// what is plugged into a control port besides a joystick
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ControlDevice {
+   Joystick,
+   // a pair of paddles on POTX and POTY
+   Paddles,
+   // the 1351 mouse in proportional mode
+   Mouse,
+}
+
+impl ControlDevice {
+   pub fn from_name(name: &str) -> Result<ControlDevice, String> {
+      match name.to_lowercase().as_str() {
+         "joystick"        => Ok(ControlDevice::Joystick),
+         "paddles"         => Ok(ControlDevice::Paddles),
+         "mouse" | "1351"  => Ok(ControlDevice::Mouse),
+         _                 => Err(format!("Unknown Control Port Device: {}", name))
+      }
+   }
+}
+
+pub struct ControlPort {
+   device: ControlDevice,
+   // paddle positions, the pot counts they give
+   paddles: [u8; 2],
+   // mouse position in 1351 steps, y grows upwards
+   mouse: (u16, u16),
+   // buttons on the joystick lines
+   buttons: u8,
+}
+
+impl ControlPort {
+   pub fn new(device: ControlDevice) -> Self {
+      Self {
+         device: device,
+         paddles: [0x80; 2],
+         mouse: (0, 0),
+         buttons: 0,
+      }
+   }
+
+   pub fn set_paddle(&mut self, paddle: usize, value: u8) {
+      self.paddles[paddle] = value;
+   }
+
+   pub fn move_paddle(&mut self, paddle: usize, delta: i32) {
+      self.paddles[paddle] = (self.paddles[paddle] as i32 + delta).clamp(0, 0xFF) as u8;
+   }
+
+   pub fn move_mouse(&mut self, dx: i32, dy: i32) {
+      self.mouse.0 = self.mouse.0.wrapping_add(dx as u16);
+      self.mouse.1 = self.mouse.1.wrapping_add(dy as u16);
+   }
+
+   // paddle fire buttons are on the left and right lines, the 1351 has its left button on
+   // fire and its right button on up
+   pub fn set_button(&mut self, button: usize, pressed: bool) {
+      let line = match (self.device, button) {
+         (ControlDevice::Paddles, 0) => JOY_LEFT,
+         (ControlDevice::Paddles, _) => JOY_RIGHT,
+         (ControlDevice::Mouse, 0)   => JOY_FIRE,
+         (ControlDevice::Mouse, _)   => JOY_UP,
+         _                           => return
+      };
+      if pressed { self.buttons |= line; } else { self.buttons &= !line; }
+   }
+
+   pub fn buttons(&self) -> u8 {
+      self.buttons
+   }
+
+   // the POTX/POTY counts. The 1351 times its output so that bits 1-6 hold the position
+   // modulo 64; bit 0 is noise on a real mouse that drivers mask off, here it stays clear.
+   // A joystick leaves the inputs open
+   pub fn pots(&self) -> [u8; 2] {
+      match self.device {
+         ControlDevice::Joystick => [0xFF; 2],
+         ControlDevice::Paddles  => self.paddles,
+         ControlDevice::Mouse    => [((self.mouse.0 & 0x3F) << 1) as u8, ((self.mouse.1 & 0x3F) << 1) as u8],
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // the signed motion a 1351 driver gets from two readings of a pot
+   fn delta(old: u8, new: u8) -> i8 {
+      let steps = ((new >> 1).wrapping_sub(old >> 1) & 0x3F) as i8;
+      if steps >= 0x20 { steps - 0x40 } else { steps }
+   }
+
+   #[test]
+   fn paddles_cover_the_pot_range() {
+      let mut port = ControlPort::new(ControlDevice::Paddles);
+      assert_eq!(port.pots(), [0x80, 0x80]);
+      port.set_paddle(0, 0x00);
+      port.set_paddle(1, 0xFF);
+      assert_eq!(port.pots(), [0x00, 0xFF]);
+      // turning stops at the ends
+      port.move_paddle(0, -10);
+      port.move_paddle(1, 10);
+      assert_eq!(port.pots(), [0x00, 0xFF]);
+      port.move_paddle(0, 300);
+      port.move_paddle(1, -0x7F);
+      assert_eq!(port.pots(), [0xFF, 0x80]);
+   }
+
+   #[test]
+   fn mouse_position_is_in_bits_1_to_6() {
+      let mut port = ControlPort::new(ControlDevice::Mouse);
+      let start = port.pots();
+      port.move_mouse(5, -3);
+      let moved = port.pots();
+      assert_eq!((delta(start[0], moved[0]), delta(start[1], moved[1])), (5, -3));
+      assert_eq!((moved[0] & 0x81, moved[1] & 0x81), (0, 0));
+
+      // the position wraps at 64 steps, so a driver sees up to 31 steps either way
+      port.move_mouse(31, -31);
+      let wrapped = port.pots();
+      assert_eq!((delta(moved[0], wrapped[0]), delta(moved[1], wrapped[1])), (31, -31));
+      assert_eq!(wrapped, [(36 << 1) & 0x7E, ((64 - 34) << 1) & 0x7E]);
+   }
+
+   #[test]
+   fn joystick_leaves_the_pots_open() {
+      let mut port = ControlPort::new(ControlDevice::Joystick);
+      port.set_button(0, true);
+      assert_eq!(port.pots(), [0xFF, 0xFF]);
+      assert_eq!(port.buttons(), 0);
+   }
+
+   #[test]
+   fn buttons_go_to_the_joystick_lines() {
+      let mut port = ControlPort::new(ControlDevice::Mouse);
+      port.set_button(0, true);
+      port.set_button(1, true);
+      assert_eq!(port.buttons(), JOY_FIRE | JOY_UP);
+      let mut port = ControlPort::new(ControlDevice::Paddles);
+      port.set_button(1, true);
+      assert_eq!(port.buttons(), JOY_RIGHT);
+   }
+}
@@ -17,7 +17,8 @@ pub struct Cpu {
    a: u8,
    x: u8,
    y: u8,
-   status: u8
+   status: u8,
+   irq_depth: u8
 }
 
 impl Cpu {
@@ -28,7 +29,8 @@ impl Cpu {
          a: 0,
          x: 0,
          y: 0,
-         status: 0b0010000
+         status: 0b0010000,
+         irq_depth: 0
       }
    }
 
@@ -36,10 +38,87 @@ impl Cpu {
       self.pc = address;
    }
 
+   pub fn pc(&self) -> u16 {
+      self.pc
+   }
+
    pub fn set_sp(&mut self, address: u16) {
       self.sp = address;
    }
 
+   // true while running an interrupt handler, i.e. between entry and its RTI
+   pub fn in_interrupt(&self) -> bool {
+      self.irq_depth > 0
+   }
+
+   // the cycles the next instruction takes: reads through an index that crosses a page
+   // take one more, a taken branch one more and another if it lands in a different page
+   pub fn peek_cycles(&self, memory: &mut dyn IOMem) -> u8 {
+      let op = match get_opcode(memory.read_u8(self.pc)) {
+         Some(op) => op,
+         None     => return 1
+      };
+      let operand = self.pc.wrapping_add(1);
+      let extra = match op.mode {
+         OpMode::AbsoluteX | OpMode::AbsoluteY | OpMode::IndirectY if page_penalty(op.mnemonic) => {
+            let (base, index) = match op.mode {
+               OpMode::AbsoluteX => (memory.read_u16(operand), self.x),
+               OpMode::AbsoluteY => (memory.read_u16(operand), self.y),
+               _                 => {
+                  let pointer = memory.read_u8(operand);
+                  let low = memory.read_u8(pointer as u16);
+                  let high = memory.read_u8(pointer.wrapping_add(1) as u16);
+                  ((high as u16) << 8 | (low as u16), self.y)
+               }
+            };
+            crosses_page(base, base.wrapping_add(index as u16)) as u8
+         },
+         OpMode::Relative if self.branch_taken(op.mnemonic) => {
+            let next = self.pc.wrapping_add(2);
+            let target = next.wrapping_add(memory.read_u8(operand) as i8 as u16);
+            1 + crosses_page(next, target) as u8
+         },
+         _ => 0
+      };
+      op.cycles + extra
+   }
+
+   fn branch_taken(&self, mnemonic: &str) -> bool {
+      match mnemonic {
+         "BCC" => self.status & CARRY == 0,
+         "BCS" => self.status & CARRY != 0,
+         "BEQ" => self.status & ZERO != 0,
+         "BNE" => self.status & ZERO == 0,
+         "BMI" => self.status & NEGATIV != 0,
+         "BPL" => self.status & NEGATIV == 0,
+         "BVS" => self.status & OVERFLW != 0,
+         "BVC" => self.status & OVERFLW == 0,
+         _     => false
+      }
+   }
+
+   pub fn irq(&mut self, memory: &mut dyn IOMem) -> bool {
+      if self.status == self.status | INTERRUPT {
+         return false;
+      }
+      self.interrupt(memory, 0xFFFE);
+      true
+   }
+
+   pub fn nmi(&mut self, memory: &mut dyn IOMem) {
+      self.interrupt(memory, 0xFFFA);
+   }
+
+   fn interrupt(&mut self, memory: &mut dyn IOMem, vector: u16) {
+      let _pc = self.pc;
+      self.push_stack_u16(memory, _pc);
+      let _status = self.status & !BREAK;
+      self.push_stack(memory, _status);
+      self.status |= INTERRUPT;
+      self.pc = memory.read_u16(vector);
+      self.irq_depth = self.irq_depth.saturating_add(1);
+   }
+
    pub fn execute_instructions(&mut self, memory: &mut dyn IOMem) -> Result<(), String> {
       let byte = memory.read_u8(self.pc);
       let opcode = match get_opcode(byte) {
@@ -64,12 +143,13 @@ impl Cpu {
       //    self.x,
       //    self.y,
       //    self.sp);
-      // self.pc += (opcode.bytes - 1) as u16;
+      self.pc += (opcode.bytes - 1) as u16;
+      let accumulator = matches!(opcode.mode, OpMode::Accumulator);
       
       match opcode.mnemonic {
          "ADC" => self.adc(memory, address),
          "AND" => self.and(memory, address),
-         "ASL" => self.asl(memory, address),
+         "ASL" => self.asl(memory, address, accumulator),
          "BCC" => self.bcc(memory, address),
          "BCS" => self.bcs(memory, address),
          "BEQ" => self.beq(memory, address),
@@ -99,15 +179,15 @@ impl Cpu {
          "LDA" => self.lda(memory, address),
          "LDX" => self.ldx(memory, address),
          "LDY" => self.ldy(memory, address),
-         "LSR" => self.lsr(memory, address),
+         "LSR" => self.lsr(memory, address, accumulator),
          "NOP" => self.nop(),
          "ORA" => self.ora(memory, address),
          "PHA" => self.pha(memory),
          "PHP" => self.php(memory),
          "PLA" => self.pla(memory),
          "PLP" => self.plp(memory),
-         "ROL" => self.rol(memory, address),
-         "ROR" => self.ror(memory, address),
+         "ROL" => self.rol(memory, address, accumulator),
+         "ROR" => self.ror(memory, address, accumulator),
          "RTI" => self.rti(memory),
          "RTS" => self.rts(memory),
          "SBC" => self.sbc(memory, address),
@@ -135,6 +215,10 @@ impl Cpu {
 
    fn adc(&mut self, memory: &mut dyn IOMem, address: u16) {
       let data = memory.read_u8(address);
+      if self.status == self.status | DECIMAL {
+         self.add_a_decimal(data);
+         return;
+      }
       self.add_a(data);
    }
    fn and(&mut self, memory: &mut dyn IOMem, address: u16) {
@@ -142,8 +226,8 @@ impl Cpu {
       self.a = self.a & data;
       self.set_zn(self.a);
    }
-   fn asl(&mut self, memory: &mut dyn IOMem, address: u16) {
-      if address == self.a as u16 {
+   fn asl(&mut self, memory: &mut dyn IOMem, address: u16, accumulator: bool) {
+      if accumulator {
          let data = self.a;
 
          if data >> 7 == 1 {
@@ -214,6 +298,7 @@ impl Cpu {
       self.push_stack(memory, _status);
       self.status |= INTERRUPT;
       self.pc = memory.read_u16(0xFFFE);
+      self.irq_depth = self.irq_depth.saturating_add(1);
    }
    fn bmi(&mut self, memory: &mut dyn IOMem, address: u16) {
       if self.status == self.status | NEGATIV {
@@ -317,7 +402,7 @@ impl Cpu {
       self.pc = address;
    }
    fn jsr(&mut self, memory: &mut dyn IOMem, address: u16) {
-      self.push_stack_u16(memory, self.pc + 1);
+      self.push_stack_u16(memory, self.pc - 1);
       let addr = address;
       self.pc = addr;
    }
@@ -336,8 +421,8 @@ impl Cpu {
       self.y = data;
       self.set_zn(self.y);
    }
-   fn lsr(&mut self, memory: &mut dyn IOMem, address: u16) {
-      if address == self.a as u16 {
+   fn lsr(&mut self, memory: &mut dyn IOMem, address: u16, accumulator: bool) {
+      if accumulator {
          let data = self.a;
 
          if data & 1 == 1 {
@@ -351,8 +436,7 @@ impl Cpu {
       } else {
          let mut data = memory.read_u8(address);
 
-         // if 0b1000_0000 >> 7 == 0b0000_0001 
-         if data >> 7 == 1 {
+         if data & 1 == 1 {
             self.status |= CARRY;
          } else {
             self.status &= !CARRY;
@@ -385,8 +469,8 @@ impl Cpu {
    fn plp(&mut self, memory: &mut dyn IOMem) {
       self.status = self.pop_stack(memory);
    }
-   fn rol(&mut self, memory: &mut dyn IOMem, address: u16) {
-      if address == self.a as u16 {
+   fn rol(&mut self, memory: &mut dyn IOMem, address: u16, accumulator: bool) {
+      if accumulator {
          let mut data = self.a;
          let old_carry = if self.status == self.status | CARRY {
             true
@@ -427,16 +511,11 @@ impl Cpu {
          }
          
          memory.write_u8(address, data);
-
-         if data >> 7 == 1{
-            self.status |= NEGATIV;
-         } else {
-            self.status &= !NEGATIV;
-         }
+         self.set_zn(data);
       }
    }
-   fn ror(&mut self, memory: &mut dyn IOMem, address: u16) {
-      if address == self.a as u16 {
+   fn ror(&mut self, memory: &mut dyn IOMem, address: u16, accumulator: bool) {
+      if accumulator {
          let mut data = self.a;
          let old_carry = if self.status == self.status | CARRY {
             true
@@ -452,7 +531,7 @@ impl Cpu {
 
          data = data >> 1;
          if old_carry {
-            data = data | 1;
+            data = data | 0x80;
          }
 
          self.a = data;
@@ -465,7 +544,7 @@ impl Cpu {
             false
          };
 
-         if data >> 7 == 1{
+         if data & 1 == 1 {
             self.status |= CARRY;
          } else {
             self.status &= !CARRY;
@@ -473,27 +552,27 @@ impl Cpu {
 
          data = data >> 1;
          if old_carry {
-            data = data | 1;
+            data = data | 0x80;
          }
          
          memory.write_u8(address, data);
-
-         if data >> 7 == 1{
-            self.status |= NEGATIV;
-         } else {
-            self.status &= !NEGATIV;
-         }
+         self.set_zn(data);
       }
    }
    fn rti(&mut self, memory: &mut dyn IOMem) {
       self.status = self.pop_stack(memory);
       self.pc = self.pop_stack_u16(memory);
+      self.irq_depth = self.irq_depth.saturating_sub(1);
    }
    fn rts(&mut self, memory: &mut dyn IOMem) {
       self.pc = self.pop_stack_u16(memory) + 1;
    }
    fn sbc(&mut self, memory: &mut dyn IOMem, address: u16) {
       let data = memory.read_u8(address);
+      if self.status == self.status | DECIMAL {
+         self.sub_a_decimal(data);
+         return;
+      }
       self.add_a(((data as i8).wrapping_neg().wrapping_sub(1)) as u8);
    }
    fn sec(&mut self) {
@@ -563,6 +642,59 @@ impl Cpu {
       self.set_zn(self.a);
    }
 
+   // NMOS decimal mode: Z comes from the binary sum, N and V from the intermediate result
+   fn add_a_decimal(&mut self, value: u8) {
+      let carry = (self.status & CARRY) as u16;
+      let (a, value) = (self.a as u16, value as u16);
+      let binary = (a + value + carry) as u8;
+
+      let mut low = (a & 0x0F) + (value & 0x0F) + carry;
+      if low > 0x09 {
+         low += 0x06;
+      }
+      let mut sum = (a & 0xF0) + (value & 0xF0) + if low > 0x0F { 0x10 } else { 0 } + (low & 0x0F);
+
+      self.set_zn(binary);
+      if sum & 0x80 != 0 {
+         self.status |= NEGATIV;
+      } else {
+         self.status &= !NEGATIV;
+      }
+      if (a ^ sum) & (value ^ sum) & 0x80 != 0 {
+         self.status |= OVERFLW;
+      } else {
+         self.status &= !OVERFLW;
+      }
+      if sum > 0x9F {
+         sum += 0x60;
+      }
+      if sum > 0xFF {
+         self.status |= CARRY;
+      } else {
+         self.status &= !CARRY;
+      }
+      self.a = sum as u8;
+   }
+
+   // NMOS decimal mode: all flags come from the binary difference
+   fn sub_a_decimal(&mut self, value: u8) {
+      let borrow = 1 - (self.status & CARRY) as i16;
+      let (a, data) = (self.a as i16, value as i16);
+
+      let mut low = (a & 0x0F) - (data & 0x0F) - borrow;
+      let mut high = (a >> 4) - (data >> 4);
+      if low < 0 {
+         low -= 6;
+         high -= 1;
+      }
+      if high < 0 {
+         high -= 6;
+      }
+
+      self.add_a(!value);
+      self.a = (((high << 4) | (low & 0x0F)) & 0xFF) as u8;
+   }
+
    fn set_zn(&mut self, value: u8) {
       if value == 0 {   
          self.status |= ZERO;
@@ -578,13 +710,13 @@ impl Cpu {
    }
 
    pub fn pop_stack(&mut self, memory: &mut dyn IOMem) -> u8 {
-      self.sp += 1;
+      self.sp = (self.sp + 1) & 0xFF;
       memory.read_u8(STACK_POINTER + self.sp) 
    }
 
    pub fn push_stack(&mut self, memory: &mut dyn IOMem, value: u8) {
       memory.write_u8(STACK_POINTER + self.sp, value);
-      self.sp -= 1;
+      self.sp = self.sp.wrapping_sub(1) & 0xFF;
    }
 
    pub fn pop_stack_u16(&mut self, memory: &mut dyn IOMem) -> u16 {
@@ -639,9 +771,10 @@ impl Cpu {
    }
 
    fn address_indirect(&self, memory: &mut dyn IOMem) -> u16 {
-      let pointer = memory.read_u8(self.pc);
-      let low = memory.read_u8(pointer as u16);
-      let high = memory.read_u8(pointer.wrapping_add(1) as u16);
+      // JMP ($xxFF) fetches the high byte from the start of the same page
+      let pointer = memory.read_u16(self.pc);
+      let low = memory.read_u8(pointer);
+      let high = memory.read_u8((pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF));
       (high as u16) << 8 | (low as u16)
    }
 
@@ -688,3 +821,266 @@ impl Cpu {
       addr
    }
 }
+
+// stores and read-modify-write instructions always take the extra cycle, it is in their count
+fn page_penalty(mnemonic: &str) -> bool {
+   matches!(mnemonic, "ADC" | "AND" | "CMP" | "EOR" | "LDA" | "LDX" | "LDY" | "ORA" | "SBC")
+}
+
+fn crosses_page(from: u16, to: u16) -> bool {
+   from & 0xFF00 != to & 0xFF00
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   struct Ram([u8; 0x10000]);
+
+   impl IOMem for Ram {
+      fn read_u8(&mut self, addr: u16) -> u8 {
+         self.0[addr as usize]
+      }
+      fn read_u16(&mut self, addr: u16) -> u16 {
+         self.0[addr as usize] as u16 | (self.0[addr.wrapping_add(1) as usize] as u16) << 8
+      }
+      fn write_u8(&mut self, addr: u16, value: u8) {
+         self.0[addr as usize] = value;
+      }
+   }
+
+   // a CPU at $C000 with the program there
+   fn setup(program: &[u8]) -> (Cpu, Ram) {
+      let mut ram = Ram([0; 0x10000]);
+      ram.0[0xC000..0xC000 + program.len()].copy_from_slice(program);
+      let mut cpu = Cpu::new();
+      cpu.set_pc(0xC000);
+      cpu.set_sp(0xFF);
+      (cpu, ram)
+   }
+
+   fn run(cpu: &mut Cpu, ram: &mut Ram, instructions: usize) {
+      for _ in 0..instructions {
+         cpu.execute_instructions(ram).unwrap();
+      }
+   }
+
+   #[test]
+   fn pc_steps_past_the_operands() {
+      // LDA #$01, LDX $10, NOP
+      let (mut cpu, mut ram) = setup(&[0xA9, 0x01, 0xA6, 0x10, 0xEA]);
+      run(&mut cpu, &mut ram, 1);
+      assert_eq!(cpu.pc(), 0xC002);
+      run(&mut cpu, &mut ram, 1);
+      assert_eq!(cpu.pc(), 0xC004);
+      run(&mut cpu, &mut ram, 1);
+      assert_eq!(cpu.pc(), 0xC005);
+   }
+
+   #[test]
+   fn every_opcode_steps_over_its_operands() {
+      for code in 0..=0xFFu8 {
+         let opcode = match get_opcode(code) {
+            Some(opcode) => opcode,
+            None         => continue
+         };
+         let bytes = match opcode.mode {
+            OpMode::Implied | OpMode::Accumulator => 1,
+            OpMode::Absolute | OpMode::AbsoluteX | OpMode::AbsoluteY | OpMode::Indirect => 3,
+            _ => 2
+         };
+         assert_eq!(opcode.bytes, bytes, "{} {:02X}", opcode.mnemonic, code);
+         if matches!(opcode.mnemonic, "BRK" | "JMP" | "JSR" | "RTS" | "RTI") {
+            continue;
+         }
+         // operand $1000 or $00; a branch by 0 lands after itself either way
+         let (mut cpu, mut ram) = setup(&[code, 0x00, 0x10]);
+         run(&mut cpu, &mut ram, 1);
+         assert_eq!(cpu.pc(), 0xC000 + bytes as u16, "{} {:02X}", opcode.mnemonic, code);
+      }
+   }
+
+   #[test]
+   fn jsr_pushes_the_last_byte_of_the_instruction_and_rts_returns_after_it() {
+      // JSR $C010 ... $C010: RTS
+      let (mut cpu, mut ram) = setup(&[0x20, 0x10, 0xC0]);
+      ram.0[0xC010] = 0x60;
+      run(&mut cpu, &mut ram, 1);
+      assert_eq!(cpu.pc(), 0xC010);
+      assert_eq!((ram.0[0x1FF], ram.0[0x1FE]), (0xC0, 0x02));
+      run(&mut cpu, &mut ram, 1);
+      assert_eq!(cpu.pc(), 0xC003);
+      assert_eq!(cpu.sp, 0xFF);
+   }
+
+   #[test]
+   fn nested_subroutines_return_in_order() {
+      // JSR $C010, NOP ... $C010: JSR $C020, RTS ... $C020: RTS
+      let (mut cpu, mut ram) = setup(&[0x20, 0x10, 0xC0, 0xEA]);
+      ram.0[0xC010..0xC014].copy_from_slice(&[0x20, 0x20, 0xC0, 0x60]);
+      ram.0[0xC020] = 0x60;
+      run(&mut cpu, &mut ram, 2);
+      assert_eq!(cpu.pc(), 0xC020);
+      run(&mut cpu, &mut ram, 1);
+      assert_eq!(cpu.pc(), 0xC013);
+      run(&mut cpu, &mut ram, 2);
+      assert_eq!(cpu.pc(), 0xC004);
+   }
+
+   #[test]
+   fn indexed_read_crossing_a_page_takes_a_cycle_more() {
+      // LDA $10F0,X
+      let (mut cpu, mut ram) = setup(&[0xBD, 0xF0, 0x10]);
+      cpu.x = 0x0F;
+      assert_eq!(cpu.peek_cycles(&mut ram), 4);
+      cpu.x = 0x10;
+      assert_eq!(cpu.peek_cycles(&mut ram), 5);
+   }
+
+   #[test]
+   fn indexed_store_has_a_fixed_count() {
+      // STA $10F0,X
+      let (mut cpu, mut ram) = setup(&[0x9D, 0xF0, 0x10]);
+      cpu.x = 0x10;
+      assert_eq!(cpu.peek_cycles(&mut ram), 5);
+   }
+
+   #[test]
+   fn indirect_y_read_crossing_a_page_takes_a_cycle_more() {
+      // LDA ($FB),Y with the pointer at $20FF
+      let (mut cpu, mut ram) = setup(&[0xB1, 0xFB]);
+      ram.0[0xFB] = 0xFF;
+      ram.0[0xFC] = 0x20;
+      cpu.y = 0;
+      assert_eq!(cpu.peek_cycles(&mut ram), 5);
+      cpu.y = 1;
+      assert_eq!(cpu.peek_cycles(&mut ram), 6);
+   }
+
+   #[test]
+   fn branch_cycles_depend_on_taken_and_target_page() {
+      // BNE +2 at $C000, BNE -4 at $C000 lands in the page before
+      let (mut cpu, mut ram) = setup(&[0xD0, 0x02]);
+      cpu.status |= ZERO;
+      assert_eq!(cpu.peek_cycles(&mut ram), 2);
+      cpu.status &= !ZERO;
+      assert_eq!(cpu.peek_cycles(&mut ram), 3);
+      ram.0[0xC001] = 0xFC;
+      assert_eq!(cpu.peek_cycles(&mut ram), 4);
+   }
+
+   // runs one instruction on A with the given status, returns A and the arithmetic flags
+   fn alu(program: &[u8], a: u8, status: u8) -> (u8, u8) {
+      let (mut cpu, mut ram) = setup(program);
+      cpu.a = a;
+      cpu.status = status;
+      run(&mut cpu, &mut ram, 1);
+      (cpu.a, cpu.status & (NEGATIV | OVERFLW | ZERO | CARRY))
+   }
+
+   #[test]
+   fn decimal_adc_adjusts_the_sum_and_sets_the_nmos_flags() {
+      // ADC #$46: 58 + 46 + 1 = 105, N and V come from the sum before the high digit is adjusted
+      assert_eq!(alu(&[0x69, 0x46], 0x58, DECIMAL | CARRY), (0x05, NEGATIV | OVERFLW | CARRY));
+      // ADC #$01: 99 + 1 = 100, Z comes from the binary sum $9A
+      assert_eq!(alu(&[0x69, 0x01], 0x99, DECIMAL), (0x00, NEGATIV | CARRY));
+      assert_eq!(alu(&[0x69, 0x22], 0x12, DECIMAL), (0x34, 0));
+   }
+
+   #[test]
+   fn decimal_sbc_adjusts_the_difference_and_takes_flags_from_binary() {
+      // SBC #$12: 46 - 12 = 34
+      assert_eq!(alu(&[0xE9, 0x12], 0x46, DECIMAL | CARRY), (0x34, CARRY));
+      // SBC #$21: 12 - 21 = 91 with a borrow, N from the binary $F1
+      assert_eq!(alu(&[0xE9, 0x21], 0x12, DECIMAL | CARRY), (0x91, NEGATIV));
+      // SBC #$00 with a borrow in: 40 - 0 - 1 = 39
+      assert_eq!(alu(&[0xE9, 0x00], 0x40, DECIMAL), (0x39, CARRY));
+      assert_eq!(alu(&[0xE9, 0x12], 0x12, DECIMAL | CARRY), (0x00, ZERO | CARRY));
+   }
+
+   #[test]
+   fn shifts_move_the_outgoing_bit_into_carry() {
+      // ASL A, LSR A
+      assert_eq!(alu(&[0x0A], 0x81, 0), (0x02, CARRY));
+      assert_eq!(alu(&[0x0A], 0x40, CARRY), (0x80, NEGATIV));
+      assert_eq!(alu(&[0x4A], 0x01, 0), (0x00, ZERO | CARRY));
+      assert_eq!(alu(&[0x4A], 0x80, CARRY), (0x40, 0));
+   }
+
+   #[test]
+   fn rotates_move_carry_in_and_the_outgoing_bit_out() {
+      // ROL A, ROR A
+      assert_eq!(alu(&[0x2A], 0x80, CARRY), (0x01, CARRY));
+      assert_eq!(alu(&[0x2A], 0x40, 0), (0x80, NEGATIV));
+      assert_eq!(alu(&[0x6A], 0x01, CARRY), (0x80, NEGATIV | CARRY));
+      assert_eq!(alu(&[0x6A], 0x01, 0), (0x00, ZERO | CARRY));
+   }
+
+   #[test]
+   fn memory_shifts_and_rotates_write_back() {
+      // ASL $10, ROL $11, LSR $12, ROR $13
+      let (mut cpu, mut ram) = setup(&[0x06, 0x10, 0x26, 0x11, 0x46, 0x12, 0x66, 0x13]);
+      ram.0[0x10..0x14].copy_from_slice(&[0xC0, 0x01, 0x03, 0x02]);
+      cpu.status = 0;
+      run(&mut cpu, &mut ram, 1);
+      assert_eq!((ram.0[0x10], cpu.status & CARRY), (0x80, CARRY));
+      run(&mut cpu, &mut ram, 1);
+      assert_eq!((ram.0[0x11], cpu.status & CARRY), (0x03, 0));
+      run(&mut cpu, &mut ram, 1);
+      assert_eq!((ram.0[0x12], cpu.status & CARRY), (0x01, CARRY));
+      run(&mut cpu, &mut ram, 1);
+      assert_eq!((ram.0[0x13], cpu.status & CARRY), (0x81, 0));
+   }
+
+   #[test]
+   fn shifts_and_rotates_work_in_every_memory_mode() {
+      // (opcodes for zp, zp,X, abs, abs,X; value in; carry in; value out; flags out)
+      let groups: [([u8; 4], u8, u8, u8, u8); 4] = [
+         ([0x06, 0x16, 0x0E, 0x1E], 0x81, 0,     0x02, CARRY),
+         ([0x46, 0x56, 0x4E, 0x5E], 0x01, CARRY, 0x00, ZERO | CARRY),
+         ([0x26, 0x36, 0x2E, 0x3E], 0x40, CARRY, 0x81, NEGATIV),
+         ([0x66, 0x76, 0x6E, 0x7E], 0x02, CARRY, 0x81, NEGATIV),
+      ];
+      for (codes, value, carry, result, flags) in groups {
+         // X = 2, so the indexed modes reach $12 and $1236
+         for (code, address) in codes.into_iter().zip([0x0010, 0x0012, 0x1234, 0x1236]) {
+            let operand = if code & 0x08 != 0 { vec![code, 0x34, 0x12] } else { vec![code, 0x10] };
+            let (mut cpu, mut ram) = setup(&operand);
+            cpu.x = 2;
+            cpu.status = carry;
+            ram.0[address] = value;
+            run(&mut cpu, &mut ram, 1);
+            assert_eq!(ram.0[address], result, "{:02X}", code);
+            assert_eq!(cpu.status & (NEGATIV | ZERO | CARRY), flags, "{:02X}", code);
+         }
+      }
+   }
+
+   #[test]
+   fn binary_adc_ignores_decimal_adjustment() {
+      // ADC #$46 with D clear: $58 + $46 + 1 = $9F, signed overflow
+      assert_eq!(alu(&[0x69, 0x46], 0x58, CARRY), (0x9F, NEGATIV | OVERFLW));
+      // SBC #$21: $12 - $21 = $F1 with a borrow
+      assert_eq!(alu(&[0xE9, 0x21], 0x12, CARRY), (0xF1, NEGATIV));
+   }
+
+   #[test]
+   fn jmp_indirect_reads_the_pointer_inside_a_page() {
+      let (mut cpu, mut ram) = setup(&[0x6C, 0x00, 0x10]);
+      ram.0[0x1000] = 0x34;
+      ram.0[0x1001] = 0x12;
+      run(&mut cpu, &mut ram, 1);
+      assert_eq!(cpu.pc(), 0x1234);
+   }
+
+   #[test]
+   fn jmp_indirect_wraps_within_the_pointer_page() {
+      // JMP ($10FF) takes the high byte from $1000, not $1100
+      let (mut cpu, mut ram) = setup(&[0x6C, 0xFF, 0x10]);
+      ram.0[0x10FF] = 0x34;
+      ram.0[0x1000] = 0x12;
+      ram.0[0x1100] = 0x56;
+      run(&mut cpu, &mut ram, 1);
+      assert_eq!(cpu.pc(), 0x1234);
+   }
+}
@@ -0,0 +1,182 @@
+use crate::framebuffer::*;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CrtFilter {
+   pub enabled: bool,
+   pub pal_blend: bool,
+   pub scanlines: f32,       // darkening of every second output line, 0.0 - 1.0
+   pub blur: f32,            // horizontal blur, 0.0 - 1.0
+   pub aspect: bool,         // stretch the output to 4:3
+}
+
+impl CrtFilter {
+   pub fn new() -> Self {
+      Self {
+         enabled: false,
+         pal_blend: true,
+         scanlines: 0.3,
+         blur: 0.5,
+         aspect: true,
+      }
+   }
+
+   pub fn output_size(&self, width: usize, height: usize) -> (usize, usize) {
+      let out_height = height * 2;
+      let out_width = if self.aspect {
+         (out_height as f32 * 4.0 / 3.0).round() as usize
+      } else {
+         width * 2
+      };
+      (out_width, out_height)
+   }
+
+   pub fn apply(&self, input: &FrameBuffer) -> FrameBuffer {
+      let (width, height) = (input.width(), input.height());
+      let mut lines: Vec<Vec<[f32; 3]>> = (0..height)
+         .map(|y| (0..width).map(|x| to_yuv(input.get_pixel(x, y))).collect())
+         .collect();
+
+      if self.pal_blend {
+         pal_blend(&mut lines);
+      }
+      if self.blur > 0.0 {
+         for line in lines.iter_mut() {
+            blur_line(line, self.blur);
+         }
+      }
+
+      let (out_width, out_height) = self.output_size(width, height);
+      let mut output = FrameBuffer::new(out_width, out_height);
+      let scale = width as f32 / out_width as f32;
+
+      for (y, line) in lines.iter().enumerate() {
+         for x in 0..out_width {
+            // linear interpolation between the two nearest source pixels
+            let src = ((x as f32 + 0.5) * scale - 0.5).max(0.0);
+            let left = (src as usize).min(width - 1);
+            let right = (left + 1).min(width - 1);
+            let t = src - left as f32;
+            let yuv = [0, 1, 2].map(|c| line[left][c] * (1.0 - t) + line[right][c] * t);
+
+            output.put_pixel(x, y * 2, to_rgb(yuv, 1.0));
+            output.put_pixel(x, y * 2 + 1, to_rgb(yuv, 1.0 - self.scanlines));
+         }
+      }
+
+      output
+   }
+}
+
+// PAL averages the chroma of each line with the line before it through the delay line
+fn pal_blend(lines: &mut [Vec<[f32; 3]>]) {
+   let mut previous: Option<Vec<[f32; 3]>> = None;
+   for line in lines.iter_mut() {
+      let current = line.clone();
+      if let Some(prev) = &previous {
+         for (pixel, above) in line.iter_mut().zip(prev.iter()) {
+            pixel[1] = (pixel[1] + above[1]) * 0.5;
+            pixel[2] = (pixel[2] + above[2]) * 0.5;
+         }
+      }
+      previous = Some(current);
+   }
+}
+
+fn blur_line(line: &mut [[f32; 3]], amount: f32) {
+   let source = line.to_vec();
+   let last = source.len() - 1;
+   let side = amount * 0.25;
+   for (x, pixel) in line.iter_mut().enumerate() {
+      let left = source[x.saturating_sub(1)];
+      let right = source[(x + 1).min(last)];
+      *pixel = [0, 1, 2].map(|c| source[x][c] * (1.0 - 2.0 * side) + (left[c] + right[c]) * side);
+   }
+}
+
+fn to_yuv(color: u32) -> [f32; 3] {
+   let r = ((color >> 16) & 0xFF) as f32 / 255.0;
+   let g = ((color >> 8) & 0xFF) as f32 / 255.0;
+   let b = (color & 0xFF) as f32 / 255.0;
+   let y = 0.299 * r + 0.587 * g + 0.114 * b;
+   [y, 0.492 * (b - y), 0.877 * (r - y)]
+}
+
+fn to_rgb(yuv: [f32; 3], brightness: f32) -> u32 {
+   let y = yuv[0];
+   let r = y + yuv[2] / 0.877;
+   let b = y + yuv[1] / 0.492;
+   let g = (y - 0.299 * r - 0.114 * b) / 0.587;
+   let channel = |v: f32| ((v * brightness).clamp(0.0, 1.0) * 255.0).round() as u32;
+   (channel(r) << 16) | (channel(g) << 8) | channel(b)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn filled(width: usize, height: usize, color: u32) -> FrameBuffer {
+      let mut frame = FrameBuffer::new(width, height);
+      for y in 0..height {
+         for x in 0..width {
+            frame.put_pixel(x, y, color);
+         }
+      }
+      frame
+   }
+
+   #[test]
+   fn output_doubles_the_lines_and_stretches_to_4_3() {
+      let mut filter = CrtFilter::new();
+      assert_eq!(filter.output_size(384, 272), (725, 544));
+      filter.aspect = false;
+      assert_eq!(filter.output_size(384, 272), (768, 544));
+      assert_eq!(filter.apply(&filled(384, 272, 0)).width(), 768);
+   }
+
+   #[test]
+   fn every_second_line_is_darkened() {
+      let mut filter = CrtFilter::new();
+      filter.scanlines = 0.5;
+      let output = filter.apply(&filled(8, 4, 0x808080));
+      assert_eq!(output.get_pixel(3, 2), 0x808080);
+      assert_eq!(output.get_pixel(3, 3), 0x404040);
+   }
+
+   #[test]
+   fn pal_blends_the_chroma_of_neighbouring_lines() {
+      let mut filter = CrtFilter::new();
+      filter.blur = 0.0;
+      filter.scanlines = 0.0;
+      filter.aspect = false;
+      let mut frame = filled(4, 2, 0xFF0000);
+      for x in 0..4 {
+         frame.put_pixel(x, 1, 0x0000FF);
+      }
+      // without the delay line the colors stay apart
+      filter.pal_blend = false;
+      assert_eq!(filter.apply(&frame).get_pixel(2, 2), 0x0000FF);
+      // with it the second line takes half its chroma from the red one
+      filter.pal_blend = true;
+      let output = filter.apply(&frame);
+      assert_eq!(output.get_pixel(2, 0), 0xFF0000);
+      let blended = output.get_pixel(2, 2);
+      assert!(blended >> 16 > 0x40 && blended & 0xFF > 0x40, "{:06X}", blended);
+   }
+
+   #[test]
+   fn blur_softens_edges() {
+      let mut filter = CrtFilter::new();
+      filter.pal_blend = false;
+      filter.aspect = false;
+      let mut frame = filled(8, 1, 0x000000);
+      for x in 4..8 {
+         frame.put_pixel(x, 0, 0xFFFFFF);
+      }
+      // output pixel 6 lies between source pixels 2 and 3, left of the edge
+      filter.blur = 0.0;
+      assert_eq!(filter.apply(&frame).get_pixel(6, 0), 0x000000);
+      filter.blur = 1.0;
+      let gray = filter.apply(&frame).get_pixel(6, 0);
+      assert!(gray > 0x000000 && gray < 0xFFFFFF, "{:06X}", gray);
+   }
+}
@@ -0,0 +1,600 @@
+use std::fs::File;
+use std::io::Read;
+
+use crate::c64mem::*;
+use crate::cia::*;
+use crate::config::*;
+use crate::controlport::*;
+use crate::cpu::*;
+use crate::framebuffer::*;
+use crate::gfxview::*;
+use crate::joystick::*;
+use crate::keyboard::*;
+use crate::memory::*;
+use crate::model::*;
+use crate::overlay;
+use crate::overlay::*;
+use crate::palette::*;
+use crate::psid::*;
+use crate::resampler::*;
+use crate::screenshot;
+use crate::sid::*;
+use crate::sidlog::*;
+use crate::userport::*;
+use crate::vic::*;
+use crate::wav::*;
+
+// the audio output is always stereo, interleaved left/right
+pub const AUDIO_CHANNELS: u16 = 2;
+
+const RESET_VECTOR: u16 = 0xFFFC;
+
+pub struct Emu {
+   cpu: Cpu,
+   mem: C64Mem,
+   vic: Vic,
+   frame: FrameBuffer,
+   audio: [Resampler; 2],
+   sid_layout: Vec<(u16, SidPan)>,
+   cia_models: [CiaModel; 2],
+   sid_gains: Vec<(f32, f32)>,
+   samples: Vec<f32>,
+   recording: Option<WavWriter>,
+   owner: CycleOwner,
+   stats: Vec<LineStats>,
+   last_stats: Vec<LineStats>,
+   breakpoints: Vec<u16>,
+   at_breakpoint: bool,
+   resumed: bool,
+   nmi_line: bool,
+   restore: bool,
+   joysticks: [Joystick; 2],
+   control_ports: [ControlPort; 2],
+   mains_phase: u32,
+   serial_devices: Vec<Box<dyn SerialDevice>>
+}
+
+impl Emu {
+   pub fn new(config: Config) -> Self {
+      let cpu = Cpu::new();
+      let mem = C64Mem::new();
+      let mut vic = Vic::new(config.model);
+      vic.set_palette(config.palette);
+      let frame = FrameBuffer::new(vic.frame_width(), vic.frame_height());
+      let audio = [
+         Resampler::new(config.model.cpu_clock(), config.sample_rate),
+         Resampler::new(config.model.cpu_clock(), config.sample_rate)
+      ];
+      let lines = vic.frame_height();
+      let mut sid_layout = vec![(SID_ADDRESS, config.sid_pan)];
+      sid_layout.extend_from_slice(&config.extra_sids);
+   
+      let mut emu = Self {
+         cpu: cpu,
+         mem: mem,
+         vic: vic,
+         frame: frame,
+         audio: audio,
+         sid_layout: Vec::new(),
+         cia_models: config.cia_models,
+         sid_gains: Vec::new(),
+         samples: Vec::new(),
+         recording: None,
+         owner: CycleOwner::Main,
+         stats: vec![LineStats::default(); lines],
+         last_stats: vec![LineStats::default(); lines],
+         breakpoints: config.breakpoints,
+         at_breakpoint: false,
+         resumed: false,
+         nmi_line: false,
+         restore: false,
+         joysticks: [Joystick::new(config.autofire), Joystick::new(config.autofire)],
+         control_ports: [ControlPort::new(config.control_devices[0]), ControlPort::new(config.control_devices[1])],
+         mains_phase: 0,
+         serial_devices: Vec::new()
+      };
+      if config.user_port == UserPortDevice::Loopback {
+         emu.connect_serial_device(Box::new(LoopbackPlug));
+      }
+      emu.install_cias();
+      emu.install_sids(config.sid_model, &sid_layout);
+      emu.sid_layout = sid_layout;
+      emu
+   }
+
+   fn install_cias(&mut self) {
+      self.mem.cia1 = Cia::new(self.cia_models[0]);
+      self.mem.cia2 = Cia::new(self.cia_models[1]);
+   }
+
+   // each channel is scaled so that the SIDs panned to it can't clip together
+   fn install_sids(&mut self, model: SidModel, layout: &[(u16, SidPan)]) {
+      let clock = self.vic.model().cpu_clock();
+      self.mem.sids = layout.iter().map(|&(address, _)| (address, Sid::new(model, clock))).collect();
+
+      let gains: Vec<(f32, f32)> = layout.iter().map(|(_, pan)| pan.gains()).collect();
+      let left = gains.iter().map(|gain| gain.0).sum::<f32>().max(1.0);
+      let right = gains.iter().map(|gain| gain.1).sum::<f32>().max(1.0);
+      self.sid_gains = gains.iter().map(|gain| (gain.0 / left, gain.1 / right)).collect();
+   }
+
+   pub fn start(&mut self, pc_addr: u16) {
+      let (basic, chargen, kernal) = (
+         self.load_file("rom/basic.bin"),
+         self.load_file("rom/characters.bin"),
+         self.load_file("rom/kernal.bin")
+      );
+
+      self.mem.load_rom(&basic, 0xA000);
+      self.mem.load_rom(&chargen, 0xD000);
+      self.mem.load_rom(&kernal, 0xE000);
+      self.cpu.set_pc(pc_addr);
+      self.cpu.set_sp(0xFF);
+
+      self.vic.init(&mut self.mem);
+   }
+
+   // boots through the KERNAL reset vector
+   pub fn reset(&mut self) {
+      self.start(0);
+      let address = self.mem.read_u16(RESET_VECTOR);
+      self.cpu.set_pc(address);
+   }
+
+   pub fn step(&mut self) -> Result<(), String> {
+      self.owner = CycleOwner::Stolen;
+      while self.vic.ba_low() {
+         self.clock();
+      }
+
+      // NMI is edge triggered, IRQ is taken as long as a source holds the line low
+      let nmi = self.mem.cia2.irq() || self.restore;
+      if nmi && !self.nmi_line {
+         self.cpu.nmi(&mut self.mem);
+         self.owner = CycleOwner::Irq;
+         for _ in 0..7 {
+            self.clock();
+         }
+      }
+      self.nmi_line = nmi;
+
+      let irq = self.vic.irq(&self.mem) || self.mem.cia1.irq();
+      if irq && self.cpu.irq(&mut self.mem) {
+         self.owner = CycleOwner::Irq;
+         for _ in 0..7 {
+            self.clock();
+         }
+      }
+
+      self.owner = if self.cpu.in_interrupt() { CycleOwner::Irq } else { CycleOwner::Main };
+
+      // let the VIC catch up to the last cycle of the instruction so that
+      // register writes land at the right beam position
+      let cycles = self.cpu.peek_cycles(&mut self.mem);
+      for _ in 1..cycles {
+         self.clock();
+      }
+      let result = self.cpu.execute_instructions(&mut self.mem);
+      self.clock();
+      result
+   }
+
+   // runs to the end of the frame, or until the CPU reaches a breakpoint
+   pub fn run_frame(&mut self) -> Result<(), String> {
+      let mut error = None;
+      while !self.vic.frame_ready() {
+         if self.at_breakpoint {
+            return Ok(());
+         }
+         if !self.resumed && self.breakpoints.contains(&self.cpu.pc()) {
+            self.at_breakpoint = true;
+            return Ok(());
+         }
+         self.resumed = false;
+
+         if let Err(e) = self.step() {
+            error = Some(e);
+         }
+      }
+
+      std::mem::swap(&mut self.stats, &mut self.last_stats);
+      self.stats.iter_mut().for_each(|line| *line = LineStats::default());
+      self.joysticks.iter_mut().for_each(|joystick| joystick.end_frame());
+      self.update_joysticks();
+
+      if let Err(e) = self.record_audio() {
+         error = Some(e);
+      }
+      if let Err(e) = self.log_sid_frame() {
+         error = Some(e);
+      }
+
+      match error {
+         Some(e) => Err(e),
+         None    => Ok(())
+      }
+   }
+
+   pub fn at_breakpoint(&self) -> bool {
+      self.at_breakpoint
+   }
+
+   pub fn resume(&mut self) {
+      if self.at_breakpoint {
+         self.at_breakpoint = false;
+         self.resumed = true;
+      }
+   }
+
+   fn clock(&mut self) {
+      let line = self.vic.raster() as usize;
+      self.vic.clock(&mut self.mem, &mut self.frame);
+      self.mem.cia1.clock();
+      self.mem.cia2.clock();
+      if !self.serial_devices.is_empty() {
+         self.clock_serial_devices();
+      }
+      let model = self.vic.model();
+      self.mains_phase += model.mains_frequency();
+      if self.mains_phase >= model.cpu_clock() {
+         self.mains_phase -= model.cpu_clock();
+         self.mem.cia1.tod_tick();
+         self.mem.cia2.tod_tick();
+      }
+      if let Some(log) = &mut self.mem.sid_log {
+         log.clock();
+      }
+      let pots = self.selected_pots();
+      self.mem.sids[0].1.set_pot_inputs(pots);
+      let (mut left, mut right) = (0.0, 0.0);
+      for ((_, sid), gain) in self.mem.sids.iter_mut().zip(&self.sid_gains) {
+         sid.clock();
+         left += sid.output() * gain.0;
+         right += sid.output() * gain.1;
+      }
+      self.audio[0].push(left);
+      self.audio[1].push(right);
+
+      let stats = &mut self.stats[line];
+      stats.add(self.owner);
+      stats.bad_line |= self.vic.bad_line();
+      stats.sprite_dma |= self.vic.sprite_dma();
+   }
+
+   // the frame with per-line CPU usage; the beam is marked while stopped at a breakpoint
+   pub fn overlay(&self) -> FrameBuffer {
+      let stats = if self.at_breakpoint { &self.stats } else { &self.last_stats };
+      let beam = if self.at_breakpoint {
+         Some(((self.vic.cycle() as usize - 1) * 8, self.vic.raster() as usize))
+      } else {
+         None
+      };
+      overlay::render(&self.frame, stats, self.vic.cycles_per_line(), beam)
+   }
+
+   pub fn frame(&self) -> &FrameBuffer {
+      &self.frame
+   }
+
+   // the part of the frame a monitor shows, or only the display window
+   pub fn screen(&self, crop_border: bool) -> FrameBuffer {
+      let (x, y, width, height) = if crop_border {
+         self.vic.model().display_area()
+      } else {
+         self.vic.model().visible_area()
+      };
+      self.frame.crop(x, y, width, height)
+   }
+
+   pub fn screenshot(&self, file_name: &str, crop_border: bool) -> Result<(), String> {
+      screenshot::save(&self.screen(crop_border), file_name)
+   }
+
+   // sprites, charset, screen or any memory as the VIC currently sees it
+   pub fn gfx_view(&self, view: &GfxView) -> FrameBuffer {
+      view.render(&self.mem, self.vic.palette())
+   }
+
+   pub fn gfx_view_title(&self, view: &GfxView) -> String {
+      view.title(&self.mem)
+   }
+
+   pub fn model(&self) -> VicModel {
+      self.vic.model()
+   }
+
+   pub fn set_light_pen(&mut self, position: Option<(usize, usize)>) {
+      self.vic.set_light_pen(position);
+   }
+
+   // the audio device may not give us the rate we asked for
+   pub fn set_sample_rate(&mut self, sample_rate: u32) {
+      if sample_rate != self.audio[0].output_rate() {
+         let clock = self.vic.model().cpu_clock();
+         self.audio = [Resampler::new(clock, sample_rate), Resampler::new(clock, sample_rate)];
+      }
+   }
+
+   pub fn set_audio_adjust(&mut self, adjust: f64) {
+      for channel in self.audio.iter_mut() {
+         channel.set_adjust(adjust);
+      }
+   }
+
+   // interleaved stereo samples at the output rate produced since the last call
+   pub fn audio_samples(&mut self) -> Vec<f32> {
+      std::mem::take(&mut self.samples)
+   }
+
+   fn record_audio(&mut self) -> Result<(), String> {
+      let left = self.audio[0].take();
+      let right = self.audio[1].take();
+      let samples: Vec<f32> = left.iter().zip(&right).flat_map(|(&l, &r)| [l, r]).collect();
+      let result = match &mut self.recording {
+         Some(recording) => recording.write(&samples),
+         None            => Ok(())
+      };
+      self.samples.extend_from_slice(&samples);
+
+      let full = self.recording.as_ref().is_some_and(|recording| recording.is_full());
+      if result.is_err() || full {
+         self.stop_recording()?;
+      }
+      result
+   }
+
+   // records the audio output to a .wav file, optionally only the next few seconds
+   pub fn start_recording(&mut self, file_name: &str, seconds: Option<f64>) -> Result<(), String> {
+      self.stop_recording()?;
+      self.recording = Some(WavWriter::create(file_name, self.audio[0].output_rate(), AUDIO_CHANNELS, seconds)?);
+      Ok(())
+   }
+
+   pub fn stop_recording(&mut self) -> Result<(), String> {
+      match self.recording.take() {
+         Some(recording) => recording.finish(),
+         None            => Ok(())
+      }
+   }
+
+   pub fn is_recording(&self) -> bool {
+      self.recording.is_some()
+   }
+
+   // logs SID register writes as text, as per-frame register dumps or both
+   pub fn start_sid_log(&mut self, log: Option<&str>, dump: Option<&str>) -> Result<(), String> {
+      self.stop_sid_log()?;
+      let sids: Vec<u16> = self.mem.sids.iter().map(|(address, _)| *address).collect();
+      self.mem.sid_log = Some(SidLog::create(log, dump, &sids)?);
+      Ok(())
+   }
+
+   pub fn stop_sid_log(&mut self) -> Result<(), String> {
+      match self.mem.sid_log.take() {
+         Some(sid_log) => sid_log.finish(),
+         None          => Ok(())
+      }
+   }
+
+   fn log_sid_frame(&mut self) -> Result<(), String> {
+      let result = match &mut self.mem.sid_log {
+         Some(sid_log) => sid_log.end_frame(),
+         None          => Ok(())
+      };
+      if result.is_err() {
+         self.mem.sid_log = None;
+      }
+      result
+   }
+
+   pub fn set_key(&mut self, key: C64Key, pressed: bool) {
+      self.mem.keyboard.set_key(key, pressed);
+   }
+
+   pub fn set_shift_override(&mut self, shift: Option<bool>) {
+      self.mem.keyboard.set_shift_override(shift);
+   }
+
+   pub fn toggle_shift_lock(&mut self) {
+      self.mem.keyboard.toggle_shift_lock();
+   }
+
+   // RESTORE is not in the matrix, it pulls NMI
+   pub fn set_restore(&mut self, pressed: bool) {
+      self.restore = pressed;
+   }
+
+   // port is 0 for control port 1, 1 for control port 2; lines are JOY_* bits
+   pub fn set_joystick(&mut self, port: usize, lines: u8) {
+      self.joysticks[port].set(lines);
+      self.update_joysticks();
+   }
+
+   // fire presses per second while fire is held, None turns autofire off
+   pub fn set_autofire(&mut self, autofire: Option<f32>) {
+      self.joysticks.iter_mut().for_each(|joystick| joystick.set_autofire(autofire));
+      self.update_joysticks();
+   }
+
+   fn update_joysticks(&mut self) {
+      let frame_rate = self.vic.model().frame_rate();
+      for (port, (joystick, control_port)) in self.joysticks.iter().zip(&self.control_ports).enumerate() {
+         self.mem.keyboard.set_joystick(port, joystick.lines(frame_rate) | control_port.buttons());
+      }
+   }
+
+   // paddle is 0 for POTX, 1 for POTY
+   pub fn set_paddle(&mut self, port: usize, paddle: usize, value: u8) {
+      self.control_ports[port].set_paddle(paddle, value);
+   }
+
+   pub fn move_paddle(&mut self, port: usize, paddle: usize, delta: i32) {
+      self.control_ports[port].move_paddle(paddle, delta);
+   }
+
+   pub fn move_mouse(&mut self, port: usize, dx: i32, dy: i32) {
+      self.control_ports[port].move_mouse(dx, dy);
+   }
+
+   // button 0 is the first paddle's or the left mouse button, 1 the other
+   pub fn set_port_button(&mut self, port: usize, button: usize, pressed: bool) {
+      self.control_ports[port].set_button(button, pressed);
+      self.update_joysticks();
+   }
+
+   pub fn connect_serial_device(&mut self, device: Box<dyn SerialDevice>) {
+      self.serial_devices.push(device);
+   }
+
+   fn clock_serial_devices(&mut self) {
+      let pins = [
+         SerialPins { cnt: self.mem.cia1.cnt_output(), sp: self.mem.cia1.sp_output() },
+         SerialPins { cnt: self.mem.cia2.cnt_output(), sp: self.mem.cia2.sp_output() }
+      ];
+      let mut inputs = [SerialPins::RELEASED; 2];
+      for device in &mut self.serial_devices {
+         let driven = device.clock(pins);
+         for (input, line) in inputs.iter_mut().zip(driven) {
+            input.cnt &= line.cnt;
+            input.sp &= line.sp;
+         }
+      }
+      self.mem.cia1.set_cnt_input(inputs[0].cnt);
+      self.mem.cia1.set_sp_input(inputs[0].sp);
+      self.mem.cia2.set_cnt_input(inputs[1].cnt);
+      self.mem.cia2.set_sp_input(inputs[1].sp);
+   }
+
+   // CIA 1 PA6 switches control port 1 onto the SID's pot inputs, PA7 control port 2
+   fn selected_pots(&self) -> [u8; 2] {
+      let select = self.mem.cia1.port_a_output() >> 6;
+      let mut pots = [0xFF; 2];
+      for (port, control_port) in self.control_ports.iter().enumerate() {
+         if select & (1 << port) != 0 {
+            let inputs = control_port.pots();
+            // both switched on puts the resistances in parallel, the lower one dominates
+            pots = [pots[0].min(inputs[0]), pots[1].min(inputs[1])];
+         }
+      }
+      pots
+   }
+
+   pub fn set_palette(&mut self, palette: Palette) {
+      self.vic.set_palette(palette);
+   }
+
+   // .prg files start with their little-endian load address
+   pub fn load_prg(&mut self, data: &[u8]) -> Result<u16, String> {
+      if data.len() < 3 {
+         return Err(String::from("PRG File Too Short"));
+      }
+      let address = u16::from_le_bytes([data[0], data[1]]);
+      if address as usize + data.len() - 2 > 0x10000 {
+         return Err(format!("PRG Does Not Fit At {:#06X}", address));
+      }
+      self.mem.load_ram(&data[2..].to_vec(), address);
+      Ok(address)
+   }
+
+   // resets the machine and starts the player for one song of a .sid file
+   pub fn play_sid(&mut self, tune: &Psid, song: u16) -> Result<(), String> {
+      if song == 0 || song > tune.songs {
+         return Err(format!("No Song {} In {}", song, tune.name));
+      }
+      let (blocks, entry) = tune.memory_image(song, self.vic.model())?;
+
+      // tunes for more SIDs bring their own layout: the first left, the second right
+      let extra_sids = tune.extra_sids();
+      let layout = if extra_sids.is_empty() {
+         self.sid_layout.clone()
+      } else {
+         let pans = [SidPan::Left, SidPan::Right, SidPan::Center];
+         std::iter::once(SID_ADDRESS).chain(extra_sids).zip(pans).collect()
+      };
+
+      let model = self.mem.sids[0].1.model();
+      self.cpu = Cpu::new();
+      // a running SID log carries on across songs
+      let sid_log = self.mem.sid_log.take();
+      self.mem = C64Mem::new();
+      self.mem.sid_log = sid_log;
+      self.install_cias();
+      self.install_sids(model, &layout);
+      self.start(entry);
+      for (address, data) in &blocks {
+         self.mem.load_ram(data, *address);
+      }
+      Ok(())
+   }
+
+   pub fn peek(&self, addr: u16) -> u8 {
+      self.mem.ram[addr as usize]
+   }
+
+   pub fn load_file(&mut self, file_name: &str) -> Vec<u8> {
+      let mut file = File::open(file_name).unwrap();
+      let mut data = Vec::<u8>::new();
+      file.read_to_end(&mut data).expect("File Not Exist");
+      data
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const TA_LO : u8 = 0x04;
+   const SDR   : u8 = 0x0C;
+   const CRA   : u8 = 0x0E;
+
+   // shifts a byte into one CIA msb first, four cycles with CNT low, four high per bit
+   struct ShiftIn {
+      port: usize,
+      byte: u8,
+      cycle: usize,
+   }
+
+   impl SerialDevice for ShiftIn {
+      fn clock(&mut self, _pins: [SerialPins; 2]) -> [SerialPins; 2] {
+         let mut driven = [SerialPins::RELEASED; 2];
+         let bit = self.cycle / 8;
+         if bit < 8 {
+            driven[self.port] = SerialPins {
+               cnt: self.cycle % 8 >= 4,
+               sp: self.byte & (0x80 >> bit) != 0
+            };
+         }
+         self.cycle += 1;
+         driven
+      }
+   }
+
+   #[test]
+   fn external_device_shifts_a_byte_into_either_cia() {
+      for port in 0..2 {
+         let mut emu = Emu::new(Config::new());
+         emu.connect_serial_device(Box::new(ShiftIn { port: port, byte: 0xA5, cycle: 0 }));
+         for _ in 0..80 {
+            emu.clock();
+         }
+         assert_eq!(emu.mem.read_u8(0xDC0C), if port == 0 { 0xA5 } else { 0x00 });
+         assert_eq!(emu.mem.read_u8(0xDD0C), if port == 1 { 0xA5 } else { 0x00 });
+      }
+   }
+
+   #[test]
+   fn loopback_carries_a_byte_from_cia1_to_cia2() {
+      let mut config = Config::new();
+      config.user_port = UserPortDevice::Loopback;
+      let mut emu = Emu::new(config);
+      emu.mem.cia1.write(TA_LO, 4);
+      emu.mem.cia1.write(TA_LO + 1, 0);
+      // timer A running continuously drives the shift clock with the port in output mode
+      emu.mem.cia1.write(CRA, 0x41);
+      emu.mem.cia1.write(SDR, 0x3C);
+      for _ in 0..200 {
+         emu.clock();
+      }
+      assert_eq!(emu.mem.cia2.read(SDR), 0x3C);
+   }
+}
@@ -0,0 +1,70 @@
+// RGBA8 pixels, row-major, 4 bytes per pixel
+#[derive(Clone)]
+pub struct FrameBuffer {
+   width: usize,
+   height: usize,
+   pixels: Vec<u8>,
+}
+
+impl FrameBuffer {
+   pub fn new(width: usize, height: usize) -> Self {
+      Self {
+         width: width,
+         height: height,
+         pixels: vec![0u8; width * height * 4],
+      }
+   }
+
+   pub fn from_pixels(width: usize, height: usize, pixels: Vec<u8>) -> Result<Self, String> {
+      if pixels.len() != width * height * 4 {
+         return Err(format!("Frame Buffer Size Mismatch: {}x{} needs {} bytes, got {}", width, height, width * height * 4, pixels.len()));
+      }
+      Ok(Self {
+         width: width,
+         height: height,
+         pixels: pixels,
+      })
+   }
+
+   pub fn width(&self) -> usize {
+      self.width
+   }
+
+   pub fn height(&self) -> usize {
+      self.height
+   }
+
+   pub fn pixels(&self) -> &[u8] {
+      &self.pixels
+   }
+
+   pub fn pitch(&self) -> usize {
+      self.width * 4
+   }
+
+   // color is 0xRRGGBB
+   pub fn put_pixel(&mut self, x: usize, y: usize, color: u32) {
+      let offset = (y * self.width + x) * 4;
+      self.pixels[offset] = (color >> 16) as u8;
+      self.pixels[offset + 1] = (color >> 8) as u8;
+      self.pixels[offset + 2] = color as u8;
+      self.pixels[offset + 3] = 0xFF;
+   }
+
+   pub fn get_pixel(&self, x: usize, y: usize) -> u32 {
+      let offset = (y * self.width + x) * 4;
+      (self.pixels[offset] as u32) << 16
+         | (self.pixels[offset + 1] as u32) << 8
+         | self.pixels[offset + 2] as u32
+   }
+
+   pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> FrameBuffer {
+      let mut cropped = FrameBuffer::new(width, height);
+      for row in 0..height {
+         let src = ((y + row) * self.width + x) * 4;
+         let dst = row * width * 4;
+         cropped.pixels[dst..dst + width * 4].copy_from_slice(&self.pixels[src..src + width * 4]);
+      }
+      cropped
+   }
+}
@@ -0,0 +1,408 @@
+use crate::framebuffer::*;
+use crate::memory::*;
+use crate::palette::*;
+use crate::vic::*;
+
+const SPRITE_WIDTH    : usize = 24;
+const SPRITE_HEIGHT   : usize = 21;
+const SPRITE_CELL_W   : usize = 28;
+const SPRITE_CELL_H   : usize = 25;
+const SPRITE_COLUMNS  : usize = 8;
+const BROWSE_SPRITES  : usize = 32;
+const GRID_COLOR      : u32 = 0x202020;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ViewPage {
+   Sprites,
+   Charset,
+   Screen,
+   Memory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ViewKind {
+   HiresSprite,
+   MultiSprite,
+   HiresChar,
+   MultiChar,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GfxView {
+   pub page: ViewPage,
+   pub kind: ViewKind,
+   pub address: u16,
+}
+
+impl GfxView {
+   pub fn new() -> Self {
+      Self {
+         page: ViewPage::Sprites,
+         kind: ViewKind::HiresChar,
+         address: 0x0000,
+      }
+   }
+
+   // "sprites", "charset", "screen" or a memory view such as "multi-sprite@2000"
+   pub fn from_spec(spec: &str) -> Result<Self, String> {
+      let mut view = GfxView::new();
+      let (name, address) = match spec.split_once('@') {
+         Some((name, address)) => (name, Some(address)),
+         None                  => (spec, None)
+      };
+
+      view.page = match name {
+         "sprites" => ViewPage::Sprites,
+         "charset" => ViewPage::Charset,
+         "screen"  => ViewPage::Screen,
+         _         => ViewPage::Memory
+      };
+      if view.page == ViewPage::Memory {
+         view.kind = match name {
+            "hires-sprite" => ViewKind::HiresSprite,
+            "multi-sprite" => ViewKind::MultiSprite,
+            "hires-char"   => ViewKind::HiresChar,
+            "multi-char"   => ViewKind::MultiChar,
+            _              => return Err(format!("Unknown Graphics View: {}", name))
+         };
+         let address = address.ok_or(format!("Graphics View Needs An Address: {}", spec))?;
+         view.address = u16::from_str_radix(address.trim_start_matches('$'), 16)
+            .map_err(|_| format!("Bad Graphics View Address: {}", address))?;
+      } else if address.is_some() {
+         return Err(format!("Graphics View Takes No Address: {}", spec));
+      }
+
+      Ok(view)
+   }
+
+   pub fn next_page(&mut self) {
+      self.page = match self.page {
+         ViewPage::Sprites => ViewPage::Charset,
+         ViewPage::Charset => ViewPage::Screen,
+         ViewPage::Screen  => ViewPage::Memory,
+         ViewPage::Memory  => ViewPage::Sprites,
+      };
+   }
+
+   pub fn is_sprite(&self) -> bool {
+      matches!(self.kind, ViewKind::HiresSprite | ViewKind::MultiSprite)
+   }
+
+   pub fn is_multicolor(&self) -> bool {
+      matches!(self.kind, ViewKind::MultiSprite | ViewKind::MultiChar)
+   }
+
+   pub fn toggle_multicolor(&mut self) {
+      self.kind = match self.kind {
+         ViewKind::HiresSprite => ViewKind::MultiSprite,
+         ViewKind::MultiSprite => ViewKind::HiresSprite,
+         ViewKind::HiresChar   => ViewKind::MultiChar,
+         ViewKind::MultiChar   => ViewKind::HiresChar,
+      };
+   }
+
+   pub fn toggle_sprite(&mut self) {
+      self.kind = match self.kind {
+         ViewKind::HiresSprite => ViewKind::HiresChar,
+         ViewKind::MultiSprite => ViewKind::MultiChar,
+         ViewKind::HiresChar   => ViewKind::HiresSprite,
+         ViewKind::MultiChar   => ViewKind::MultiSprite,
+      };
+   }
+
+   // size of one step through memory: a sprite or a character
+   pub fn step(&self) -> u16 {
+      if self.is_sprite() { 64 } else { 8 }
+   }
+
+   // size of the whole memory page: 32 sprites or 256 characters
+   pub fn page_size(&self) -> u16 {
+      if self.is_sprite() { (BROWSE_SPRITES * 64) as u16 } else { 0x800 }
+   }
+
+   pub fn title(&self, mem: &dyn IOVic) -> String {
+      let bank = mem.bank();
+      match self.page {
+         ViewPage::Sprites => format!("Sprites (Bank {:#06X})", bank),
+         ViewPage::Charset => format!("Charset {:#06X}", bank | Vic::char_base(mem)),
+         ViewPage::Screen  => format!("Screen {:#06X}", bank | Vic::video_matrix(mem)),
+         ViewPage::Memory  => format!("{:?} {:#06X}", self.kind, self.address),
+      }
+   }
+
+   pub fn render(&self, mem: &dyn IOVic, palette: &Palette) -> FrameBuffer {
+      match self.page {
+         ViewPage::Sprites => render_sprites(mem, palette),
+         ViewPage::Charset => render_charset(mem, palette),
+         ViewPage::Screen  => render_screen(mem, palette),
+         ViewPage::Memory  => self.render_memory(mem, palette),
+      }
+   }
+
+   fn render_memory(&self, mem: &dyn IOVic, palette: &Palette) -> FrameBuffer {
+      let fetch = |offset: u16| {
+         let addr = self.address.wrapping_add(offset);
+         mem.fetch_bank(addr, addr)
+      };
+
+      if self.is_sprite() {
+         let rows = BROWSE_SPRITES / SPRITE_COLUMNS;
+         let mut output = sprite_grid(rows);
+         let background = mem.read(BACKGROUND_COLOR_0) & 0x0F;
+         for n in 0..BROWSE_SPRITES {
+            let data: Vec<u8> = (0..63).map(|i| fetch((n * 64 + i) as u16)).collect();
+            let (x, y) = sprite_cell(n);
+            draw_sprite(&mut output, x, y, &data, self.is_multicolor(), |value| {
+               if value == 0 { background } else { sprite_color(mem, 0, value) }
+            }, palette);
+         }
+         output
+      } else {
+         // the color of the first screen cell stands in for the color RAM
+         let style = CellStyle {
+            mode: if self.is_multicolor() { 1 } else { 0 },
+            background: background_colors(mem),
+            palette: palette,
+         };
+         let c = ((mem.fetch_color(0) | if self.is_multicolor() { 0x08 } else { 0 }) as u16) << 8;
+         let mut output = FrameBuffer::new(128, 128);
+         for code in 0..256u16 {
+            let data: Vec<u8> = (0..8).map(|row| fetch(code * 8 + row)).collect();
+            let (x, y) = ((code as usize % 16) * 8, (code as usize / 16) * 8);
+            draw_cell(&mut output, x, y, &data, c | code, &style);
+         }
+         output
+      }
+   }
+}
+
+// the eight sprites with their own pointers, colors and multicolor flags
+fn render_sprites(mem: &dyn IOVic, palette: &Palette) -> FrameBuffer {
+   let mut output = sprite_grid(1);
+   let multicolor = mem.read(SPRITE_MULTY_COL);
+   let background = mem.read(BACKGROUND_COLOR_0) & 0x0F;
+
+   for n in 0..8 {
+      let pointer = (mem.fetch(Vic::video_matrix(mem) | 0x3F8 | n as u16) as u16) << 6;
+      let data: Vec<u8> = (0..63).map(|i| mem.fetch(pointer + i)).collect();
+      let (x, y) = sprite_cell(n);
+      draw_sprite(&mut output, x, y, &data, multicolor & (1 << n) != 0, |value| {
+         if value == 0 { background } else { sprite_color(mem, n, value) }
+      }, palette);
+   }
+   output
+}
+
+// the active character set in a 16x16 grid, colored like the top left screen cell
+fn render_charset(mem: &dyn IOVic, palette: &Palette) -> FrameBuffer {
+   let style = CellStyle {
+      mode: display_mode(mem) & 0x05,
+      background: background_colors(mem),
+      palette: palette,
+   };
+   let color = (mem.fetch_color(0) as u16) << 8;
+   let mut output = FrameBuffer::new(128, 128);
+
+   for code in 0..256u16 {
+      let fetch_code = if style.mode & 0x04 != 0 { code & 0x3F } else { code };
+      let data: Vec<u8> = (0..8).map(|row| mem.fetch(Vic::char_base(mem) | (fetch_code << 3) | row)).collect();
+      let (x, y) = ((code as usize % 16) * 8, (code as usize / 16) * 8);
+      draw_cell(&mut output, x, y, &data, color | code, &style);
+   }
+   output
+}
+
+// the 40x25 display decoded in the current mode, without border, scrolling or sprites
+fn render_screen(mem: &dyn IOVic, palette: &Palette) -> FrameBuffer {
+   let mode = display_mode(mem);
+   let style = CellStyle {
+      mode: mode,
+      background: background_colors(mem),
+      palette: palette,
+   };
+   let mut output = FrameBuffer::new(320, 200);
+
+   for vc in 0..1000u16 {
+      let code = mem.fetch(Vic::video_matrix(mem) | vc);
+      let c = ((mem.fetch_color(vc) as u16) << 8) | code as u16;
+      let data: Vec<u8> = (0..8).map(|rc| {
+         let addr = if mode & 0x02 != 0 {
+            Vic::bitmap_base(mem) | (vc << 3) | rc
+         } else {
+            let code = if mode & 0x04 != 0 { code & 0x3F } else { code };
+            Vic::char_base(mem) | ((code as u16) << 3) | rc
+         };
+         mem.fetch(addr)
+      }).collect();
+      let (x, y) = ((vc as usize % 40) * 8, (vc as usize / 40) * 8);
+      draw_cell(&mut output, x, y, &data, c, &style);
+   }
+   output
+}
+
+fn display_mode(mem: &dyn IOVic) -> u8 {
+   ((mem.read(Y_SCROLL) & 0x60) | (mem.read(X_SCROLL) & 0x10)) >> 4
+}
+
+// how the cells of a page are decoded: the display mode and the colors shared by all cells
+struct CellStyle<'a> {
+   mode: u8,
+   background: [u8; 4],
+   palette: &'a Palette,
+}
+
+// c is the color RAM nibble in the high byte and the character code in the low byte
+fn draw_cell(output: &mut FrameBuffer, x: usize, y: usize, data: &[u8], c: u16, style: &CellStyle) {
+   let multicolor = graphics_multicolor(style.mode, c);
+   for (row, byte) in data.iter().enumerate() {
+      for pixel in 0..8 {
+         let value = if multicolor {
+            (byte >> (6 - (pixel & 6))) & 3
+         } else {
+            (byte >> (7 - pixel)) & 1
+         };
+         let color = graphics_color(style.mode, multicolor, value, c, &style.background);
+         output.put_pixel(x + pixel, y + row, style.palette.color(color));
+      }
+   }
+}
+
+fn draw_sprite<F: Fn(u8) -> u8>(output: &mut FrameBuffer, x: usize, y: usize, data: &[u8], multicolor: bool, color: F, palette: &Palette) {
+   for row in 0..SPRITE_HEIGHT {
+      let bits = ((data[row * 3] as u32) << 16) | ((data[row * 3 + 1] as u32) << 8) | data[row * 3 + 2] as u32;
+      for pixel in 0..SPRITE_WIDTH {
+         let value = if multicolor {
+            ((bits >> (22 - (pixel & !1))) & 3) as u8
+         } else {
+            (((bits >> (23 - pixel)) & 1) << 1) as u8
+         };
+         output.put_pixel(x + pixel, y + row, palette.color(color(value)));
+      }
+   }
+}
+
+fn sprite_grid(rows: usize) -> FrameBuffer {
+   let mut output = FrameBuffer::new(SPRITE_COLUMNS * SPRITE_CELL_W, rows * SPRITE_CELL_H);
+   for y in 0..output.height() {
+      for x in 0..output.width() {
+         output.put_pixel(x, y, GRID_COLOR);
+      }
+   }
+   output
+}
+
+fn sprite_cell(n: usize) -> (usize, usize) {
+   let column = n % SPRITE_COLUMNS;
+   let row = n / SPRITE_COLUMNS;
+   (column * SPRITE_CELL_W + 2, row * SPRITE_CELL_H + 2)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::c64mem::*;
+
+   // a machine with the VIC in bank 1, the video matrix at $4400 and characters
+   // or the bitmap at $6000, white in the color RAM on a blue background
+   fn bank_1() -> (C64Mem, Palette) {
+      let mut mem = C64Mem::new();
+      mem.write_u8(0xDD02, 0x03);
+      mem.write_u8(0xDD00, 0x02);
+      mem.write_u8(MEM_PTR, 0x18);
+      mem.write_u8(BACKGROUND_COLOR_0, 6);
+      mem.write_u8(0xD800, 1);
+      (mem, Palette::new(PaletteKind::Pepto))
+   }
+
+   fn pixels(frame: &FrameBuffer, palette: &Palette, x: usize, y: usize, count: usize) -> Vec<u8> {
+      (x..x + count).map(|x| (0..16).find(|&color| palette.color(color) == frame.get_pixel(x, y)).unwrap()).collect()
+   }
+
+   #[test]
+   fn titles_name_the_addresses_in_the_bank() {
+      let (mem, _) = bank_1();
+      let mut view = GfxView::new();
+      assert_eq!(view.title(&mem), "Sprites (Bank 0x4000)");
+      view.next_page();
+      assert_eq!(view.title(&mem), "Charset 0x6000");
+      view.next_page();
+      assert_eq!(view.title(&mem), "Screen 0x4400");
+   }
+
+   #[test]
+   fn sprites_are_found_through_their_pointers() {
+      let (mut mem, palette) = bank_1();
+      mem.write_u8(COLOR_SPRITE_PTR, 5);
+      mem.write_u8(COLOR_SPRITE_PTR + 1, 7);
+      mem.write_u8(SPRITE_MULTY_COL0, 2);
+      mem.write_u8(SPRITE_MULTY_COL1, 3);
+      mem.write_u8(SPRITE_MULTY_COL, 0x02);
+      // sprite 0 at $4800, sprite 1 in multicolor at $4840
+      mem.ram[0x47F8] = 0x20;
+      mem.ram[0x47F9] = 0x21;
+      mem.ram[0x4800..0x4803].copy_from_slice(&[0x80, 0x00, 0x01]);
+      mem.ram[0x4840] = 0b01_10_11_00;
+
+      let frame = GfxView::new().render(&mem, &palette);
+      let (x, y) = sprite_cell(0);
+      assert_eq!(pixels(&frame, &palette, x, y, 2), [5, 6]);
+      assert_eq!(pixels(&frame, &palette, x + 22, y, 2), [6, 5]);
+      let (x, y) = sprite_cell(1);
+      assert_eq!(pixels(&frame, &palette, x, y, 8), [2, 2, 7, 7, 3, 3, 6, 6]);
+   }
+
+   #[test]
+   fn charset_is_read_from_the_character_base() {
+      let (mut mem, palette) = bank_1();
+      mem.ram[0x6008] = 0x81;
+      // the same character in the other banks isn't seen
+      mem.ram[0x2010] = 0xFF;
+      let mut view = GfxView::new();
+      view.page = ViewPage::Charset;
+      let frame = view.render(&mem, &palette);
+      assert_eq!(pixels(&frame, &palette, 8, 0, 8), [1, 6, 6, 6, 6, 6, 6, 1]);
+      assert_eq!(pixels(&frame, &palette, 16, 0, 8), [6; 8]);
+   }
+
+   #[test]
+   fn screen_decodes_text_and_bitmaps() {
+      let (mut mem, palette) = bank_1();
+      let mut view = GfxView::new();
+      view.page = ViewPage::Screen;
+      // the second cell shows character 1, whose data at $6008 is also that cell's bitmap
+      mem.ram[0x4401] = 0x01;
+      mem.ram[0x6008] = 0xF0;
+      mem.write_u8(0xD801, 1);
+      let frame = view.render(&mem, &palette);
+      assert_eq!(pixels(&frame, &palette, 8, 0, 8), [1, 1, 1, 1, 6, 6, 6, 6]);
+      // a bitmap is colored by its matrix byte
+      mem.write_u8(Y_SCROLL, 0x3B);
+      mem.ram[0x4401] = 0x2E;
+      let frame = view.render(&mem, &palette);
+      assert_eq!(pixels(&frame, &palette, 8, 0, 8), [2, 2, 2, 2, 14, 14, 14, 14]);
+   }
+
+   #[test]
+   fn memory_views_see_ram_or_the_character_rom_of_the_address_bank() {
+      let (mut mem, palette) = bank_1();
+      let mut rom = vec![0u8; 0x1000];
+      rom[0] = 0x80;
+      mem.load_rom(&rom, 0xD000);
+      mem.ram[0x5000] = 0x01;
+      // $5000 is RAM in bank 1, $9000 is the character ROM in bank 2
+      let frame = GfxView::from_spec("hires-char@5000").unwrap().render(&mem, &palette);
+      assert_eq!(pixels(&frame, &palette, 0, 0, 8), [6, 6, 6, 6, 6, 6, 6, 1]);
+      let frame = GfxView::from_spec("hires-char@$9000").unwrap().render(&mem, &palette);
+      assert_eq!(pixels(&frame, &palette, 0, 0, 8), [1, 6, 6, 6, 6, 6, 6, 6]);
+   }
+
+   #[test]
+   fn view_specs_parse() {
+      let view = GfxView::from_spec("multi-sprite@2000").unwrap();
+      assert_eq!((view.page, view.kind, view.address), (ViewPage::Memory, ViewKind::MultiSprite, 0x2000));
+      assert_eq!(GfxView::from_spec("screen").unwrap().page, ViewPage::Screen);
+      assert!(GfxView::from_spec("screen@0400").is_err());
+      assert!(GfxView::from_spec("hires-char").is_err());
+      assert!(GfxView::from_spec("petscii@0400").is_err());
+   }
+}
@@ -0,0 +1,214 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::*;
+use crate::emu::*;
+use crate::framebuffer::*;
+use crate::model::*;
+use crate::screenshot;
+
+const DIFF_COLOR: u32 = 0xFF0000;
+
+pub struct GoldenTest {
+   pub name: String,
+   pub program: String,
+   pub frames: u32,
+   pub until: Option<(u16, u8)>,
+   pub crop_border: bool,
+   pub model: VicModel,
+   pub cia_model: CiaModel,
+}
+
+// one test per line: name, program, maximum frames, then options
+//    text   text.prg   50   until=$02:$01 crop model=6567r8 cia=6526a
+pub fn parse_manifest(text: &str) -> Result<Vec<GoldenTest>, String> {
+   let mut tests = Vec::new();
+
+   for (number, line) in text.lines().enumerate() {
+      let line = line.split('#').next().unwrap_or("").trim();
+      if line.is_empty() {
+         continue;
+      }
+
+      let fields: Vec<&str> = line.split_whitespace().collect();
+      if fields.len() < 3 {
+         return Err(format!("Manifest Line {}: Expected Name, Program And Frames", number + 1));
+      }
+      let mut test = GoldenTest {
+         name: fields[0].to_string(),
+         program: fields[1].to_string(),
+         frames: fields[2].parse().map_err(|_| format!("Manifest Line {}: Bad Frame Count", number + 1))?,
+         until: None,
+         crop_border: false,
+         model: VicModel::Mos6569,
+         cia_model: CiaModel::Mos6526,
+      };
+
+      for option in &fields[3..] {
+         match option.split_once('=') {
+            Some(("until", value)) => test.until = Some(parse_condition(value)?),
+            Some(("model", value)) => test.model = VicModel::from_name(value)?,
+            Some(("cia", value))   => test.cia_model = CiaModel::from_name(value)?,
+            None if *option == "crop" => test.crop_border = true,
+            _                      => return Err(format!("Manifest Line {}: Unknown Option {}", number + 1, option))
+         }
+      }
+      tests.push(test);
+   }
+
+   Ok(tests)
+}
+
+// "$addr:$value", e.g. "$02:$01"
+fn parse_condition(text: &str) -> Result<(u16, u8), String> {
+   let (addr, value) = text.split_once(':').ok_or(format!("Bad Condition: {}", text))?;
+   let addr = u16::from_str_radix(addr.trim_start_matches('$'), 16)
+      .map_err(|_| format!("Bad Condition Address: {}", addr))?;
+   let value = u8::from_str_radix(value.trim_start_matches('$'), 16)
+      .map_err(|_| format!("Bad Condition Value: {}", value))?;
+   Ok((addr, value))
+}
+
+pub fn run_test(test: &GoldenTest, dir: &Path) -> Result<FrameBuffer, String> {
+   let mut config = Config::new();
+   config.model = test.model;
+   config.cia_models = [test.cia_model; 2];
+
+   let program = dir.join(&test.program);
+   let data = fs::read(&program).map_err(|e| format!("{}: {}", program.display(), e))?;
+
+   let mut emu = Emu::new(config);
+   let address = emu.load_prg(&data)?;
+   emu.start(address);
+
+   // once the condition holds, one more frame is drawn so the image is complete
+   let mut settled = test.until.is_none();
+   let mut condition_met = false;
+   for _ in 0..test.frames {
+      emu.run_frame()?;
+      if condition_met {
+         break;
+      }
+      if let Some((addr, value)) = test.until {
+         if emu.peek(addr) == value {
+            condition_met = true;
+            settled = true;
+         }
+      }
+   }
+
+   if !settled {
+      return Err(format!("Condition Not Met After {} Frames", test.frames));
+   }
+   Ok(emu.screen(test.crop_border))
+}
+
+// returns the number of differing pixels and an image marking them
+pub fn compare(actual: &FrameBuffer, expected: &FrameBuffer) -> (usize, FrameBuffer) {
+   let mut diff = FrameBuffer::new(actual.width(), actual.height());
+   let mut count = 0;
+
+   for y in 0..actual.height() {
+      for x in 0..actual.width() {
+         let pixel = actual.get_pixel(x, y);
+         if pixel != expected.get_pixel(x, y) {
+            diff.put_pixel(x, y, DIFF_COLOR);
+            count += 1;
+         } else {
+            // dimmed gray copy of the matching pixel
+            let gray = (((pixel >> 16) & 0xFF) + ((pixel >> 8) & 0xFF) + (pixel & 0xFF)) / 9;
+            diff.put_pixel(x, y, (gray << 16) | (gray << 8) | gray);
+         }
+      }
+   }
+
+   (count, diff)
+}
+
+fn output_path(dir: &Path, name: &str, suffix: &str) -> PathBuf {
+   dir.join(format!("{}{}", name, suffix))
+}
+
+// runs every test in the manifest, returns whether all of them passed
+pub fn run_suite(manifest: &str, update: bool) -> Result<bool, String> {
+   let text = fs::read_to_string(manifest).map_err(|e| format!("{}: {}", manifest, e))?;
+   let tests = parse_manifest(&text)?;
+   let dir = Path::new(manifest).parent().unwrap_or(Path::new("."));
+   let mut failures = 0;
+
+   for test in &tests {
+      let golden = output_path(dir, &test.name, ".ppm");
+      let diff_file = output_path(dir, &test.name, ".diff.png");
+      let actual_file = output_path(dir, &test.name, ".actual.png");
+
+      let actual = match run_test(test, dir) {
+         Ok(actual) => actual,
+         Err(e)     => {
+            println!("FAIL {}: {}", test.name, e);
+            failures += 1;
+            continue;
+         }
+      };
+
+      if update {
+         fs::write(&golden, screenshot::encode_ppm(&actual))
+            .map_err(|e| format!("{}: {}", golden.display(), e))?;
+         println!("UPDATED {}", test.name);
+         continue;
+      }
+
+      let expected = match screenshot::load_ppm(&golden.to_string_lossy()) {
+         Ok(expected) => expected,
+         Err(e)       => {
+            println!("FAIL {}: {}", test.name, e);
+            failures += 1;
+            continue;
+         }
+      };
+
+      if expected.width() != actual.width() || expected.height() != actual.height() {
+         println!("FAIL {}: size {}x{}, golden is {}x{}", test.name,
+            actual.width(), actual.height(), expected.width(), expected.height());
+         failures += 1;
+         continue;
+      }
+
+      let (count, diff) = compare(&actual, &expected);
+      if count == 0 {
+         let _ = fs::remove_file(&diff_file);
+         let _ = fs::remove_file(&actual_file);
+         println!("PASS {}", test.name);
+      } else {
+         screenshot::save(&diff, &diff_file.to_string_lossy())?;
+         screenshot::save(&actual, &actual_file.to_string_lossy())?;
+         println!("FAIL {}: {} pixels differ, see {}", test.name, count, diff_file.display());
+         failures += 1;
+      }
+   }
+
+   println!("{} of {} golden tests passed", tests.len() - failures, tests.len());
+   Ok(failures == 0)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn golden_images_match() {
+      let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/goldens.txt");
+      assert_eq!(run_suite(manifest, false), Ok(true));
+   }
+
+   #[test]
+   fn manifest_options_are_parsed() {
+      let tests = parse_manifest("# comment\nntsc  text.prg  50  until=$02:$01 crop model=6567r8 cia=6526a\n").unwrap();
+      assert_eq!(tests.len(), 1);
+      assert_eq!(tests[0].until, Some((0x02, 0x01)));
+      assert!(tests[0].crop_border);
+      assert_eq!(tests[0].model, VicModel::Mos6567R8);
+      assert_eq!(tests[0].cia_model, CiaModel::Mos6526A);
+      assert!(parse_manifest("text text.prg").is_err());
+      assert!(parse_manifest("text text.prg 50 speed=2").is_err());
+   }
+}
@@ -0,0 +1,190 @@
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::keyboard::Scancode;
+use sdl2::GameControllerSubsystem;
+
+use crate::controlport::*;
+use crate::emu::*;
+use crate::joystick::*;
+
+// how far a stick has to move to count as a direction
+const AXIS_THRESHOLD  : i16 = 16384;
+
+struct Pad {
+   controller: GameController,
+   buttons: u8,
+   axes: u8,
+}
+
+// drives the two control ports from host keys, SDL game controllers and the mouse. The
+// first controller goes to port 2 like the keys, the second to port 1; swapping exchanges
+// the ports of the joysticks. On a port with paddles a controller turns them with its
+// sticks, and the mouse works the first port with paddles or a 1351
+pub struct JoyMap {
+   keys: [Vec<(Scancode, u8)>; 2],
+   key_lines: [u8; 2],
+   pads: Vec<Pad>,
+   devices: [ControlDevice; 2],
+   swapped: bool,
+}
+
+impl JoyMap {
+   // keys are SDL key names for up, down, left, right and fire of each port
+   pub fn new(keys: &[Vec<String>; 2], devices: [ControlDevice; 2]) -> Result<Self, String> {
+      let mut joy_map = Self {
+         keys: [Vec::new(), Vec::new()],
+         key_lines: [0; 2],
+         pads: Vec::new(),
+         devices: devices,
+         swapped: false,
+      };
+      for (port, names) in keys.iter().enumerate() {
+         for (name, line) in names.iter().zip([JOY_UP, JOY_DOWN, JOY_LEFT, JOY_RIGHT, JOY_FIRE]) {
+            let scancode = Scancode::from_name(name).ok_or(format!("Unknown Key: {}", name))?;
+            joy_map.keys[port].push((scancode, line));
+         }
+      }
+      Ok(joy_map)
+   }
+
+   // returns whether the key belongs to a joystick, then it doesn't reach the keyboard
+   pub fn key(&mut self, emu: &mut Emu, scancode: Scancode, pressed: bool) -> bool {
+      let mut used = false;
+      for port in 0..2 {
+         for &(key, line) in &self.keys[port] {
+            if key == scancode {
+               if pressed { self.key_lines[port] |= line; } else { self.key_lines[port] &= !line; }
+               used = true;
+            }
+         }
+      }
+      if used {
+         self.update(emu);
+      }
+      used
+   }
+
+   // index is the SDL joystick device index
+   pub fn add_controller(&mut self, subsystem: &GameControllerSubsystem, index: u32) -> Result<(), String> {
+      if !subsystem.is_game_controller(index) {
+         return Ok(());
+      }
+      let controller = subsystem.open(index).map_err(|e| format!("Game Controller {}: {}", index, e))?;
+      if self.pads.iter().all(|pad| pad.controller.instance_id() != controller.instance_id()) {
+         self.pads.push(Pad {
+            controller: controller,
+            buttons: 0,
+            axes: 0,
+         });
+      }
+      Ok(())
+   }
+
+   pub fn remove_controller(&mut self, emu: &mut Emu, instance_id: u32) {
+      self.pads.retain(|pad| pad.controller.instance_id() != instance_id);
+      self.update(emu);
+   }
+
+   pub fn button(&mut self, emu: &mut Emu, instance_id: u32, button: Button, pressed: bool) {
+      if let Some(port) = self.paddle_port(instance_id) {
+         match button {
+            Button::A | Button::X => emu.set_port_button(port, 0, pressed),
+            Button::B | Button::Y => emu.set_port_button(port, 1, pressed),
+            _                     => {}
+         }
+         return;
+      }
+      let line = match button {
+         Button::DPadUp    => JOY_UP,
+         Button::DPadDown  => JOY_DOWN,
+         Button::DPadLeft  => JOY_LEFT,
+         Button::DPadRight => JOY_RIGHT,
+         Button::A | Button::B | Button::X | Button::Y => JOY_FIRE,
+         _                 => return
+      };
+      if let Some(pad) = self.pads.iter_mut().find(|pad| pad.controller.instance_id() == instance_id) {
+         if pressed { pad.buttons |= line; } else { pad.buttons &= !line; }
+         self.update(emu);
+      }
+   }
+
+   // the left stick, or for paddles the horizontal position of the left and right stick
+   pub fn axis(&mut self, emu: &mut Emu, instance_id: u32, axis: Axis, value: i16) {
+      if let Some(port) = self.paddle_port(instance_id) {
+         // turning a paddle clockwise lowers its count
+         let position = (0x7FFF - value as i32) >> 8;
+         match axis {
+            Axis::LeftX  => emu.set_paddle(port, 0, position as u8),
+            Axis::RightX => emu.set_paddle(port, 1, position as u8),
+            _            => {}
+         }
+         return;
+      }
+      let (negative, positive) = match axis {
+         Axis::LeftX => (JOY_LEFT, JOY_RIGHT),
+         Axis::LeftY => (JOY_UP, JOY_DOWN),
+         _           => return
+      };
+      if let Some(pad) = self.pads.iter_mut().find(|pad| pad.controller.instance_id() == instance_id) {
+         pad.axes &= !(negative | positive);
+         if value <= -AXIS_THRESHOLD {
+            pad.axes |= negative;
+         } else if value >= AXIS_THRESHOLD {
+            pad.axes |= positive;
+         }
+         self.update(emu);
+      }
+   }
+
+   // the port the mouse works, if any
+   pub fn mouse_port(&self) -> Option<usize> {
+      self.devices.iter().position(|&device| device != ControlDevice::Joystick)
+   }
+
+   pub fn mouse_motion(&mut self, emu: &mut Emu, dx: i32, dy: i32) {
+      match self.mouse_port() {
+         Some(port) if self.devices[port] == ControlDevice::Mouse => emu.move_mouse(port, dx, -dy),
+         Some(port)                                                => {
+            emu.move_paddle(port, 0, -dx);
+            emu.move_paddle(port, 1, -dy);
+         },
+         None                                                      => {}
+      }
+   }
+
+   // button 0 is the left mouse button, 1 the right one
+   pub fn mouse_button(&mut self, emu: &mut Emu, button: usize, pressed: bool) {
+      if let Some(port) = self.mouse_port() {
+         emu.set_port_button(port, button, pressed);
+      }
+   }
+
+   // returns whether the ports are now swapped
+   pub fn swap(&mut self, emu: &mut Emu) -> bool {
+      self.swapped = !self.swapped;
+      self.update(emu);
+      self.swapped
+   }
+
+   fn pad_port(&self, index: usize) -> usize {
+      (1 - index % 2) ^ self.swapped as usize
+   }
+
+   fn paddle_port(&self, instance_id: u32) -> Option<usize> {
+      let index = self.pads.iter().position(|pad| pad.controller.instance_id() == instance_id)?;
+      let port = self.pad_port(index);
+      if self.devices[port] == ControlDevice::Paddles { Some(port) } else { None }
+   }
+
+   fn update(&self, emu: &mut Emu) {
+      let mut lines = self.key_lines;
+      if self.swapped {
+         lines.swap(0, 1);
+      }
+      for (index, pad) in self.pads.iter().enumerate() {
+         lines[self.pad_port(index)] |= pad.buttons | pad.axes;
+      }
+      for (port, &lines) in lines.iter().enumerate() {
+         emu.set_joystick(port, lines);
+      }
+   }
+}
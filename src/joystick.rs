@@ -0,0 +1,100 @@
+// lines of a digital joystick, pulled low on the CIA 1 port while active
+pub const JOY_UP      : u8 = 0x01;
+pub const JOY_DOWN    : u8 = 0x02;
+pub const JOY_LEFT    : u8 = 0x04;
+pub const JOY_RIGHT   : u8 = 0x08;
+pub const JOY_FIRE    : u8 = 0x10;
+
+// presses per second when autofire is switched on without a rate
+pub const DEFAULT_AUTOFIRE : f32 = 10.0;
+
+pub struct Joystick {
+   // lines held by the host
+   held: u8,
+   // fire presses per second while fire is held
+   autofire: Option<f32>,
+   // frames since fire went down
+   fire_frames: u32,
+}
+
+impl Joystick {
+   pub fn new(autofire: Option<f32>) -> Self {
+      Self {
+         held: 0,
+         autofire: autofire,
+         fire_frames: 0,
+      }
+   }
+
+   pub fn set(&mut self, lines: u8) {
+      if lines & JOY_FIRE != 0 && self.held & JOY_FIRE == 0 {
+         self.fire_frames = 0;
+      }
+      self.held = lines;
+   }
+
+   pub fn set_autofire(&mut self, autofire: Option<f32>) {
+      self.autofire = autofire;
+      self.fire_frames = 0;
+   }
+
+   pub fn end_frame(&mut self) {
+      self.fire_frames = self.fire_frames.wrapping_add(1);
+   }
+
+   // the lines as the port sees them; autofire presses fire for the first half of each period
+   pub fn lines(&self, frame_rate: f64) -> u8 {
+      match self.autofire {
+         Some(rate) if self.held & JOY_FIRE != 0 => {
+            let phase = self.fire_frames as f64 * rate as f64 / frame_rate;
+            if phase.fract() < 0.5 { self.held } else { self.held & !JOY_FIRE }
+         },
+         _ => self.held
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // whether fire reaches the port in each of the next frames
+   fn fire_trace(joystick: &mut Joystick, frames: usize) -> Vec<bool> {
+      (0..frames).map(|_| {
+         let fire = joystick.lines(50.0) & JOY_FIRE != 0;
+         joystick.end_frame();
+         fire
+      }).collect()
+   }
+
+   #[test]
+   fn fire_is_held_without_autofire() {
+      let mut joystick = Joystick::new(None);
+      joystick.set(JOY_FIRE | JOY_LEFT);
+      assert!(fire_trace(&mut joystick, 20).iter().all(|&fire| fire));
+      assert_eq!(joystick.lines(50.0), JOY_FIRE | JOY_LEFT);
+   }
+
+   #[test]
+   fn autofire_presses_for_the_first_half_of_each_period() {
+      // 10 Hz at 50 frames per second is a press every 5 frames
+      let mut joystick = Joystick::new(Some(10.0));
+      joystick.set(JOY_FIRE | JOY_UP);
+      assert_eq!(fire_trace(&mut joystick, 10),
+                 vec![true, true, true, false, false, true, true, true, false, false]);
+      // the directions are not touched
+      fire_trace(&mut joystick, 3);
+      assert_eq!(joystick.lines(50.0), JOY_UP);
+   }
+
+   #[test]
+   fn autofire_restarts_with_each_press() {
+      let mut joystick = Joystick::new(Some(10.0));
+      joystick.set(JOY_FIRE);
+      fire_trace(&mut joystick, 4);
+      joystick.set(0);
+      assert_eq!(joystick.lines(50.0), 0);
+      joystick.set(JOY_FIRE);
+      assert_eq!(fire_trace(&mut joystick, 4), vec![true, true, true, false]);
+   }
+}
@@ -0,0 +1,222 @@
+// C64 keys in matrix order: the key at index n sits on port A line n / 8 and port B
+// line n % 8 of CIA 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum C64Key {
+   InstDel, Return, CursorRight, F7, F1, F3, F5, CursorDown,
+   Num3, W, A, Num4, Z, S, E, LeftShift,
+   Num5, R, D, Num6, C, F, T, X,
+   Num7, Y, G, Num8, B, H, U, V,
+   Num9, I, J, Num0, M, K, O, N,
+   Plus, P, L, Minus, Period, Colon, At, Comma,
+   Pound, Asterisk, Semicolon, ClrHome, RightShift, Equals, UpArrow, Slash,
+   Num1, LeftArrow, Ctrl, Num2, Space, Commodore, Q, RunStop,
+}
+
+impl C64Key {
+   // (port A line, port B line)
+   pub fn position(&self) -> (usize, usize) {
+      let index = *self as usize;
+      (index / 8, index % 8)
+   }
+}
+
+pub struct Keyboard {
+   // pressed keys, one byte of port B lines per port A line
+   matrix: [u8; 8],
+   // SHIFT LOCK latches the left SHIFT down
+   shift_lock: bool,
+   // forces the SHIFT keys up or down while a symbolically mapped key needs it
+   shift_override: Option<bool>,
+   // active lines of the joysticks in control port 1 (on port B) and 2 (on port A)
+   joysticks: [u8; 2],
+}
+
+impl Keyboard {
+   pub fn new() -> Self {
+      Self {
+         matrix: [0; 8],
+         shift_lock: false,
+         shift_override: None,
+         joysticks: [0; 2],
+      }
+   }
+
+   pub fn set_key(&mut self, key: C64Key, pressed: bool) {
+      let (a, b) = key.position();
+      if pressed {
+         self.matrix[a] |= 1 << b;
+      } else {
+         self.matrix[a] &= !(1 << b);
+      }
+   }
+
+   pub fn toggle_shift_lock(&mut self) {
+      self.shift_lock = !self.shift_lock;
+   }
+
+   pub fn set_shift_override(&mut self, shift: Option<bool>) {
+      self.shift_override = shift;
+   }
+
+   // port is 0 for control port 1, 1 for control port 2
+   pub fn set_joystick(&mut self, port: usize, lines: u8) {
+      self.joysticks[port] = lines;
+   }
+
+   fn pressed(&self) -> [u8; 8] {
+      let mut matrix = self.matrix;
+      if self.shift_lock {
+         let (a, b) = C64Key::LeftShift.position();
+         matrix[a] |= 1 << b;
+      }
+      if let Some(shift) = self.shift_override {
+         for key in [C64Key::LeftShift, C64Key::RightShift] {
+            let (a, b) = key.position();
+            matrix[a] &= !(1 << b);
+         }
+         if shift {
+            let (a, b) = C64Key::LeftShift.position();
+            matrix[a] |= 1 << b;
+         }
+      }
+      matrix
+   }
+
+   // the levels on port A and B given what CIA 1 drives onto them. A pressed key connects
+   // its two lines, so a low line pulls down everything it is connected to through any
+   // number of keys: with three corners of a rectangle pressed the fourth reads as pressed.
+   // The joysticks pull their lines low the same way, so joystick 1 reads as keys and keys
+   // held while joystick 2 is moved read on the wrong rows
+   pub fn scan(&self, port_a: u8, port_b: u8) -> (u8, u8) {
+      let matrix = self.pressed();
+      let (mut low_a, mut low_b) = (!port_a | self.joysticks[1], !port_b | self.joysticks[0]);
+      loop {
+         let (mut next_a, mut next_b) = (low_a, low_b);
+         for (a, &lines) in matrix.iter().enumerate() {
+            if low_a & (1 << a) != 0 {
+               next_b |= lines;
+            }
+            if low_b & lines != 0 {
+               next_a |= 1 << a;
+            }
+         }
+         if next_a == low_a && next_b == low_b {
+            return (!low_a, !low_b);
+         }
+         low_a = next_a;
+         low_b = next_b;
+      }
+   }
+}
+
+// how host keys translate to C64 keys
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyMapping {
+   // by position on the PC keyboard, SHIFT works as on the C64
+   Positional,
+   // by the character on the key, so SHIFT+' types " as SHIFT+2 does on the C64
+   Symbolic,
+}
+
+impl KeyMapping {
+   pub fn from_name(name: &str) -> Result<KeyMapping, String> {
+      match name.to_lowercase().as_str() {
+         "positional" => Ok(KeyMapping::Positional),
+         "symbolic"   => Ok(KeyMapping::Symbolic),
+         _            => Err(format!("Unknown Key Mapping: {}", name))
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::joystick::*;
+
+   // port B as the KERNAL reads it with only port A line a driven low
+   fn column(keyboard: &Keyboard, a: usize) -> u8 {
+      keyboard.scan(!(1 << a), 0xFF).1
+   }
+
+   fn press(keys: &[C64Key]) -> Keyboard {
+      let mut keyboard = Keyboard::new();
+      for &key in keys {
+         keyboard.set_key(key, true);
+      }
+      keyboard
+   }
+
+   #[test]
+   fn key_connects_its_lines() {
+      let mut keyboard = press(&[C64Key::A]);
+      assert_eq!(column(&keyboard, 1), !(1 << 2));
+      assert_eq!(column(&keyboard, 2), 0xFF);
+      keyboard.set_key(C64Key::A, false);
+      assert_eq!(column(&keyboard, 1), 0xFF);
+   }
+
+   #[test]
+   fn two_keys_do_not_ghost() {
+      // A and D share port B line 2
+      let keyboard = press(&[C64Key::A, C64Key::D]);
+      assert_eq!(column(&keyboard, 1), !(1 << 2));
+      assert_eq!(column(&keyboard, 2), !(1 << 2));
+   }
+
+   #[test]
+   fn three_corners_of_a_rectangle_ghost_the_fourth() {
+      // A, S and D hold three corners of the rectangle A S / D F
+      let keyboard = press(&[C64Key::A, C64Key::S, C64Key::D]);
+      assert_eq!(column(&keyboard, 1), !(1 << 2 | 1 << 5));
+      assert_eq!(column(&keyboard, 2), !(1 << 2 | 1 << 5));
+      assert_eq!(C64Key::F.position(), (2, 5));
+      // other rows stay clear
+      assert_eq!(column(&keyboard, 3), 0xFF);
+   }
+
+   #[test]
+   fn shift_lock_and_override_work_the_shift_keys() {
+      let mut keyboard = press(&[C64Key::RightShift]);
+      keyboard.toggle_shift_lock();
+      assert_eq!(column(&keyboard, 1), !(1 << 7));
+      assert_eq!(column(&keyboard, 6), !(1 << 4));
+      keyboard.set_shift_override(Some(false));
+      assert_eq!(column(&keyboard, 1), 0xFF);
+      assert_eq!(column(&keyboard, 6), 0xFF);
+      keyboard.toggle_shift_lock();
+      keyboard.set_shift_override(Some(true));
+      assert_eq!(column(&keyboard, 1), !(1 << 7));
+      assert_eq!(column(&keyboard, 6), 0xFF);
+   }
+
+   #[test]
+   fn joystick_1_reads_as_keys_in_every_column() {
+      // fire in control port 1 pulls port B line 4 low, the line of SPACE in column 7
+      let mut keyboard = Keyboard::new();
+      keyboard.set_joystick(0, JOY_FIRE);
+      for a in 0..8 {
+         assert_eq!(column(&keyboard, a), !(1 << 4));
+      }
+      assert_eq!(C64Key::Space.position(), (7, 4));
+      // and with no column driven at all
+      assert_eq!(keyboard.scan(0xFF, 0xFF).1, !(1 << 4));
+   }
+
+   #[test]
+   fn joystick_1_through_a_held_key_pulls_its_column() {
+      // left pulls port B line 2 low, which reaches port A line 1 through A
+      let mut keyboard = press(&[C64Key::A]);
+      keyboard.set_joystick(0, JOY_LEFT);
+      assert_eq!(keyboard.scan(0xFF, 0xFF).0, !(1 << 1));
+   }
+
+   #[test]
+   fn joystick_2_moves_held_keys_to_other_columns() {
+      // up in control port 2 holds port A line 0 low, so INST/DEL reads in every column
+      let mut keyboard = press(&[C64Key::InstDel]);
+      keyboard.set_joystick(1, JOY_UP);
+      assert_eq!(column(&keyboard, 3), !(1 << 0));
+      keyboard.set_joystick(1, 0);
+      assert_eq!(column(&keyboard, 3), 0xFF);
+   }
+}
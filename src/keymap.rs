@@ -0,0 +1,277 @@
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+
+use crate::emu::*;
+use crate::keyboard::*;
+
+// translates SDL key events into the C64 keyboard matrix. A key that needs SHIFT
+// different from the host, e.g. " which is SHIFT+2 on the C64 but SHIFT+' on a PC,
+// forces SHIFT while it is held
+pub struct KeyMap {
+   mapping: KeyMapping,
+   held: Vec<(Scancode, C64Key, Option<bool>)>,
+}
+
+impl KeyMap {
+   pub fn new(mapping: KeyMapping) -> Self {
+      Self {
+         mapping: mapping,
+         held: Vec::new(),
+      }
+   }
+
+   // returns whether the key means anything to the C64
+   pub fn key_down(&mut self, emu: &mut Emu, scancode: Scancode, keycode: Option<Keycode>, keymod: Mod) -> bool {
+      match scancode {
+         Scancode::CapsLock => {
+            emu.toggle_shift_lock();
+            return true;
+         },
+         Scancode::PageUp => {
+            emu.set_restore(true);
+            return true;
+         },
+         _ => {}
+      }
+
+      let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+      let key = match common_key(scancode) {
+         Some(key) => Some(key),
+         None      => match self.mapping {
+            KeyMapping::Positional => positional_key(scancode).map(|key| (key, None)),
+            KeyMapping::Symbolic   => keycode.and_then(|keycode| symbolic_key(keycode, shift)),
+         }
+      };
+      let (key, shift) = match key {
+         Some(key) => key,
+         None      => return false
+      };
+
+      if !self.held.iter().any(|(held, _, _)| *held == scancode) {
+         self.held.push((scancode, key, shift));
+      }
+      emu.set_key(key, true);
+      self.update_shift(emu);
+      true
+   }
+
+   pub fn key_up(&mut self, emu: &mut Emu, scancode: Scancode) -> bool {
+      if scancode == Scancode::PageUp {
+         emu.set_restore(false);
+         return true;
+      }
+
+      let index = match self.held.iter().position(|(held, _, _)| *held == scancode) {
+         Some(index) => index,
+         None        => return false
+      };
+      let (_, key, _) = self.held.remove(index);
+      // another host key may still hold the same C64 key, e.g. LEFT and RIGHT both hold CRSR
+      if !self.held.iter().any(|(_, held, _)| *held == key) {
+         emu.set_key(key, false);
+      }
+      self.update_shift(emu);
+      true
+   }
+
+   // the most recently pressed key decides
+   fn update_shift(&self, emu: &mut Emu) {
+      let shift = self.held.iter().rev().find_map(|(_, _, shift)| *shift);
+      emu.set_shift_override(shift);
+   }
+}
+
+// keys that are the same in both mappings
+fn common_key(scancode: Scancode) -> Option<(C64Key, Option<bool>)> {
+   let key = match scancode {
+      Scancode::Return    => (C64Key::Return, None),
+      Scancode::Space     => (C64Key::Space, None),
+      Scancode::Backspace => (C64Key::InstDel, None),
+      Scancode::Home      => (C64Key::ClrHome, None),
+      Scancode::End       => (C64Key::RunStop, None),
+      Scancode::Tab       => (C64Key::Ctrl, None),
+      Scancode::LCtrl     => (C64Key::Commodore, None),
+      Scancode::LShift    => (C64Key::LeftShift, None),
+      Scancode::RShift    => (C64Key::RightShift, None),
+      Scancode::Right     => (C64Key::CursorRight, None),
+      Scancode::Down      => (C64Key::CursorDown, None),
+      Scancode::Left      => (C64Key::CursorRight, Some(true)),
+      Scancode::Up        => (C64Key::CursorDown, Some(true)),
+      Scancode::F1        => (C64Key::F1, None),
+      Scancode::F2        => (C64Key::F3, None),
+      Scancode::F3        => (C64Key::F5, None),
+      Scancode::F4        => (C64Key::F7, None),
+      _                   => return None
+   };
+   Some(key)
+}
+
+// the C64 key in the same place on a US PC keyboard
+fn positional_key(scancode: Scancode) -> Option<C64Key> {
+   let key = match scancode {
+      Scancode::Grave        => C64Key::LeftArrow,
+      Scancode::Num1         => C64Key::Num1,
+      Scancode::Num2         => C64Key::Num2,
+      Scancode::Num3         => C64Key::Num3,
+      Scancode::Num4         => C64Key::Num4,
+      Scancode::Num5         => C64Key::Num5,
+      Scancode::Num6         => C64Key::Num6,
+      Scancode::Num7         => C64Key::Num7,
+      Scancode::Num8         => C64Key::Num8,
+      Scancode::Num9         => C64Key::Num9,
+      Scancode::Num0         => C64Key::Num0,
+      Scancode::Minus        => C64Key::Plus,
+      Scancode::Equals       => C64Key::Minus,
+      Scancode::Insert       => C64Key::Pound,
+      Scancode::LeftBracket  => C64Key::At,
+      Scancode::RightBracket => C64Key::Asterisk,
+      Scancode::Delete       => C64Key::UpArrow,
+      Scancode::Semicolon    => C64Key::Colon,
+      Scancode::Apostrophe   => C64Key::Semicolon,
+      Scancode::Backslash    => C64Key::Equals,
+      Scancode::Comma        => C64Key::Comma,
+      Scancode::Period       => C64Key::Period,
+      Scancode::Slash        => C64Key::Slash,
+      Scancode::A            => C64Key::A,
+      Scancode::B            => C64Key::B,
+      Scancode::C            => C64Key::C,
+      Scancode::D            => C64Key::D,
+      Scancode::E            => C64Key::E,
+      Scancode::F            => C64Key::F,
+      Scancode::G            => C64Key::G,
+      Scancode::H            => C64Key::H,
+      Scancode::I            => C64Key::I,
+      Scancode::J            => C64Key::J,
+      Scancode::K            => C64Key::K,
+      Scancode::L            => C64Key::L,
+      Scancode::M            => C64Key::M,
+      Scancode::N            => C64Key::N,
+      Scancode::O            => C64Key::O,
+      Scancode::P            => C64Key::P,
+      Scancode::Q            => C64Key::Q,
+      Scancode::R            => C64Key::R,
+      Scancode::S            => C64Key::S,
+      Scancode::T            => C64Key::T,
+      Scancode::U            => C64Key::U,
+      Scancode::V            => C64Key::V,
+      Scancode::W            => C64Key::W,
+      Scancode::X            => C64Key::X,
+      Scancode::Y            => C64Key::Y,
+      Scancode::Z            => C64Key::Z,
+      _                      => return None
+   };
+   Some(key)
+}
+
+// the C64 key and SHIFT that type the character on the host key, for a US layout.
+// Characters the C64 types the same way keep the host SHIFT
+fn symbolic_key(keycode: Keycode, shift: bool) -> Option<(C64Key, Option<bool>)> {
+   let key = match (keycode, shift) {
+      (Keycode::Num1, _)             => (C64Key::Num1, None),
+      (Keycode::Num2, false)         => (C64Key::Num2, None),
+      (Keycode::Num2, true)          => (C64Key::At, Some(false)),
+      (Keycode::Num3, _)             => (C64Key::Num3, None),
+      (Keycode::Num4, _)             => (C64Key::Num4, None),
+      (Keycode::Num5, _)             => (C64Key::Num5, None),
+      (Keycode::Num6, false)         => (C64Key::Num6, None),
+      (Keycode::Num6, true)          => (C64Key::UpArrow, Some(false)),
+      (Keycode::Num7, false)         => (C64Key::Num7, None),
+      (Keycode::Num7, true)          => (C64Key::Num6, Some(true)),
+      (Keycode::Num8, false)         => (C64Key::Num8, None),
+      (Keycode::Num8, true)          => (C64Key::Asterisk, Some(false)),
+      (Keycode::Num9, false)         => (C64Key::Num9, None),
+      (Keycode::Num9, true)          => (C64Key::Num8, Some(true)),
+      (Keycode::Num0, false)         => (C64Key::Num0, None),
+      (Keycode::Num0, true)          => (C64Key::Num9, Some(true)),
+      (Keycode::Minus, false)        => (C64Key::Minus, Some(false)),
+      (Keycode::Minus, true)         => (C64Key::LeftArrow, Some(false)),
+      (Keycode::Equals, false)       => (C64Key::Equals, Some(false)),
+      (Keycode::Equals, true)        => (C64Key::Plus, Some(false)),
+      (Keycode::Quote, false)        => (C64Key::Num7, Some(true)),
+      (Keycode::Quote, true)         => (C64Key::Num2, Some(true)),
+      (Keycode::Semicolon, false)    => (C64Key::Semicolon, Some(false)),
+      (Keycode::Semicolon, true)     => (C64Key::Colon, Some(false)),
+      (Keycode::LeftBracket, _)      => (C64Key::Colon, Some(true)),
+      (Keycode::RightBracket, _)     => (C64Key::Semicolon, Some(true)),
+      (Keycode::Backslash, _)        => (C64Key::Pound, Some(false)),
+      (Keycode::Backquote, _)        => (C64Key::LeftArrow, Some(false)),
+      (Keycode::Insert, _)           => (C64Key::InstDel, Some(true)),
+      (Keycode::Delete, _)           => (C64Key::InstDel, Some(false)),
+      (Keycode::Comma, _)            => (C64Key::Comma, None),
+      (Keycode::Period, _)           => (C64Key::Period, None),
+      (Keycode::Slash, _)            => (C64Key::Slash, None),
+      (Keycode::Plus, _)             => (C64Key::Plus, Some(false)),
+      (Keycode::Asterisk, _)         => (C64Key::Asterisk, Some(false)),
+      (Keycode::At, _)               => (C64Key::At, Some(false)),
+      (Keycode::Colon, _)            => (C64Key::Colon, Some(false)),
+      _                              => return letter_key(keycode).map(|key| (key, None))
+   };
+   Some(key)
+}
+
+fn letter_key(keycode: Keycode) -> Option<C64Key> {
+   let key = match keycode {
+      Keycode::A => C64Key::A,
+      Keycode::B => C64Key::B,
+      Keycode::C => C64Key::C,
+      Keycode::D => C64Key::D,
+      Keycode::E => C64Key::E,
+      Keycode::F => C64Key::F,
+      Keycode::G => C64Key::G,
+      Keycode::H => C64Key::H,
+      Keycode::I => C64Key::I,
+      Keycode::J => C64Key::J,
+      Keycode::K => C64Key::K,
+      Keycode::L => C64Key::L,
+      Keycode::M => C64Key::M,
+      Keycode::N => C64Key::N,
+      Keycode::O => C64Key::O,
+      Keycode::P => C64Key::P,
+      Keycode::Q => C64Key::Q,
+      Keycode::R => C64Key::R,
+      Keycode::S => C64Key::S,
+      Keycode::T => C64Key::T,
+      Keycode::U => C64Key::U,
+      Keycode::V => C64Key::V,
+      Keycode::W => C64Key::W,
+      Keycode::X => C64Key::X,
+      Keycode::Y => C64Key::Y,
+      Keycode::Z => C64Key::Z,
+      _          => return None
+   };
+   Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn positional_keys_follow_the_key_position() {
+      assert_eq!(positional_key(Scancode::Minus), Some(C64Key::Plus));
+      assert_eq!(positional_key(Scancode::Equals), Some(C64Key::Minus));
+      assert_eq!(positional_key(Scancode::Semicolon), Some(C64Key::Colon));
+      assert_eq!(positional_key(Scancode::Apostrophe), Some(C64Key::Semicolon));
+      assert_eq!(positional_key(Scancode::Backslash), Some(C64Key::Equals));
+      assert_eq!(positional_key(Scancode::A), Some(C64Key::A));
+      assert_eq!(positional_key(Scancode::M), Some(C64Key::M));
+      assert_eq!(positional_key(Scancode::Z), Some(C64Key::Z));
+   }
+
+   #[test]
+   fn symbolic_keys_type_the_host_character() {
+      // SHIFT+' is " which is SHIFT+2 on the C64
+      assert_eq!(symbolic_key(Keycode::Quote, true), Some((C64Key::Num2, Some(true))));
+      // SHIFT+2 is @ which has its own unshifted key
+      assert_eq!(symbolic_key(Keycode::Num2, true), Some((C64Key::At, Some(false))));
+      assert_eq!(symbolic_key(Keycode::Num2, false), Some((C64Key::Num2, None)));
+      assert_eq!(symbolic_key(Keycode::Num9, true), Some((C64Key::Num8, Some(true))));
+      assert_eq!(symbolic_key(Keycode::Semicolon, true), Some((C64Key::Colon, Some(false))));
+   }
+
+   #[test]
+   fn cursor_left_and_up_are_shifted_cursor_keys() {
+      assert_eq!(common_key(Scancode::Left), Some((C64Key::CursorRight, Some(true))));
+      assert_eq!(common_key(Scancode::Up), Some((C64Key::CursorDown, Some(true))));
+      assert_eq!(common_key(Scancode::Right), Some((C64Key::CursorRight, None)));
+   }
+}
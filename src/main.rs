@@ -1,88 +1,167 @@
 extern crate sdl2;
 
-use rand::Rng;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
 use sdl2::event::Event;
-use sdl2::EventPump;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
 use sdl2::pixels::PixelFormatEnum;
+use sdl2::keyboard::Mod;
+use sdl2::mouse::MouseButton;
+use sdl2::rect::Rect;
 
-use std::fs::File;
-use std::io::Read;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use cpu::*;
-use c64mem::*;
-use memory::*;
-use vic::*;
+use config::*;
+use emu::*;
+use gfxview::*;
+use joymap::*;
+use joystick::*;
+use keymap::*;
+use palette::*;
+use psid::*;
 
+mod cia;
 mod cpu;
 mod opcode;
 mod memory;
 mod c64mem;
+mod config;
+mod controlport;
+mod crt;
+mod emu;
+mod framebuffer;
+mod gfxview;
+mod golden;
+mod joymap;
+mod joystick;
+mod keyboard;
+mod keymap;
+mod model;
+mod overlay;
+mod palette;
+mod psid;
+mod resampler;
+mod screenshot;
+mod sid;
+mod sidlog;
+mod userport;
 mod vic;
+mod wav;
 
-pub struct Emu {
-    cpu: Cpu,
-    mem: C64Mem
-}
-
-impl Emu {
-    pub fn new() -> Self {
-        let cpu = Cpu::new();
-        let mem = C64Mem::new();
-    
-        Self {
-            cpu: cpu,
-            mem: mem
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config = match Config::from_args(&args) {
+        Ok(config) => config,
+        Err(e)     => {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
-    }
-
-    pub fn start(&mut self, pc_addr: u16) {
-        let (basic, chargen, kernal) = (
-            self.load_file("rom/basic.bin"),
-            self.load_file("rom/characters.bin"),
-            self.load_file("rom/kernal.bin")
-        );
-
-        let test_rom = vec! [
-            0xa9, 0x64, 0xe9, 0x01, 0xc9, 0x00, 0xd0, 0xfa, 0x00
-        ];
+    };
 
-        self.mem.load_rom(&test_rom, 0xE000);
-        self.cpu.set_pc(pc_addr);
-        self.cpu.set_sp(0xFF);
-
-        let mut vic = Vic::new();
-        vic.init(&mut self.mem);
+    if let Some(manifest) = &config.golden {
+        match golden::run_suite(manifest, config.update_goldens) {
+            Ok(true)  => return,
+            Ok(false) => std::process::exit(1),
+            Err(e)    => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
     }
 
-    pub fn load_file(&mut self, file_name: &str) -> Vec<u8> {
-        let mut file = File::open(file_name).unwrap();
-        let mut data = Vec::<u8>::new();
-        file.read_to_end(&mut data).expect("File Not Exist");
-        data
+    if config.headless {
+        if let Err(e) = run_headless(config) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
     }
-}
 
-fn main() {
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
+    let controller_subsystem = sdl_context.game_controller().unwrap();
     let window = video_subsystem
         .window("Malmmodore 64", 800, 600)
         .position_centered()
         .build()
         .unwrap();
         
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let mut canvas = window.into_canvas().build().unwrap();
     canvas.set_draw_color(Color::RGB(0, 0, 0));
     canvas.clear();
     canvas.present();
         
     let mut event_pump = sdl_context.event_pump().unwrap();
 
-    let mut emu = Emu::new();
-    emu.start(0xE000);
+    let mut crt = config.crt;
+    let audio = if config.sound { open_audio(&sdl_context, config.sample_rate) } else { None };
+    let record = config.record.clone();
+    let record_seconds = config.record_seconds;
+    let (sid_log, sid_dump) = (config.sid_log.clone(), config.sid_dump.clone());
+    let (tune, mut song) = match load_tune(&config) {
+        Ok(tune) => tune,
+        Err(e)   => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut key_map = KeyMap::new(config.key_mapping);
+    let mut joy_map = match JoyMap::new(&config.joy_keys, config.control_devices) {
+        Ok(joy_map) => joy_map,
+        Err(e)      => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // a mouse or paddles on a control port take the host mouse over from the light pen
+    sdl_context.mouse().set_relative_mouse_mode(joy_map.mouse_port().is_some());
+    let autofire_rate = config.autofire.unwrap_or(DEFAULT_AUTOFIRE);
+    let mut autofire = config.autofire.is_some();
+    let mut emu = Emu::new(config);
+    if let Err(e) = start(&mut emu, &tune, song) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    if let Some(audio) = &audio {
+        emu.set_sample_rate(audio.spec().freq as u32);
+    }
+    if sid_log.is_some() || sid_dump.is_some() {
+        if let Err(e) = emu.start_sid_log(sid_log.as_deref(), sid_dump.as_deref()) {
+            eprintln!("{}", e);
+        }
+    }
+    if let Some(file_name) = &record {
+        if let Err(e) = emu.start_recording(file_name, record_seconds) {
+            eprintln!("{}", e);
+        }
+    }
+    let model = emu.model();
+
+    let (visible_x, visible_y, visible_width, visible_height) = model.visible_area();
+    let visible = Rect::new(visible_x as i32, visible_y as i32, visible_width as u32, visible_height as u32);
+    let frame_time = Duration::from_secs_f64(1.0 / model.frame_rate());
+
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGBA32, emu.frame().width() as u32, emu.frame().height() as u32)
+        .unwrap();
+    let mut overlay_texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGBA32, (emu.frame().width() + overlay::BAR_WIDTH) as u32, emu.frame().height() as u32)
+        .unwrap();
+    let (crt_width, crt_height) = crt.output_size(visible_width, visible_height);
+    let mut crt_texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGBA32, crt_width as u32, crt_height as u32)
+        .unwrap();
+    let mut viewer_texture = None;
+    let mut viewer_size = (0, 0);
+
+    let mut screenshots = 0;
+    let mut recordings = 0;
+    let mut show_overlay = false;
+    let mut viewer: Option<GfxView> = None;
+
+    let mut palette_kind = PaletteKind::Pepto;
+    let mut next_frame = Instant::now();
 
     'running: loop {
         for event in event_pump.poll_iter() {
@@ -91,13 +170,292 @@ fn main() {
                 Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                     break 'running
                 },
+                Event::KeyDown { keycode: Some(Keycode::F5), .. } => {
+                    emu.resume();
+                },
+                Event::KeyDown { keycode: Some(key @ (Keycode::Left | Keycode::Right)), .. } if tune.is_some() => {
+                    let songs = tune.as_ref().unwrap().songs;
+                    song = match key {
+                        Keycode::Left => if song > 1 { song - 1 } else { songs },
+                        _             => song % songs + 1
+                    };
+                    if let Err(e) = start(&mut emu, &tune, song) {
+                        eprintln!("{}", e);
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::F6), .. } => {
+                    let result = if emu.is_recording() {
+                        emu.stop_recording()
+                    } else {
+                        recordings += 1;
+                        emu.start_recording(&format!("recording-{}.wav", recordings - 1), None)
+                    };
+                    if let Err(e) = result {
+                        eprintln!("{}", e);
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::F7), .. } => {
+                    viewer = match viewer {
+                        None => Some(GfxView::new()),
+                        Some(mut view) if view.page != ViewPage::Memory => {
+                            view.next_page();
+                            Some(view)
+                        },
+                        Some(_) => None
+                    };
+                    if viewer.is_none() {
+                        canvas.window_mut().set_title("Malmmodore 64").unwrap();
+                    }
+                },
+                Event::KeyDown { keycode: Some(key), .. } if viewer.is_some() && is_viewer_key(key) => {
+                    let view = viewer.as_mut().unwrap();
+                    match key {
+                        Keycode::PageUp   => view.address = view.address.wrapping_sub(view.page_size()),
+                        Keycode::PageDown => view.address = view.address.wrapping_add(view.page_size()),
+                        Keycode::Up       => view.address = view.address.wrapping_sub(view.step()),
+                        Keycode::Down     => view.address = view.address.wrapping_add(view.step()),
+                        Keycode::M        => view.toggle_multicolor(),
+                        _                 => view.toggle_sprite()
+                    }
+                    view.page = ViewPage::Memory;
+                },
+                Event::KeyDown { keycode: Some(Keycode::F8), .. } => {
+                    show_overlay = !show_overlay;
+                },
+                Event::KeyDown { keycode: Some(Keycode::F9), .. } => {
+                    palette_kind = Palette::next_kind(palette_kind);
+                    emu.set_palette(Palette::new(palette_kind));
+                },
+                Event::KeyDown { keycode: Some(Keycode::F10), .. } => {
+                    crt.enabled = !crt.enabled;
+                },
+                Event::KeyDown { keycode: Some(Keycode::F12), keymod, .. } => {
+                    let crop_border = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                    let file_name = format!("screenshot-{}.png", screenshots);
+                    let result = match &viewer {
+                        Some(view) => screenshot::save(&emu.gfx_view(view), &file_name),
+                        None       => emu.screenshot(&file_name, crop_border)
+                    };
+                    match result {
+                        Ok(())  => screenshots += 1,
+                        Err(e)  => eprintln!("{}", e)
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::F11), keymod, .. } => {
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        autofire = !autofire;
+                        emu.set_autofire(if autofire { Some(autofire_rate) } else { None });
+                        eprintln!("Autofire {}", if autofire { "on" } else { "off" });
+                    } else {
+                        let swapped = joy_map.swap(&mut emu);
+                        eprintln!("Joysticks {}", if swapped { "swapped" } else { "in their ports" });
+                    }
+                },
+                // keys that belong to a joystick don't reach the keyboard
+                Event::KeyDown { scancode: Some(scancode), keycode, keymod, repeat: false, .. }
+                    if viewer.is_none() && !joy_map.key(&mut emu, scancode, true) => {
+                    key_map.key_down(&mut emu, scancode, keycode, keymod);
+                },
+                Event::KeyUp { scancode: Some(scancode), .. } if !joy_map.key(&mut emu, scancode, false) => {
+                    key_map.key_up(&mut emu, scancode);
+                },
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Err(e) = joy_map.add_controller(&controller_subsystem, which) {
+                        eprintln!("{}", e);
+                    }
+                },
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    joy_map.remove_controller(&mut emu, which);
+                },
+                Event::ControllerButtonDown { which, button, .. } => {
+                    joy_map.button(&mut emu, which, button, true);
+                },
+                Event::ControllerButtonUp { which, button, .. } => {
+                    joy_map.button(&mut emu, which, button, false);
+                },
+                Event::ControllerAxisMotion { which, axis, value, .. } => {
+                    joy_map.axis(&mut emu, which, axis, value);
+                },
+                Event::MouseMotion { xrel, yrel, .. } => {
+                    joy_map.mouse_motion(&mut emu, xrel, yrel);
+                },
+                Event::MouseButtonDown { mouse_btn: button @ (MouseButton::Left | MouseButton::Right), .. } => {
+                    joy_map.mouse_button(&mut emu, (button == MouseButton::Right) as usize, true);
+                },
+                Event::MouseButtonUp { mouse_btn: button @ (MouseButton::Left | MouseButton::Right), .. } => {
+                    joy_map.mouse_button(&mut emu, (button == MouseButton::Right) as usize, false);
+                },
                 _ => {}
             }
         }
 
-        let _ = emu.cpu.execute_instructions(&mut emu.mem);
-        ::std::thread::sleep(Duration::new(0, 70_000));
+        // the left mouse button holds the light pen against the screen
+        let mouse = event_pump.mouse_state();
+        let (window_width, window_height) = canvas.output_size().unwrap();
+        let light_pen = if joy_map.mouse_port().is_none() && mouse.left() && mouse.x() >= 0 && mouse.y() >= 0 {
+            let column = visible_x + mouse.x() as usize * visible_width / window_width as usize;
+            let line = visible_y + mouse.y() as usize * visible_height / window_height as usize;
+            Some((column.min(visible_x + visible_width - 1), line.min(visible_y + visible_height - 1)))
+        } else {
+            None
+        };
+        emu.set_light_pen(light_pen);
+
+        let _ = emu.run_frame();
+
+        let samples = emu.audio_samples();
+        if let Some(audio) = &audio {
+            queue_audio(audio, &mut emu, &samples);
+        }
+
+        canvas.clear();
+        if let Some(view) = &viewer {
+            let image = emu.gfx_view(view);
+            if viewer_size != (image.width(), image.height()) {
+                viewer_size = (image.width(), image.height());
+                viewer_texture = Some(texture_creator
+                    .create_texture_streaming(PixelFormatEnum::RGBA32, image.width() as u32, image.height() as u32)
+                    .unwrap());
+            }
+            let viewer_texture = viewer_texture.as_mut().unwrap();
+            viewer_texture.update(None, image.pixels(), image.pitch()).unwrap();
+            canvas.copy(viewer_texture, None, None).unwrap();
+            canvas.window_mut().set_title(&format!("Malmmodore 64 - {}", emu.gfx_view_title(view))).unwrap();
+        } else if show_overlay || emu.at_breakpoint() {
+            let overlay = emu.overlay();
+            overlay_texture.update(None, overlay.pixels(), overlay.pitch()).unwrap();
+            canvas.copy(&overlay_texture, None, None).unwrap();
+        } else if crt.enabled {
+            let screen = crt.apply(&emu.screen(false));
+            crt_texture.update(None, screen.pixels(), screen.pitch()).unwrap();
+            canvas.copy(&crt_texture, None, None).unwrap();
+        } else {
+            let frame = emu.frame();
+            texture.update(None, frame.pixels(), frame.pitch()).unwrap();
+            canvas.copy(&texture, visible, None).unwrap();
+        }
+        canvas.present();
+
+        next_frame += frame_time;
+        let now = Instant::now();
+        if next_frame > now {
+            ::std::thread::sleep(next_frame - now);
+        } else {
+            next_frame = now;
+        }
+    }
+
+    if let Err(e) = emu.stop_recording() {
+        eprintln!("{}", e);
+    }
+    if let Err(e) = emu.stop_sid_log() {
+        eprintln!("{}", e);
+    }
+}
+
+fn run_headless(config: Config) -> Result<(), String> {
+    let frames = config.frames;
+    let screenshot = config.screenshot.clone();
+    let crop_border = config.crop_border;
+    let rip = config.rip.clone();
+    let record = config.record.clone();
+    let record_seconds = config.record_seconds;
+    let (sid_log, sid_dump) = (config.sid_log.clone(), config.sid_dump.clone());
+    let (tune, song) = load_tune(&config)?;
+
+    let mut emu = Emu::new(config);
+    start(&mut emu, &tune, song)?;
+    if sid_log.is_some() || sid_dump.is_some() {
+        emu.start_sid_log(sid_log.as_deref(), sid_dump.as_deref())?;
+    }
+    if let Some(file_name) = &record {
+        emu.start_recording(file_name, record_seconds)?;
+    }
+
+    // a timed recording keeps the machine running until it is complete
+    let mut frame = 0;
+    while frame < frames || (record_seconds.is_some() && emu.is_recording() && !emu.at_breakpoint()) {
+        let _ = emu.run_frame();
+        emu.audio_samples();
+        frame += 1;
     }
+    emu.stop_recording()?;
+    emu.stop_sid_log()?;
+
+    if let Some(file_name) = screenshot {
+        emu.screenshot(&file_name, crop_border)?;
+    }
+    for (view, file_name) in &rip {
+        screenshot::save(&emu.gfx_view(view), file_name)?;
+    }
+    Ok(())
+}
+
+// the .sid file to play and the song to start with
+fn load_tune(config: &Config) -> Result<(Option<Psid>, u16), String> {
+    match &config.sid_file {
+        Some(file_name) => {
+            let tune = Psid::load(file_name)?;
+            let song = config.song.unwrap_or(tune.start_song);
+            Ok((Some(tune), song))
+        },
+        None            => Ok((None, 0))
+    }
+}
+
+fn start(emu: &mut Emu, tune: &Option<Psid>, song: u16) -> Result<(), String> {
+    match tune {
+        Some(tune) => {
+            emu.play_sid(tune, song)?;
+            println!("{}", tune.info(song));
+        },
+        None       => emu.reset()
+    }
+    Ok(())
+}
+
+// how much audio we try to keep queued ahead of the device
+const AUDIO_LATENCY: f64 = 0.06;
+
+fn open_audio(sdl_context: &sdl2::Sdl, sample_rate: u32) -> Option<AudioQueue<f32>> {
+    let spec = AudioSpecDesired {
+        freq: Some(sample_rate as i32),
+        channels: Some(AUDIO_CHANNELS as u8),
+        samples: Some(1024),
+    };
+    let queue = sdl_context.audio().and_then(|audio| audio.open_queue::<f32, _>(None, &spec));
+    match queue {
+        Ok(queue) => {
+            queue.resume();
+            Some(queue)
+        },
+        Err(e)    => {
+            eprintln!("No Sound: {}", e);
+            None
+        }
+    }
+}
+
+// dynamic rate control: bend the resampling ratio slightly so that the queue stays
+// around the target latency instead of slowly running dry or piling up against video
+fn queue_audio(audio: &AudioQueue<f32>, emu: &mut Emu, samples: &[f32]) {
+    let rate = audio.spec().freq as f64;
+    let target = rate * AUDIO_LATENCY;
+    let frame_size = std::mem::size_of::<f32>() * AUDIO_CHANNELS as usize;
+    let queued = (audio.size() as usize / frame_size) as f64;
+
+    if queued > target * 4.0 {
+        // far behind, e.g. after a breakpoint: drop the backlog
+        audio.clear();
+    }
+    let error = ((queued - target) / target).clamp(-1.0, 1.0);
+    emu.set_audio_adjust(1.0 + error * resampler::MAX_ADJUST);
+
+    if let Err(e) = audio.queue_audio(samples) {
+        eprintln!("{}", e);
+    }
+}
 
-    println!("Hello, world!");
+fn is_viewer_key(key: Keycode) -> bool {
+    matches!(key, Keycode::PageUp | Keycode::PageDown | Keycode::Up | Keycode::Down | Keycode::M | Keycode::C)
 }
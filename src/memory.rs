@@ -1,15 +1,17 @@
 pub trait IOMem {
-   fn read_u8(&self, addr: u16) -> u8;
-   fn read_u16(&self, addr: u16) -> u16;
+   fn read_u8(&mut self, addr: u16) -> u8;
+   fn read_u16(&mut self, addr: u16) -> u16;
    fn write_u8(&mut self, addr: u16, value: u8);
 }
 
-pub trait IOCia {
-   fn read(&self, address: u16) -> u8;
-   fn write(&mut self, address: u16, value: u8);
-}
-
 pub trait IOVic {
    fn read(&self, addr: u16) -> u8;
    fn write(&mut self, addr: u16, value: u8);
-}
+   // 14-bit address inside the VIC's current 16K bank
+   fn fetch(&self, addr: u16) -> u8;
+   // the same access through any of the four banks, for the graphics viewer
+   fn fetch_bank(&self, bank: u16, addr: u16) -> u8;
+   fn bank(&self) -> u16;
+   fn fetch_color(&self, index: u16) -> u8;
+   fn raster_compare(&self) -> u16;
+}
\ No newline at end of file
@@ -0,0 +1,147 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VicModel {
+   Mos6569,
+   Mos6567R8,
+   Mos6567R56A,
+}
+
+impl VicModel {
+   pub fn from_name(name: &str) -> Result<VicModel, String> {
+      match name.to_lowercase().as_str() {
+         "6569" | "pal"          => Ok(VicModel::Mos6569),
+         "6567r8" | "ntsc"       => Ok(VicModel::Mos6567R8),
+         "6567r56a" | "ntsc-old" => Ok(VicModel::Mos6567R56A),
+         _                       => Err(format!("Unknown VIC Model: {}", name))
+      }
+   }
+
+   pub fn raster_lines(&self) -> u16 {
+      match self {
+         VicModel::Mos6569     => 312,
+         VicModel::Mos6567R8   => 263,
+         VicModel::Mos6567R56A => 262,
+      }
+   }
+
+   pub fn cycles_per_line(&self) -> u16 {
+      match self {
+         VicModel::Mos6569     => 63,
+         VicModel::Mos6567R8   => 65,
+         VicModel::Mos6567R56A => 64,
+      }
+   }
+
+   pub fn cpu_clock(&self) -> u32 {
+      match self {
+         VicModel::Mos6569     => 985248,
+         VicModel::Mos6567R8   => 1022727,
+         VicModel::Mos6567R56A => 1022727,
+      }
+   }
+
+   // the power line frequency that drives the CIA time-of-day clocks
+   pub fn mains_frequency(&self) -> u32 {
+      match self {
+         VicModel::Mos6569     => 50,
+         VicModel::Mos6567R8   => 60,
+         VicModel::Mos6567R56A => 60,
+      }
+   }
+
+   pub fn frame_cycles(&self) -> u32 {
+      self.raster_lines() as u32 * self.cycles_per_line() as u32
+   }
+
+   pub fn frame_rate(&self) -> f64 {
+      self.cpu_clock() as f64 / self.frame_cycles() as f64
+   }
+
+   // cycle of the p-access for each sprite
+   pub fn sprite_fetch_cycles(&self) -> [u16; 8] {
+      match self {
+         VicModel::Mos6569     => [58, 60, 62, 1, 3, 5, 7, 9],
+         VicModel::Mos6567R8   => [59, 61, 63, 65, 2, 4, 6, 8],
+         VicModel::Mos6567R56A => [58, 60, 62, 64, 2, 4, 6, 8],
+      }
+   }
+
+   // (x, y, width, height) of the part of the frame a monitor shows
+   pub fn visible_area(&self) -> (usize, usize, usize, usize) {
+      match self {
+         VicModel::Mos6569     => (88, 15, 384, 272),
+         VicModel::Mos6567R8   => (88, 27, 384, 235),
+         VicModel::Mos6567R56A => (88, 27, 384, 235),
+      }
+   }
+
+   // (x, y, width, height) of the 40x25 display window, i.e. the screen without border
+   pub fn display_area(&self) -> (usize, usize, usize, usize) {
+      (120, 51, 320, 200)
+   }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CiaModel {
+   // the original CIA, flags timer interrupts a cycle late
+   Mos6526,
+   // the later 6526A/8521
+   Mos6526A,
+}
+
+impl CiaModel {
+   pub fn from_name(name: &str) -> Result<CiaModel, String> {
+      match name.to_lowercase().as_str() {
+         "6526" | "old"          => Ok(CiaModel::Mos6526),
+         "6526a" | "8521" | "new" => Ok(CiaModel::Mos6526A),
+         _                       => Err(format!("Unknown CIA Model: {}", name))
+      }
+   }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SidModel {
+   Mos6581,
+   Mos8580,
+}
+
+impl SidModel {
+   pub fn from_name(name: &str) -> Result<SidModel, String> {
+      match name.to_lowercase().as_str() {
+         "6581" => Ok(SidModel::Mos6581),
+         "8580" => Ok(SidModel::Mos8580),
+         _      => Err(format!("Unknown SID Model: {}", name))
+      }
+   }
+
+   // cycles until a value written to the SID fades from its data bus
+   pub fn bus_value_ttl(&self) -> u32 {
+      match self {
+         SidModel::Mos6581 => 0x1D00,
+         SidModel::Mos8580 => 0xA2000,
+      }
+   }
+
+   // waveform DAC output that counts as silence
+   pub fn waveform_zero(&self) -> i32 {
+      match self {
+         SidModel::Mos6581 => 0x380,
+         SidModel::Mos8580 => 0x800,
+      }
+   }
+
+   // DC offset of each voice's envelope multiplier
+   pub fn voice_dc(&self) -> i32 {
+      match self {
+         SidModel::Mos6581 => 0x800 * 0xFF,
+         SidModel::Mos8580 => 0,
+      }
+   }
+
+   // DC offset of the mixer, in units of one full scale voice
+   pub fn mixer_dc(&self) -> f32 {
+      match self {
+         SidModel::Mos6581 => -1.0 / 18.0,
+         SidModel::Mos8580 => 0.0,
+      }
+   }
+}
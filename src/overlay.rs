@@ -0,0 +1,169 @@
+use crate::framebuffer::*;
+
+pub const BAR_WIDTH   : usize = 72;
+const MARKER_WIDTH    : usize = 4;
+
+const MAIN_COLOR      : u32 = 0x40C040;
+const IRQ_COLOR       : u32 = 0xE04040;
+const STOLEN_COLOR    : u32 = 0x808080;
+const BAD_LINE_COLOR  : u32 = 0xFFE000;
+const SPRITE_COLOR    : u32 = 0x00C0FF;
+const BEAM_COLOR      : u32 = 0xFF00FF;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LineStats {
+   pub main: u16,
+   pub irq: u16,
+   pub stolen: u16,
+   pub bad_line: bool,
+   pub sprite_dma: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CycleOwner {
+   Main,
+   Irq,
+   Stolen,
+}
+
+impl LineStats {
+   pub fn add(&mut self, owner: CycleOwner) {
+      match owner {
+         CycleOwner::Main   => self.main += 1,
+         CycleOwner::Irq    => self.irq += 1,
+         CycleOwner::Stolen => self.stolen += 1,
+      }
+   }
+}
+
+fn blend(color: u32, tint: u32, amount: u32) -> u32 {
+   let mix = |shift: u32| {
+      let a = (color >> shift) & 0xFF;
+      let b = (tint >> shift) & 0xFF;
+      ((a * (256 - amount) + b * amount) >> 8) << shift
+   };
+   mix(16) | mix(8) | mix(0)
+}
+
+// the whole frame including blanking, each line tinted by where the CPU spent it, with
+// a bar on the right: badline and sprite DMA markers, then main/IRQ/stolen cycles
+pub fn render(frame: &FrameBuffer, stats: &[LineStats], cycles_per_line: u16, beam: Option<(usize, usize)>) -> FrameBuffer {
+   let width = frame.width();
+   let mut output = FrameBuffer::new(width + BAR_WIDTH, frame.height());
+   let bar_start = width + MARKER_WIDTH * 2;
+   let bar_scale = (BAR_WIDTH - MARKER_WIDTH * 2) as f32 / cycles_per_line as f32;
+
+   for y in 0..frame.height() {
+      let line = stats.get(y).copied().unwrap_or_default();
+      let tint = if line.irq > line.main {
+         Some(IRQ_COLOR)
+      } else if line.main > 0 {
+         Some(MAIN_COLOR)
+      } else {
+         None
+      };
+
+      for x in 0..width {
+         let pixel = frame.get_pixel(x, y);
+         output.put_pixel(x, y, match tint {
+            Some(tint) => blend(pixel, tint, 64),
+            None       => pixel
+         });
+      }
+
+      for x in 0..MARKER_WIDTH {
+         if line.bad_line {
+            output.put_pixel(width + x, y, BAD_LINE_COLOR);
+         }
+         if line.sprite_dma {
+            output.put_pixel(width + MARKER_WIDTH + x, y, SPRITE_COLOR);
+         }
+      }
+
+      let mut x = bar_start;
+      for (cycles, color) in [(line.main, MAIN_COLOR), (line.irq, IRQ_COLOR), (line.stolen, STOLEN_COLOR)] {
+         let end = (x + (cycles as f32 * bar_scale).round() as usize).min(output.width());
+         while x < end {
+            output.put_pixel(x, y, color);
+            x += 1;
+         }
+      }
+   }
+
+   if let Some((column, line)) = beam {
+      for x in (0..width).step_by(2) {
+         output.put_pixel(x, line, BEAM_COLOR);
+      }
+      for y in (0..frame.height()).step_by(2) {
+         output.put_pixel(column, y, BEAM_COLOR);
+      }
+      for x in column..(column + 8).min(width) {
+         output.put_pixel(x, line, 0xFFFFFF);
+      }
+   }
+
+   output
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn stats(main: u16, irq: u16, stolen: u16) -> LineStats {
+      LineStats { main: main, irq: irq, stolen: stolen, bad_line: false, sprite_dma: false }
+   }
+
+   // the colors along the bar of a line
+   fn bar(output: &FrameBuffer, width: usize, y: usize) -> Vec<u32> {
+      (width + MARKER_WIDTH * 2..output.width()).map(|x| output.get_pixel(x, y)).collect()
+   }
+
+   #[test]
+   fn cycles_are_counted_by_owner() {
+      let mut line = LineStats::default();
+      for owner in [CycleOwner::Main, CycleOwner::Main, CycleOwner::Irq, CycleOwner::Stolen] {
+         line.add(owner);
+      }
+      assert_eq!((line.main, line.irq, line.stolen), (2, 1, 1));
+   }
+
+   #[test]
+   fn bars_split_the_line_between_main_irq_and_stolen_cycles() {
+      let frame = FrameBuffer::new(16, 3);
+      // 64 bar pixels for 32 cycles, two per cycle
+      let output = render(&frame, &[stats(20, 8, 4), stats(32, 0, 0), LineStats::default()], 32, None);
+      assert_eq!(output.width(), 16 + BAR_WIDTH);
+      let colors = bar(&output, 16, 0);
+      assert_eq!(colors.iter().filter(|&&color| color == MAIN_COLOR).count(), 40);
+      assert_eq!(colors.iter().filter(|&&color| color == IRQ_COLOR).count(), 16);
+      assert_eq!(colors[56..], [STOLEN_COLOR; 8]);
+      assert_eq!(bar(&output, 16, 1), [MAIN_COLOR; 64]);
+      assert_eq!(bar(&output, 16, 2), [0; 64]);
+   }
+
+   #[test]
+   fn lines_are_tinted_by_who_used_them_and_marked_for_dma() {
+      let mut frame = FrameBuffer::new(16, 3);
+      for y in 0..3 {
+         frame.put_pixel(0, y, 0x808080);
+      }
+      let mut lines = [stats(10, 2, 0), stats(2, 10, 0), stats(0, 0, 63)];
+      lines[0].bad_line = true;
+      lines[2].sprite_dma = true;
+      let output = render(&frame, &lines, 63, None);
+      assert_eq!(output.get_pixel(0, 0), blend(0x808080, MAIN_COLOR, 64));
+      assert_eq!(output.get_pixel(0, 1), blend(0x808080, IRQ_COLOR, 64));
+      assert_eq!(output.get_pixel(0, 2), 0x808080);
+      assert_eq!([output.get_pixel(16, 0), output.get_pixel(16 + MARKER_WIDTH, 0)], [BAD_LINE_COLOR, 0]);
+      assert_eq!([output.get_pixel(16, 2), output.get_pixel(16 + MARKER_WIDTH, 2)], [0, SPRITE_COLOR]);
+   }
+
+   #[test]
+   fn beam_is_drawn_as_a_crosshair_with_the_current_cycle() {
+      let frame = FrameBuffer::new(32, 8);
+      let output = render(&frame, &[], 4, Some((8, 3)));
+      assert_eq!((8..16).map(|x| output.get_pixel(x, 3)).collect::<Vec<u32>>(), [0xFFFFFF; 8]);
+      assert_eq!([output.get_pixel(2, 3), output.get_pixel(3, 3)], [BEAM_COLOR, 0]);
+      assert_eq!([output.get_pixel(8, 0), output.get_pixel(8, 1)], [BEAM_COLOR, 0]);
+   }
+}
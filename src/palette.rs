@@ -0,0 +1,186 @@
+use std::fs;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PaletteKind {
+   Pepto,
+   Colodore,
+   Vice,
+   Grayscale,
+}
+
+const PALETTE_KINDS: [PaletteKind; 4] = [
+   PaletteKind::Pepto,
+   PaletteKind::Colodore,
+   PaletteKind::Vice,
+   PaletteKind::Grayscale,
+];
+
+const PEPTO: [u32; 16] = [
+   0x000000, 0xffffff, 0x68372b, 0x70a4b2,
+   0x6f3d86, 0x588d43, 0x352879, 0xb8c76f,
+   0x6f4f25, 0x433900, 0x9a6759, 0x444444,
+   0x6c6c6c, 0x9ad284, 0x6c5eb5, 0x959595,
+];
+
+const COLODORE: [u32; 16] = [
+   0x000000, 0xffffff, 0x813338, 0x75cec8,
+   0x8e3c97, 0x56ac4d, 0x2e2c9b, 0xedf171,
+   0x8e5029, 0x553800, 0xc46c71, 0x4a4a4a,
+   0x7b7b7b, 0xa9ff9f, 0x706deb, 0xb2b2b2,
+];
+
+const VICE: [u32; 16] = [
+   0x000000, 0xfdfefc, 0xbe1a24, 0x30e6c6,
+   0xb41ae2, 0x1fd21e, 0x211bae, 0xdff60a,
+   0xb84104, 0x6a3304, 0xfe4a57, 0x424540,
+   0x70746f, 0x59fe59, 0x5f53fe, 0xa4a7a2,
+];
+
+// luminance of each color on a 0-32 scale, as measured on the VIC-II
+const LUMA: [u32; 16] = [
+   0, 32, 10, 20, 12, 16, 8, 24,
+   12, 8, 16, 10, 15, 24, 15, 20,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+   colors: [u32; 16],
+}
+
+impl Palette {
+   pub fn new(kind: PaletteKind) -> Self {
+      let colors = match kind {
+         PaletteKind::Pepto     => PEPTO,
+         PaletteKind::Colodore  => COLODORE,
+         PaletteKind::Vice      => VICE,
+         PaletteKind::Grayscale => {
+            let mut colors = [0u32; 16];
+            for (color, luma) in colors.iter_mut().zip(LUMA.iter()) {
+               let level = (luma * 255 + 16) / 32;
+               *color = (level << 16) | (level << 8) | level;
+            }
+            colors
+         }
+      };
+
+      Self {
+         colors: colors
+      }
+   }
+
+   pub fn from_name(name: &str) -> Result<Self, String> {
+      match name.to_lowercase().as_str() {
+         "pepto"     => Ok(Palette::new(PaletteKind::Pepto)),
+         "colodore"  => Ok(Palette::new(PaletteKind::Colodore)),
+         "vice"      => Ok(Palette::new(PaletteKind::Vice)),
+         "grayscale" => Ok(Palette::new(PaletteKind::Grayscale)),
+         _ if name.to_lowercase().ends_with(".vpl") => Palette::load_vpl(name),
+         _           => Err(format!("Unknown Palette: {}", name))
+      }
+   }
+
+   pub fn next_kind(kind: PaletteKind) -> PaletteKind {
+      let index = PALETTE_KINDS.iter().position(|k| *k == kind).unwrap_or(0);
+      PALETTE_KINDS[(index + 1) % PALETTE_KINDS.len()]
+   }
+
+   pub fn load_vpl(file_name: &str) -> Result<Self, String> {
+      let text = fs::read_to_string(file_name)
+         .map_err(|e| format!("Palette {}: {}", file_name, e))?;
+      Palette::parse_vpl(&text)
+   }
+
+   // VICE palette files list one color per line as "RR GG BB [dither]" in hex,
+   // with '#' starting a comment
+   pub fn parse_vpl(text: &str) -> Result<Self, String> {
+      let mut colors = [0u32; 16];
+      let mut count = 0;
+
+      for line in text.lines() {
+         let line = line.split('#').next().unwrap_or("").trim();
+         if line.is_empty() {
+            continue;
+         }
+         if count == 16 {
+            return Err(String::from("Palette Has More Than 16 Colors"));
+         }
+
+         let fields: Vec<&str> = line.split_whitespace().collect();
+         if fields.len() < 3 {
+            return Err(format!("Bad Palette Line: {}", line));
+         }
+         let mut color = 0u32;
+         for field in &fields[0..3] {
+            let value = u8::from_str_radix(field, 16)
+               .map_err(|_| format!("Bad Palette Value: {}", field))?;
+            color = (color << 8) | value as u32;
+         }
+         colors[count] = color;
+         count += 1;
+      }
+
+      if count != 16 {
+         return Err(format!("Palette Has {} Colors, Expected 16", count));
+      }
+
+      Ok(Self {
+         colors: colors
+      })
+   }
+
+   pub fn color(&self, index: u8) -> u32 {
+      self.colors[(index & 0x0F) as usize]
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // the Pepto colors in VICE's format, with a header and the dither column
+   fn pepto_vpl() -> String {
+      let mut text = String::from("#\n# VICE Palette file\n#\n# Syntax:\n# Red Green Blue Dither\n#\n\n");
+      for color in PEPTO {
+         text += &format!("{:02X} {:02X} {:02X} 0  # a color\n", color >> 16, (color >> 8) & 0xFF, color & 0xFF);
+      }
+      text
+   }
+
+   #[test]
+   fn vpl_files_parse_to_their_colors() {
+      assert_eq!(Palette::parse_vpl(&pepto_vpl()), Ok(Palette::new(PaletteKind::Pepto)));
+   }
+
+   #[test]
+   fn bad_vpl_files_are_rejected() {
+      let text = pepto_vpl();
+      let fifteen: String = text.lines().take(22).map(|line| format!("{}\n", line)).collect();
+      assert_eq!(Palette::parse_vpl(&fifteen), Err(String::from("Palette Has 15 Colors, Expected 16")));
+      assert_eq!(Palette::parse_vpl(&(text.clone() + "00 00 00\n")), Err(String::from("Palette Has More Than 16 Colors")));
+      assert_eq!(Palette::parse_vpl(&text.replacen("FF FF FF 0", "FF FF", 1)), Err(String::from("Bad Palette Line: FF FF")));
+      assert_eq!(Palette::parse_vpl(&text.replacen("FF FF FF", "FF GG FF", 1)), Err(String::from("Bad Palette Value: GG")));
+   }
+
+   #[test]
+   fn grayscale_follows_the_luminance() {
+      let palette = Palette::new(PaletteKind::Grayscale);
+      assert_eq!(palette.color(0), 0x000000);
+      assert_eq!(palette.color(1), 0xFFFFFF);
+      // red and blue-ish colors sit in between, equal lumas give equal grays
+      assert_eq!(palette.color(2), palette.color(11));
+      assert!(palette.color(6) < palette.color(2) && palette.color(2) < palette.color(7));
+   }
+
+   #[test]
+   fn palettes_are_found_by_name_and_cycle() {
+      assert_eq!(Palette::from_name("Colodore"), Ok(Palette::new(PaletteKind::Colodore)));
+      assert!(Palette::from_name("sepia").is_err());
+      let mut kind = PaletteKind::Pepto;
+      for expected in [PaletteKind::Colodore, PaletteKind::Vice, PaletteKind::Grayscale, PaletteKind::Pepto] {
+         kind = Palette::next_kind(kind);
+         assert_eq!(kind, expected);
+      }
+      // the color index only uses the low nibble, like the VIC's registers
+      assert_eq!(Palette::new(PaletteKind::Vice).color(0xF1), VICE[1]);
+   }
+}
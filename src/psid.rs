@@ -0,0 +1,361 @@
+use std::fs;
+
+use crate::model::*;
+use crate::sid::*;
+
+const PSID_MAGIC      : &[u8] = b"PSID";
+const RSID_MAGIC      : &[u8] = b"RSID";
+const V1_HEADER       : usize = 0x76;
+const V2_HEADER       : usize = 0x7C;
+
+// CIA 1 timer A values for 60 Hz, as set by the KERNAL
+const CIA_60HZ_PAL    : u16 = 0x4025;
+const CIA_60HZ_NTSC   : u16 = 0x4295;
+
+// KERNAL handlers the RAM vectors point to after a reset
+const KERNAL_IRQ      : u16 = 0xEA31;
+const KERNAL_BRK      : u16 = 0xFE66;
+const KERNAL_NMI      : u16 = 0xFE47;
+
+const TAPE_BUFFER     : u16 = 0x0334;
+const DRIVER_SIZE     : usize = 0x80;
+
+// (address, bytes) blocks to load and the address to start at
+pub type MemoryImage = (Vec<(u16, Vec<u8>)>, u16);
+
+pub struct Psid {
+   pub rsid: bool,
+   pub version: u16,
+   pub load_address: u16,
+   pub init_address: u16,
+   pub play_address: u16,
+   pub songs: u16,
+   pub start_song: u16,
+   pub speed: u32,
+   pub name: String,
+   pub author: String,
+   pub released: String,
+   pub flags: u16,
+   pub start_page: u8,
+   pub page_length: u8,
+   pub second_sid: u8,
+   pub third_sid: u8,
+   pub data: Vec<u8>,
+}
+
+impl Psid {
+   pub fn load(file_name: &str) -> Result<Psid, String> {
+      let data = fs::read(file_name).map_err(|e| format!("SID File {}: {}", file_name, e))?;
+      Psid::parse(&data).map_err(|e| format!("SID File {}: {}", file_name, e))
+   }
+
+   pub fn parse(file: &[u8]) -> Result<Psid, String> {
+      if file.len() < V1_HEADER {
+         return Err(String::from("File Too Short"));
+      }
+      let word = |offset: usize| u16::from_be_bytes([file[offset], file[offset + 1]]);
+      let text = |offset: usize| {
+         let field = &file[offset..offset + 32];
+         let end = field.iter().position(|&c| c == 0).unwrap_or(32);
+         // Latin-1
+         field[..end].iter().map(|&c| c as char).collect::<String>()
+      };
+
+      let rsid = match &file[0..4] {
+         PSID_MAGIC => false,
+         RSID_MAGIC => true,
+         _          => return Err(String::from("Not A PSID Or RSID File"))
+      };
+      let version = word(0x04);
+      if !(1..=4).contains(&version) || (rsid && version == 1) {
+         return Err(format!("Unsupported Version: {}", version));
+      }
+      let data_offset = word(0x06) as usize;
+      if data_offset < V1_HEADER || (version > 1 && data_offset < V2_HEADER) || data_offset + 2 > file.len() {
+         return Err(format!("Bad Data Offset: {:#X}", data_offset));
+      }
+
+      let mut psid = Psid {
+         rsid: rsid,
+         version: version,
+         load_address: word(0x08),
+         init_address: word(0x0A),
+         play_address: word(0x0C),
+         songs: word(0x0E),
+         start_song: word(0x10),
+         speed: u32::from_be_bytes([file[0x12], file[0x13], file[0x14], file[0x15]]),
+         name: text(0x16),
+         author: text(0x36),
+         released: text(0x56),
+         flags: 0,
+         start_page: 0,
+         page_length: 0,
+         second_sid: 0,
+         third_sid: 0,
+         data: file[data_offset..].to_vec(),
+      };
+      if version > 1 {
+         psid.flags = word(0x76);
+         psid.start_page = file[0x78];
+         psid.page_length = file[0x79];
+      }
+      if version > 2 {
+         psid.second_sid = file[0x7A];
+      }
+      if version > 3 {
+         psid.third_sid = file[0x7B];
+      }
+
+      // a zero load address means the data starts with it, like a .prg
+      if psid.load_address == 0 {
+         psid.load_address = u16::from_le_bytes([psid.data[0], psid.data[1]]);
+         psid.data.drain(..2);
+      }
+      if psid.init_address == 0 {
+         psid.init_address = psid.load_address;
+      }
+      if psid.songs == 0 || psid.songs > 256 {
+         return Err(format!("Bad Song Count: {}", psid.songs));
+      }
+      if psid.start_song == 0 || psid.start_song > psid.songs {
+         psid.start_song = 1;
+      }
+      if psid.load_address as usize + psid.data.len() > 0x10000 {
+         return Err(format!("Tune Does Not Fit At {:#06X}", psid.load_address));
+      }
+      if rsid && (psid.play_address != 0 || psid.speed != 0) {
+         return Err(String::from("RSID Tune With Play Address Or Speed Flags"));
+      }
+
+      Ok(psid)
+   }
+
+   // 1-based song numbers as in the header
+   pub fn uses_cia(&self, song: u16) -> bool {
+      let bit = (song.max(1) - 1).min(31);
+      self.speed & (1 << bit) != 0
+   }
+
+   // SID chip the tune was written for, if the header says
+   pub fn sid_model(&self) -> Option<SidModel> {
+      match (self.flags >> 4) & 3 {
+         1 => Some(SidModel::Mos6581),
+         2 => Some(SidModel::Mos8580),
+         _ => None
+      }
+   }
+
+   // base addresses of the second and third SID, if the header asks for them
+   pub fn extra_sids(&self) -> Vec<u16> {
+      [self.second_sid, self.third_sid].iter()
+         .map(|&page| 0xD000 | (page as u16) << 4)
+         .take_while(|&address| extra_sid_address(address))
+         .collect()
+   }
+
+   pub fn info(&self, song: u16) -> String {
+      let model = match self.sid_model() {
+         Some(model) => format!(" for {:?}", model),
+         None        => String::new()
+      };
+      let chips = match self.extra_sids().len() {
+         0 => "",
+         1 => ", 2 SIDs",
+         _ => ", 3 SIDs"
+      };
+      format!("{}\n{}\n{}\n{} v{}{}{}, song {} of {}", self.name, self.author, self.released,
+         if self.rsid { "RSID" } else { "PSID" }, self.version, model, chips, song, self.songs)
+   }
+
+   fn end_address(&self) -> usize {
+      self.load_address as usize + self.data.len()
+   }
+
+   fn overlaps(&self, start: usize, length: usize) -> bool {
+      start < self.end_address() && (self.load_address as usize) < start + length
+   }
+
+   // where the player code goes: the free page from the header, the tape buffer or
+   // the first page the tune leaves alone
+   fn driver_address(&self) -> Result<u16, String> {
+      if self.start_page != 0 && self.start_page != 0xFF {
+         return Ok((self.start_page as u16) << 8);
+      }
+      if !self.overlaps(TAPE_BUFFER as usize, DRIVER_SIZE) {
+         return Ok(TAPE_BUFFER);
+      }
+      (0x04..0xD0)
+         .map(|page| page << 8)
+         .find(|&address| !self.overlaps(address, DRIVER_SIZE))
+         .map(|address| address as u16)
+         .ok_or(String::from("No Free Memory For The Player"))
+   }
+
+   // the tune and its player, entered through the player
+   pub fn memory_image(&self, song: u16, model: VicModel) -> Result<MemoryImage, String> {
+      let base = self.driver_address()?;
+      let mut blocks = vec![(self.load_address, self.data.clone())];
+
+      let own_interrupts = self.rsid || self.play_address == 0;
+      let mut code = Vec::with_capacity(DRIVER_SIZE);
+      if own_interrupts {
+         // the tune brings its own interrupt handler and expects the KERNAL's vectors
+         let vectors: Vec<u8> = [KERNAL_IRQ, KERNAL_BRK, KERNAL_NMI].iter()
+            .flat_map(|vector| vector.to_le_bytes())
+            .collect();
+         blocks.push((0x0314, vectors));
+      } else {
+         // the player runs with the KERNAL banked out, so the hardware vectors come from RAM
+         let (irq, rti) = self.irq_handler(&mut code, base);
+         let vectors: Vec<u8> = [rti, rti, irq].iter()
+            .flat_map(|vector| vector.to_le_bytes())
+            .collect();
+         blocks.push((0xFFFA, vectors));
+      }
+
+      let entry = base + code.len() as u16;
+      self.init_code(&mut code, base, song, model);
+      blocks.push((base, code));
+      Ok((blocks, entry))
+   }
+
+   // saves the registers, calls play with the memory configuration it needs and
+   // acknowledges both interrupt sources; returns the handler and RTI addresses
+   fn irq_handler(&self, code: &mut Vec<u8>, base: u16) -> (u16, u16) {
+      let [play_lo, play_hi] = self.play_address.to_le_bytes();
+      code.extend_from_slice(&[
+         0x48, 0x8A, 0x48, 0x98, 0x48,       // PHA, TXA, PHA, TYA, PHA
+         0xA5, 0x01, 0x48,                   // LDA $01, PHA
+         0xA9, bank_for(self.play_address),  // LDA #bank
+         0x85, 0x01,                         // STA $01
+         0x20, play_lo, play_hi,             // JSR play
+         0x68, 0x85, 0x01,                   // PLA, STA $01
+         0xA9, 0xFF, 0x8D, 0x19, 0xD0,       // LDA #$FF, STA $D019
+         0xAD, 0x0D, 0xDC,                   // LDA $DC0D
+         0x68, 0xA8, 0x68, 0xAA, 0x68,       // PLA, TAY, PLA, TAX, PLA
+      ]);
+      let rti = base + code.len() as u16;
+      code.push(0x40);                       // RTI
+      (base, rti)
+   }
+
+   // calls init with the song number, starts the interrupt source and idles
+   fn init_code(&self, code: &mut Vec<u8>, base: u16, song: u16, model: VicModel) {
+      let own_interrupts = self.rsid || self.play_address == 0;
+      let init_bank = if self.rsid { 0x37 } else { bank_for(self.init_address) };
+      let idle_bank = if own_interrupts { init_bank } else { 0x35 };
+      let timer = match model {
+         VicModel::Mos6569 => CIA_60HZ_PAL,
+         _                 => CIA_60HZ_NTSC,
+      };
+      let [init_lo, init_hi] = self.init_address.to_le_bytes();
+      let [timer_lo, timer_hi] = timer.to_le_bytes();
+
+      code.extend_from_slice(&[
+         0x78,                               // SEI
+         0xA9, init_bank, 0x85, 0x01,        // LDA #bank, STA $01
+         0xA9, (song - 1) as u8,             // LDA #song
+         0x20, init_lo, init_hi,             // JSR init
+         0x78,                               // SEI
+         0xA9, idle_bank, 0x85, 0x01,        // LDA #bank, STA $01
+      ]);
+
+      if own_interrupts || self.uses_cia(song) {
+         // CIA 1 timer A, at 60 Hz unless init has set it up
+         code.extend_from_slice(&[
+            0xA9, 0x00, 0x8D, 0x1A, 0xD0,    // LDA #$00, STA $D01A
+            0xAD, 0x05, 0xDC, 0xD0, 0x0A,    // LDA $DC05, BNE +10
+            0xA9, timer_lo, 0x8D, 0x04, 0xDC,// LDA #lo, STA $DC04
+            0xA9, timer_hi, 0x8D, 0x05, 0xDC,// LDA #hi, STA $DC05
+            0xA9, 0x81, 0x8D, 0x0D, 0xDC,    // LDA #$81, STA $DC0D
+            0xA9, 0x11, 0x8D, 0x0E, 0xDC,    // LDA #$11, STA $DC0E
+         ]);
+      } else {
+         // VIC raster interrupt once per frame
+         code.extend_from_slice(&[
+            0xA9, 0x7F, 0x8D, 0x0D, 0xDC,    // LDA #$7F, STA $DC0D
+            0xAD, 0x0D, 0xDC,                // LDA $DC0D
+            0xA9, 0x00, 0x8D, 0x12, 0xD0,    // LDA #$00, STA $D012
+            0xAD, 0x11, 0xD0,                // LDA $D011
+            0x29, 0x7F, 0x8D, 0x11, 0xD0,    // AND #$7F, STA $D011
+            0xA9, 0x01, 0x8D, 0x1A, 0xD0,    // LDA #$01, STA $D01A
+         ]);
+      }
+
+      code.push(0x58);                       // CLI
+      let idle = base + code.len() as u16;
+      let [idle_lo, idle_hi] = idle.to_le_bytes();
+      code.extend_from_slice(&[0x4C, idle_lo, idle_hi]); // JMP *
+   }
+}
+
+// $01 value that makes an address visible to the CPU
+fn bank_for(address: u16) -> u8 {
+   match address {
+      0x0000..=0x9FFF => 0x37,
+      0xA000..=0xCFFF => 0x36,
+      0xD000..=0xDFFF => 0x34,
+      _               => 0x35
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // a v2 header with the given magic, load/init/play addresses and song count, then data
+   fn file(magic: &[u8], load: u16, init: u16, play: u16, songs: u16, data: &[u8]) -> Vec<u8> {
+      let mut file = vec![0; V2_HEADER];
+      file[0..4].copy_from_slice(magic);
+      file[0x04..0x06].copy_from_slice(&2u16.to_be_bytes());
+      file[0x06..0x08].copy_from_slice(&(V2_HEADER as u16).to_be_bytes());
+      file[0x08..0x0A].copy_from_slice(&load.to_be_bytes());
+      file[0x0A..0x0C].copy_from_slice(&init.to_be_bytes());
+      file[0x0C..0x0E].copy_from_slice(&play.to_be_bytes());
+      file[0x0E..0x10].copy_from_slice(&songs.to_be_bytes());
+      file[0x10..0x12].copy_from_slice(&2u16.to_be_bytes());
+      file[0x16..0x1B].copy_from_slice(b"Title");
+      file[0x76..0x78].copy_from_slice(&0x0024u16.to_be_bytes());
+      file.extend_from_slice(data);
+      file
+   }
+
+   #[test]
+   fn parses_a_psid_file() {
+      let psid = Psid::parse(&file(PSID_MAGIC, 0, 0, 0x1003, 3, &[0x00, 0x10, 0xEA, 0xEA])).unwrap();
+      assert!(!psid.rsid);
+      assert_eq!(psid.version, 2);
+      // the load address comes from the first two data bytes, init defaults to it
+      assert_eq!((psid.load_address, psid.init_address, psid.play_address), (0x1000, 0x1000, 0x1003));
+      assert_eq!(psid.data, vec![0xEA, 0xEA]);
+      assert_eq!((psid.songs, psid.start_song), (3, 2));
+      assert_eq!(psid.name, "Title");
+      assert_eq!(psid.sid_model(), Some(SidModel::Mos8580));
+   }
+
+   #[test]
+   fn rejects_truncated_files() {
+      let whole = file(PSID_MAGIC, 0x1000, 0x1000, 0x1003, 1, &[0xEA]);
+      assert!(Psid::parse(&whole[..V1_HEADER - 1]).is_err());
+      // v2 header cut off before the data
+      assert!(Psid::parse(&whole[..V2_HEADER - 1]).is_err());
+      assert!(Psid::parse(&file(PSID_MAGIC, 0xFFFF, 0xFFFF, 0, 1, &[0xEA, 0xEA])).is_err());
+      assert!(Psid::parse(&file(b"XSID", 0x1000, 0x1000, 0, 1, &[0xEA])).is_err());
+   }
+
+   #[test]
+   fn parses_rsid_files_without_play_address() {
+      let rsid = Psid::parse(&file(RSID_MAGIC, 0x0801, 0x080D, 0, 1, &[0xEA, 0xEA])).unwrap();
+      assert!(rsid.rsid);
+      assert_eq!((rsid.load_address, rsid.init_address), (0x0801, 0x080D));
+      assert!(Psid::parse(&file(RSID_MAGIC, 0x0801, 0x080D, 0x1003, 1, &[0xEA, 0xEA])).is_err());
+   }
+
+   #[test]
+   fn bank_keeps_the_address_visible() {
+      assert_eq!(bank_for(0x1000), 0x37);
+      assert_eq!(bank_for(0xA000), 0x36);
+      assert_eq!(bank_for(0xD400), 0x34);
+      assert_eq!(bank_for(0xE000), 0x35);
+   }
+}
@@ -0,0 +1,118 @@
+use std::f64::consts::PI;
+
+// taps of the windowed sinc, in intermediate samples
+const TAPS            : usize = 64;
+// fractional positions the kernel is tabulated at, interpolated in between
+const PHASES          : usize = 256;
+// the intermediate rate is about this many times the output rate
+const OVERSAMPLING    : f64 = 4.0;
+// passband edge as a fraction of the output rate
+const CUTOFF          : f64 = 0.42;
+// dynamic rate control never bends the pitch by more than this
+pub const MAX_ADJUST  : f64 = 0.005;
+
+// converts the SID output from the CPU clock down to the audio device rate: a boxcar
+// average decimates to a few times the output rate, then a polyphase windowed sinc
+// band-limits and resamples to the exact (and adjustable) output rate
+pub struct Resampler {
+   output_rate: u32,
+   decimation: usize,
+   step: f64,
+   adjust: f64,
+   sum: f32,
+   count: usize,
+   history: [f32; TAPS],
+   newest: usize,
+   phase: f64,
+   kernel: Vec<f32>,
+   output: Vec<f32>,
+}
+
+impl Resampler {
+   pub fn new(input_rate: u32, output_rate: u32) -> Self {
+      let decimation = ((input_rate as f64 / (output_rate as f64 * OVERSAMPLING)).floor() as usize).max(1);
+      let intermediate_rate = input_rate as f64 / decimation as f64;
+      let cutoff = (CUTOFF * output_rate as f64 / intermediate_rate).min(0.5);
+
+      let mut kernel = vec![0.0f32; (PHASES + 1) * TAPS];
+      for phase in 0..=PHASES {
+         let fraction = phase as f64 / PHASES as f64;
+         for tap in 0..TAPS {
+            let x = tap as f64 + fraction - TAPS as f64 / 2.0;
+            let sinc = if x == 0.0 {
+               2.0 * cutoff
+            } else {
+               (2.0 * PI * cutoff * x).sin() / (PI * x)
+            };
+            // Blackman window over the kernel length
+            let w = (x + TAPS as f64 / 2.0) / TAPS as f64;
+            let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+            kernel[phase * TAPS + tap] = (sinc * window.max(0.0)) as f32;
+         }
+      }
+
+      Self {
+         output_rate: output_rate,
+         decimation: decimation,
+         step: intermediate_rate / output_rate as f64,
+         adjust: 1.0,
+         sum: 0.0,
+         count: 0,
+         history: [0.0; TAPS],
+         newest: 0,
+         phase: 0.0,
+         kernel: kernel,
+         output: Vec::new(),
+      }
+   }
+
+   pub fn output_rate(&self) -> u32 {
+      self.output_rate
+   }
+
+   // > 1.0 produces fewer output samples, < 1.0 more
+   pub fn set_adjust(&mut self, adjust: f64) {
+      self.adjust = adjust.clamp(1.0 - MAX_ADJUST, 1.0 + MAX_ADJUST);
+   }
+
+   pub fn push(&mut self, sample: f32) {
+      self.sum += sample;
+      self.count += 1;
+      if self.count < self.decimation {
+         return;
+      }
+
+      let value = self.sum / self.count as f32;
+      self.sum = 0.0;
+      self.count = 0;
+
+      self.newest = (self.newest + 1) % TAPS;
+      self.history[self.newest] = value;
+
+      self.phase += 1.0;
+      let step = self.step * self.adjust;
+      while self.phase >= step {
+         self.phase -= step;
+         let sample = self.filter(self.phase);
+         self.output.push(sample);
+      }
+   }
+
+   // fraction is how far the newest sample lies past the output instant
+   fn filter(&self, fraction: f64) -> f32 {
+      let position = fraction.min(1.0) * PHASES as f64;
+      let phase = (position as usize).min(PHASES - 1);
+      let t = (position - phase as f64) as f32;
+      let a = &self.kernel[phase * TAPS..(phase + 1) * TAPS];
+      let b = &self.kernel[(phase + 1) * TAPS..(phase + 2) * TAPS];
+
+      a.iter().zip(b).enumerate().map(|(tap, (a, b))| {
+         self.history[(self.newest + TAPS - tap) % TAPS] * (a + (b - a) * t)
+      }).sum()
+   }
+
+   // the samples produced since the last call
+   pub fn take(&mut self) -> Vec<f32> {
+      std::mem::take(&mut self.output)
+   }
+}
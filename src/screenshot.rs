@@ -0,0 +1,154 @@
+use std::fs;
+
+use crate::framebuffer::*;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const STORED_BLOCK : usize = 0xFFFF;
+
+const fn crc_table() -> [u32; 256] {
+   let mut table = [0u32; 256];
+   let mut n = 0;
+   while n < 256 {
+      let mut c = n as u32;
+      let mut k = 0;
+      while k < 8 {
+         c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+         k += 1;
+      }
+      table[n] = c;
+      n += 1;
+   }
+   table
+}
+
+static CRC_TABLE: [u32; 256] = crc_table();
+
+pub fn load_ppm(file_name: &str) -> Result<FrameBuffer, String> {
+   let data = fs::read(file_name).map_err(|e| format!("Image {}: {}", file_name, e))?;
+   decode_ppm(&data).map_err(|e| format!("Image {}: {}", file_name, e))
+}
+
+pub fn save(frame: &FrameBuffer, file_name: &str) -> Result<(), String> {
+   let data = if file_name.to_lowercase().ends_with(".ppm") {
+      encode_ppm(frame)
+   } else {
+      encode_png(frame)
+   };
+   fs::write(file_name, data).map_err(|e| format!("Screenshot {}: {}", file_name, e))
+}
+
+pub fn encode_ppm(frame: &FrameBuffer) -> Vec<u8> {
+   let mut data = format!("P6\n{} {}\n255\n", frame.width(), frame.height()).into_bytes();
+   for pixel in frame.pixels().chunks(4) {
+      data.extend_from_slice(&pixel[0..3]);
+   }
+   data
+}
+
+pub fn decode_ppm(data: &[u8]) -> Result<FrameBuffer, String> {
+   // header is "P6", width, height and maxval separated by whitespace
+   let mut fields = Vec::new();
+   let mut pos = 0;
+   while fields.len() < 4 {
+      while pos < data.len() && (data[pos] as char).is_ascii_whitespace() {
+         pos += 1;
+      }
+      if pos < data.len() && data[pos] == b'#' {
+         while pos < data.len() && data[pos] != b'\n' {
+            pos += 1;
+         }
+         continue;
+      }
+      let start = pos;
+      while pos < data.len() && !(data[pos] as char).is_ascii_whitespace() {
+         pos += 1;
+      }
+      if start == pos {
+         return Err(String::from("Truncated PPM Header"));
+      }
+      fields.push(String::from_utf8_lossy(&data[start..pos]).to_string());
+   }
+   pos += 1;
+
+   if fields[0] != "P6" || fields[3] != "255" {
+      return Err(String::from("Only 8-bit Binary PPM (P6) Is Supported"));
+   }
+   let width: usize = fields[1].parse().map_err(|_| String::from("Bad PPM Width"))?;
+   let height: usize = fields[2].parse().map_err(|_| String::from("Bad PPM Height"))?;
+
+   let rgb = &data[pos.min(data.len())..];
+   if rgb.len() < width * height * 3 {
+      return Err(String::from("Truncated PPM Data"));
+   }
+   let mut pixels = Vec::with_capacity(width * height * 4);
+   for pixel in rgb[..width * height * 3].chunks(3) {
+      pixels.extend_from_slice(pixel);
+      pixels.push(0xFF);
+   }
+   FrameBuffer::from_pixels(width, height, pixels)
+}
+
+pub fn encode_png(frame: &FrameBuffer) -> Vec<u8> {
+   let mut ihdr = Vec::new();
+   ihdr.extend_from_slice(&(frame.width() as u32).to_be_bytes());
+   ihdr.extend_from_slice(&(frame.height() as u32).to_be_bytes());
+   ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);     // 8-bit RGB, no interlace
+
+   let mut raw = Vec::with_capacity((frame.width() * 3 + 1) * frame.height());
+   for row in frame.pixels().chunks(frame.pitch()) {
+      raw.push(0);                               // filter type none
+      for pixel in row.chunks(4) {
+         raw.extend_from_slice(&pixel[0..3]);
+      }
+   }
+
+   let mut data = PNG_SIGNATURE.to_vec();
+   write_chunk(&mut data, b"IHDR", &ihdr);
+   write_chunk(&mut data, b"IDAT", &zlib_stored(&raw));
+   write_chunk(&mut data, b"IEND", &[]);
+   data
+}
+
+fn write_chunk(data: &mut Vec<u8>, kind: &[u8; 4], body: &[u8]) {
+   data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+   let start = data.len();
+   data.extend_from_slice(kind);
+   data.extend_from_slice(body);
+   let crc = crc32(&data[start..]);
+   data.extend_from_slice(&crc.to_be_bytes());
+}
+
+// zlib stream made of uncompressed deflate blocks
+fn zlib_stored(raw: &[u8]) -> Vec<u8> {
+   let mut data = vec![0x78, 0x01];
+   let mut blocks = raw.chunks(STORED_BLOCK).peekable();
+   if blocks.peek().is_none() {
+      data.extend_from_slice(&[1, 0x00, 0x00, 0xFF, 0xFF]);
+   }
+   while let Some(block) = blocks.next() {
+      let last = blocks.peek().is_none();
+      data.push(if last { 1 } else { 0 });
+      data.extend_from_slice(&(block.len() as u16).to_le_bytes());
+      data.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+      data.extend_from_slice(block);
+   }
+   data.extend_from_slice(&adler32(raw).to_be_bytes());
+   data
+}
+
+fn crc32(data: &[u8]) -> u32 {
+   let mut crc = 0xFFFFFFFFu32;
+   for byte in data {
+      crc = CRC_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+   }
+   crc ^ 0xFFFFFFFF
+}
+
+fn adler32(data: &[u8]) -> u32 {
+   let (mut a, mut b) = (1u32, 0u32);
+   for byte in data {
+      a = (a + *byte as u32) % 65521;
+      b = (b + a) % 65521;
+   }
+   (b << 16) | a
+}
@@ -0,0 +1,971 @@
+use std::sync::OnceLock;
+
+use crate::model::*;
+
+const FREQ_LO         : u8 = 0x00;
+const FREQ_HI         : u8 = 0x01;
+const PW_LO           : u8 = 0x02;
+const PW_HI           : u8 = 0x03;
+const CONTROL         : u8 = 0x04;
+const ATTACK_DECAY    : u8 = 0x05;
+const SUSTAIN_RELEASE : u8 = 0x06;
+const VOICE_REGISTERS : u8 = 7;
+const FC_LO           : u8 = 0x15;
+const FC_HI           : u8 = 0x16;
+const RES_FILT        : u8 = 0x17;
+const MODE_VOL        : u8 = 0x18;
+const POTX            : u8 = 0x19;
+const POTY            : u8 = 0x1A;
+const OSC3            : u8 = 0x1B;
+const ENV3            : u8 = 0x1C;
+
+const GATE            : u8 = 0x01;
+const SYNC            : u8 = 0x02;
+const RING            : u8 = 0x04;
+const TEST            : u8 = 0x08;
+const TRIANGLE        : u8 = 0x10;
+const SAWTOOTH        : u8 = 0x20;
+const PULSE           : u8 = 0x40;
+const NOISE           : u8 = 0x80;
+
+const LOW_PASS        : u8 = 0x10;
+const BAND_PASS       : u8 = 0x20;
+const HIGH_PASS       : u8 = 0x40;
+const VOICE3_OFF      : u8 = 0x80;
+
+const NOISE_SEED      : u32 = 0x7FFFF8;
+
+// the pots are measured every 512 cycles: 256 cycles discharging, then 256 counting
+const POT_PERIOD      : u16 = 512;
+const POT_DISCHARGE   : u16 = 256;
+
+// the SID on the mainboard, mirrored over $D400-$D7FF
+pub const SID_ADDRESS : u16 = 0xD400;
+// at most two more SIDs on expansion boards
+pub const EXTRA_SIDS  : usize = 2;
+
+// one voice at full envelope, used to scale the mixer to about -1.0 - 1.0
+const VOICE_SCALE     : f32 = (0x800 * 0xFF) as f32;
+
+// DC blocking high-pass of the C64 audio output stage, about 16 Hz at 1 MHz
+const OUTPUT_HIGH_PASS: f32 = 0.0001;
+
+// filter cutoff in Hz at points of the 11-bit FC register as measured by reSID, with a
+// spline in between; a repeated point keeps the curve straight where it meets it
+const CUTOFF_6581     : [(u16, f32); 31] = [
+   (0, 220.0), (0, 220.0), (128, 230.0), (256, 250.0), (384, 300.0), (512, 420.0),
+   (640, 780.0), (768, 1600.0), (832, 2300.0), (896, 3200.0), (960, 4300.0), (992, 5000.0),
+   (1008, 5400.0), (1016, 5700.0), (1023, 6000.0), (1023, 6000.0), (1024, 4600.0),
+   (1024, 4600.0), (1032, 4800.0), (1056, 5300.0), (1088, 6000.0), (1120, 6600.0),
+   (1152, 7200.0), (1280, 9500.0), (1408, 12000.0), (1536, 14500.0), (1664, 16000.0),
+   (1792, 17100.0), (1920, 17700.0), (2047, 18000.0), (2047, 18000.0)
+];
+const CUTOFF_8580     : [(u16, f32); 29] = [
+   (0, 0.0), (0, 0.0), (128, 800.0), (256, 1600.0), (384, 2500.0), (512, 3300.0),
+   (640, 4100.0), (768, 4800.0), (832, 5200.0), (896, 5600.0), (960, 5900.0), (992, 6100.0),
+   (1008, 6200.0), (1016, 6300.0), (1023, 6300.0), (1024, 6300.0), (1032, 6400.0),
+   (1056, 6500.0), (1088, 6700.0), (1120, 7000.0), (1152, 7100.0), (1280, 7500.0),
+   (1408, 8100.0), (1536, 8700.0), (1664, 9400.0), (1792, 10000.0), (1920, 10500.0),
+   (2047, 11000.0), (2047, 11000.0)
+];
+
+// how hard the 6581 filter's op-amps saturate, the level where they start to round off
+const FILTER_DRIVE    : f32 = 0.5;
+
+// shift register bits that drive waveform output bits 11 down to 4
+const NOISE_TAPS      : [u32; 8] = [22, 20, 16, 13, 11, 7, 4, 2];
+
+// the combined waveforms ST, PT, PS and PST, the order of their tables
+const COMBINED_SELECTS: [u8; 4] = [SAWTOOTH | TRIANGLE, PULSE | TRIANGLE, PULSE | SAWTOOTH, PULSE | SAWTOOTH | TRIANGLE];
+
+// combined waveform model parameters fitted to samples of a 6581 R2 and an 8580 R5 by
+// libsidplayfp (WaveformCalculator, the kevtris chips G and V), in COMBINED_SELECTS order
+const COMBINED_6581   : [CombinedWaveform; 4] = [
+   CombinedWaveform { bias: 0.880815,  pulse_strength: 0.0,      top_bit: 0.0,      distance: 0.3279614,  st_mix: 0.5999545 },
+   CombinedWaveform { bias: 0.8924618, pulse_strength: 2.014781, top_bit: 1.003332, distance: 0.02992322, st_mix: 0.0 },
+   CombinedWaveform { bias: 0.8646501, pulse_strength: 1.712586, top_bit: 1.137704, distance: 0.02845423, st_mix: 0.0 },
+   CombinedWaveform { bias: 0.9527834, pulse_strength: 1.794777, top_bit: 0.0,      distance: 0.09806272, st_mix: 0.7752482 }
+];
+const COMBINED_8580   : [CombinedWaveform; 4] = [
+   CombinedWaveform { bias: 0.9781665, pulse_strength: 0.0,      top_bit: 0.9899469, distance: 8.087667,  st_mix: 0.8226412 },
+   CombinedWaveform { bias: 0.9097769, pulse_strength: 2.039997, top_bit: 0.9584096, distance: 0.1765447, st_mix: 0.0 },
+   CombinedWaveform { bias: 0.9231212, pulse_strength: 2.084788, top_bit: 0.9493895, distance: 0.1712518, st_mix: 0.0 },
+   CombinedWaveform { bias: 0.9845552, pulse_strength: 1.415612, top_bit: 0.9703883, distance: 3.68829,   st_mix: 0.8265501 }
+];
+
+// cycles between envelope steps for each of the 16 rate settings
+const RATE_PERIODS    : [u16; 16] = [
+   9, 32, 63, 95, 149, 220, 267, 313, 392, 977, 1954, 3126, 3907, 11720, 19532, 31251
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EnvelopeState {
+   Attack,
+   DecaySustain,
+   Release,
+}
+
+#[derive(Clone, Copy)]
+struct Envelope {
+   state: EnvelopeState,
+   counter: u8,
+   rate_counter: u16,
+   exponential_counter: u8,
+   exponential_period: u8,
+   hold_zero: bool,
+   gate: bool,
+   attack_decay: u8,
+   sustain_release: u8,
+}
+
+impl Envelope {
+   fn new() -> Self {
+      Self {
+         state: EnvelopeState::Release,
+         counter: 0,
+         rate_counter: 0,
+         exponential_counter: 0,
+         exponential_period: 1,
+         hold_zero: true,
+         gate: false,
+         attack_decay: 0,
+         sustain_release: 0,
+      }
+   }
+
+   fn set_gate(&mut self, gate: bool) {
+      if gate && !self.gate {
+         self.state = EnvelopeState::Attack;
+         self.hold_zero = false;
+      } else if !gate && self.gate {
+         self.state = EnvelopeState::Release;
+      }
+      self.gate = gate;
+   }
+
+   fn rate_period(&self) -> u16 {
+      let rate = match self.state {
+         EnvelopeState::Attack       => self.attack_decay >> 4,
+         EnvelopeState::DecaySustain => self.attack_decay & 0x0F,
+         EnvelopeState::Release      => self.sustain_release & 0x0F,
+      };
+      RATE_PERIODS[rate as usize]
+   }
+
+   fn clock(&mut self) {
+      // the 15-bit rate counter only resets when it equals the period, so lowering the
+      // period below the current count makes it run through 0x7FFF first (the ADSR delay bug)
+      self.rate_counter += 1;
+      if self.rate_counter & 0x8000 != 0 {
+         self.rate_counter = (self.rate_counter + 1) & 0x7FFF;
+      }
+      if self.rate_counter != self.rate_period() {
+         return;
+      }
+      self.rate_counter = 0;
+
+      if self.state != EnvelopeState::Attack {
+         self.exponential_counter += 1;
+         if self.exponential_counter != self.exponential_period {
+            return;
+         }
+      }
+      self.exponential_counter = 0;
+
+      if self.hold_zero {
+         return;
+      }
+
+      match self.state {
+         EnvelopeState::Attack       => {
+            self.counter = self.counter.wrapping_add(1);
+            if self.counter == 0xFF {
+               self.state = EnvelopeState::DecaySustain;
+            }
+         },
+         EnvelopeState::DecaySustain => {
+            if self.counter != (self.sustain_release >> 4) * 0x11 {
+               self.counter = self.counter.wrapping_sub(1);
+            }
+         },
+         EnvelopeState::Release      => self.counter = self.counter.wrapping_sub(1),
+      }
+
+      // piecewise exponential decay
+      self.exponential_period = match self.counter {
+         0xFF => 1,
+         0x5D => 2,
+         0x36 => 4,
+         0x1A => 8,
+         0x0E => 16,
+         0x06 => 30,
+         0x00 => {
+            self.hold_zero = true;
+            1
+         },
+         _    => self.exponential_period
+      };
+   }
+}
+
+#[derive(Clone, Copy)]
+struct Voice {
+   accumulator: u32,
+   shift_register: u32,
+   frequency: u16,
+   pulse_width: u16,
+   control: u8,
+   msb_rising: bool,
+   waveform: u16,
+   envelope: Envelope,
+}
+
+impl Voice {
+   fn new() -> Self {
+      Self {
+         accumulator: 0,
+         shift_register: NOISE_SEED,
+         frequency: 0,
+         pulse_width: 0,
+         control: 0,
+         msb_rising: false,
+         waveform: 0,
+         envelope: Envelope::new(),
+      }
+   }
+
+   fn set_control(&mut self, value: u8) {
+      if value & TEST != 0 {
+         self.accumulator = 0;
+         self.shift_register = NOISE_SEED;
+      }
+      self.control = value;
+      self.envelope.set_gate(value & GATE != 0);
+   }
+
+   fn clock_oscillator(&mut self) {
+      self.msb_rising = false;
+      if self.control & TEST != 0 {
+         return;
+      }
+
+      let previous = self.accumulator;
+      self.accumulator = (self.accumulator + self.frequency as u32) & 0xFFFFFF;
+      self.msb_rising = previous & 0x800000 == 0 && self.accumulator & 0x800000 != 0;
+
+      // the noise LFSR is clocked by bit 19 of the accumulator
+      if previous & 0x080000 == 0 && self.accumulator & 0x080000 != 0 {
+         let feedback = ((self.shift_register >> 22) ^ (self.shift_register >> 17)) & 1;
+         self.shift_register = ((self.shift_register << 1) | feedback) & 0x7FFFFF;
+      }
+   }
+
+   fn noise(&self) -> u16 {
+      NOISE_TAPS.iter().enumerate().fold(0, |output, (bit, &tap)| {
+         output | ((((self.shift_register >> tap) & 1) as u16) << (11 - bit))
+      })
+   }
+
+   // 12-bit waveform output; source is the accumulator of the ring modulating voice
+   fn update_waveform(&mut self, source: u32, model: SidModel) {
+      let select = self.control & 0xF0;
+      if select == 0 {
+         // no waveform selected: the DAC holds its last value
+         return;
+      }
+
+      let accumulator = self.accumulator;
+      let saw = (accumulator >> 12) as u16;
+      // ring modulation swaps the msb the triangle folds at for the XOR with the source's,
+      // unless the sawtooth is selected too
+      let msb = if self.control & RING != 0 && select & SAWTOOTH == 0 {
+         (accumulator ^ source) & 0x800000
+      } else {
+         accumulator & 0x800000
+      };
+      let mut output = match select & (PULSE | SAWTOOTH | TRIANGLE) {
+         0 | PULSE => 0x0FFF,
+         TRIANGLE  => {
+            let value = if msb != 0 { !accumulator } else { accumulator };
+            ((value >> 11) & 0x0FFF) as u16
+         },
+         SAWTOOTH  => saw,
+         combined  => {
+            let table = COMBINED_SELECTS.iter().position(|&select| select == combined).unwrap();
+            combined_waveforms(model)[table][((saw & 0x07FF) | (msb >> 12) as u16) as usize]
+         }
+      };
+      if select & PULSE != 0 && self.control & TEST == 0 && saw < self.pulse_width {
+         output = 0;
+      }
+      if select & NOISE != 0 {
+         output &= self.noise();
+         // the pulled down outputs are written back into the noise shift register
+         if select != NOISE {
+            for (bit, &tap) in NOISE_TAPS.iter().enumerate() {
+               if output & (1 << (11 - bit)) == 0 {
+                  self.shift_register &= !(1 << tap);
+               }
+            }
+         }
+      }
+      self.waveform = output;
+   }
+
+   fn output(&self, model: SidModel) -> i32 {
+      (self.waveform as i32 - model.waveform_zero()) * self.envelope.counter as i32 + model.voice_dc()
+   }
+}
+
+// the combined waveforms are mixed in analog: a selected output bit is pulled towards its
+// neighbours, more the closer they are, and the pulse pulls on all of them from above
+#[derive(Clone, Copy)]
+struct CombinedWaveform {
+   // level a mixed bit must exceed to read high
+   bias: f32,
+   // pull of the pulse output, from just above bit 11
+   pulse_strength: f32,
+   // weight of the sawtooth's bit 11
+   top_bit: f32,
+   // how fast a neighbour's pull falls off, a bit i away weighs 1 / (1 + i² distance)
+   distance: f32,
+   // with the sawtooth, how much of a triangle bit is its own rather than the one below
+   st_mix: f32,
+}
+
+static WAVEFORMS_6581 : OnceLock<Vec<[u16; 4096]>> = OnceLock::new();
+static WAVEFORMS_8580 : OnceLock<Vec<[u16; 4096]>> = OnceLock::new();
+
+// the combined waveform outputs at each sawtooth value, built on first use
+fn combined_waveforms(model: SidModel) -> &'static [[u16; 4096]] {
+   let (tables, parameters) = match model {
+      SidModel::Mos6581 => (&WAVEFORMS_6581, &COMBINED_6581),
+      SidModel::Mos8580 => (&WAVEFORMS_8580, &COMBINED_8580),
+   };
+   tables.get_or_init(|| {
+      parameters.iter().zip(COMBINED_SELECTS).map(|(parameters, select)| {
+         let mut table = [0; 4096];
+         for (saw, output) in table.iter_mut().enumerate() {
+            *output = combined_waveform(parameters, select, saw as u16);
+         }
+         table
+      }).collect()
+   })
+}
+
+fn combined_waveform(parameters: &CombinedWaveform, select: u8, saw: u16) -> u16 {
+   let mut bits = [0.0f32; 12];
+   for (bit, level) in bits.iter_mut().enumerate() {
+      *level = ((saw >> bit) & 1) as f32;
+   }
+   if select & SAWTOOTH == 0 {
+      // the triangle is the sawtooth shifted up, inverted while its msb is set
+      let top = saw & 0x0800 != 0;
+      for bit in (1..12).rev() {
+         bits[bit] = if top { 1.0 - bits[bit - 1] } else { bits[bit - 1] };
+      }
+      bits[0] = 0.0;
+   } else {
+      if select & TRIANGLE != 0 {
+         // the sawtooth holds the triangle's selector low, so each bit mixes with the one below
+         bits[0] *= parameters.st_mix;
+         for bit in 1..12 {
+            bits[bit] = bits[bit - 1] * (1.0 - parameters.st_mix) + bits[bit] * parameters.st_mix;
+         }
+      }
+      bits[11] *= parameters.top_bit;
+   }
+
+   let weight = |from: usize, to: usize| 1.0 / (1.0 + (from as f32 - to as f32).powi(2) * parameters.distance);
+   (0..12).fold(0, |output, bit| {
+      let (mut sum, mut total) = (0.0, 0.0);
+      for (other, level) in bits.iter().enumerate() {
+         sum += level * weight(bit, other);
+         total += weight(bit, other);
+      }
+      if select & PULSE != 0 {
+         sum += parameters.pulse_strength * weight(bit, 12);
+         total += weight(bit, 12);
+      }
+      if (bits[bit] + sum / total) * 0.5 > parameters.bias {
+         output | (1 << bit)
+      } else {
+         output
+      }
+   })
+}
+
+// state-variable filter, integrated once per cycle
+#[derive(Clone, Copy)]
+struct Filter {
+   model: SidModel,
+   // φ2 in Hz, the rate the filter is integrated at
+   cpu_clock: f32,
+   cutoff: u16,
+   res_filt: u8,
+   mode_volume: u8,
+   w0: f32,
+   damping: f32,
+   low: f32,
+   band: f32,
+   high: f32,
+}
+
+impl Filter {
+   fn new(model: SidModel, cpu_clock: u32) -> Self {
+      let mut filter = Self {
+         model: model,
+         cpu_clock: cpu_clock as f32,
+         cutoff: 0,
+         res_filt: 0,
+         mode_volume: 0,
+         w0: 0.0,
+         damping: 0.0,
+         low: 0.0,
+         band: 0.0,
+         high: 0.0,
+      };
+      filter.update();
+      filter
+   }
+
+   fn update(&mut self) {
+      let points: &[(u16, f32)] = match self.model {
+         SidModel::Mos6581 => &CUTOFF_6581,
+         SidModel::Mos8580 => &CUTOFF_8580,
+      };
+      let frequency = interpolate(points, self.cutoff);
+      // above 16 kHz a single step per cycle is no longer stable
+      self.w0 = 2.0 * std::f32::consts::PI * frequency.min(16000.0) / self.cpu_clock;
+
+      let resonance = (self.res_filt >> 4) as f32 / 15.0;
+      let q = match self.model {
+         SidModel::Mos6581 => 0.707 + resonance,
+         SidModel::Mos8580 => 0.707 + resonance * 1.7,
+      };
+      self.damping = 1.0 / q;
+   }
+
+   // voices routed through the filter go in, the selected outputs come out
+   fn clock(&mut self, input: f32) -> f32 {
+      // the 6581 integrators' op-amps saturate, so loud signals are distorted on their way
+      // in and slewed slower; the 8580's stay linear
+      let (high, band) = match self.model {
+         SidModel::Mos6581 => (saturate(self.high), saturate(self.band)),
+         SidModel::Mos8580 => (self.high, self.band),
+      };
+      self.band -= self.w0 * high;
+      self.low -= self.w0 * band;
+      self.high = self.band * self.damping - self.low - input;
+
+      let mut output = 0.0;
+      if self.mode_volume & LOW_PASS != 0 {
+         output += self.low;
+      }
+      if self.mode_volume & BAND_PASS != 0 {
+         output += self.band;
+      }
+      if self.mode_volume & HIGH_PASS != 0 {
+         output += self.high;
+      }
+      output
+   }
+}
+
+fn saturate(level: f32) -> f32 {
+   (level * FILTER_DRIVE).tanh() / FILTER_DRIVE
+}
+
+// reSID's spline: a cubic from each point to the next with the slopes taken from the points
+// around them, straight towards a repeated point and a line between two
+fn interpolate(points: &[(u16, f32)], x: u16) -> f32 {
+   for window in points.windows(4) {
+      let [(x0, y0), (x1, y1), (x2, y2), (x3, y3)] = [window[0], window[1], window[2], window[3]]
+         .map(|(x, y)| (x as f32, y));
+      if x1 == x2 || x as f32 > x2 {
+         continue;
+      }
+      let slope = (y2 - y1) / (x2 - x1);
+      let (k1, k2) = match (x0 == x1, x2 == x3) {
+         (true, true)   => (slope, slope),
+         (true, false)  => {
+            let k2 = (y3 - y1) / (x3 - x1);
+            ((3.0 * slope - k2) / 2.0, k2)
+         },
+         (false, true)  => {
+            let k1 = (y2 - y0) / (x2 - x0);
+            (k1, (3.0 * slope - k1) / 2.0)
+         },
+         (false, false) => ((y2 - y0) / (x2 - x0), (y3 - y1) / (x3 - x1)),
+      };
+      // cubic Hermite from (x1, y1) to (x2, y2)
+      let h = x2 - x1;
+      let t = (x as f32 - x1) / h;
+      let (t2, t3) = (t * t, t * t * t);
+      return (2.0 * t3 - 3.0 * t2 + 1.0) * y1 + (t3 - 2.0 * t2 + t) * h * k1
+         + (3.0 * t2 - 2.0 * t3) * y2 + (t3 - t2) * h * k2;
+   }
+   points[points.len() - 1].1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SidPan {
+   Left,
+   Center,
+   Right
+}
+
+impl SidPan {
+   pub fn from_name(name: &str) -> Result<SidPan, String> {
+      match name.to_lowercase().as_str() {
+         "left" | "l"   => Ok(SidPan::Left),
+         "center" | "c" => Ok(SidPan::Center),
+         "right" | "r"  => Ok(SidPan::Right),
+         _              => Err(format!("Unknown SID Panning: {}", name))
+      }
+   }
+
+   // (left, right) gain
+   pub fn gains(&self) -> (f32, f32) {
+      match self {
+         SidPan::Left   => (1.0, 0.0),
+         SidPan::Center => (1.0, 1.0),
+         SidPan::Right  => (0.0, 1.0),
+      }
+   }
+}
+
+// where a second or third SID can be decoded: every $20 bytes in the SID area
+// above $D400 or in the I/O1/I/O2 expansion areas
+pub fn extra_sid_address(address: u16) -> bool {
+   address & 0x1F == 0 && matches!(address, 0xD420..=0xD7E0 | 0xDE00..=0xDFE0)
+}
+
+#[derive(Clone, Copy)]
+pub struct Sid {
+   model: SidModel,
+   voices: [Voice; 3],
+   filter: Filter,
+   bus_value: u8,
+   bus_ttl: u32,
+   dc_input: f32,
+   dc_output: f32,
+   sample: f32,
+   // the counts at which the POTX/POTY capacitors reach the threshold, 0xFF for open inputs
+   pot_inputs: [u8; 2],
+   pot_values: [u8; 2],
+   pot_measured: [Option<u8>; 2],
+   pot_cycle: u16,
+}
+
+impl Sid {
+   // cpu_clock is φ2 in Hz, which differs between PAL and NTSC machines
+   pub fn new(model: SidModel, cpu_clock: u32) -> Self {
+      Self {
+         model: model,
+         voices: [Voice::new(); 3],
+         filter: Filter::new(model, cpu_clock),
+         bus_value: 0,
+         bus_ttl: 0,
+         dc_input: 0.0,
+         dc_output: 0.0,
+         sample: 0.0,
+         pot_inputs: [0xFF; 2],
+         pot_values: [0xFF; 2],
+         pot_measured: [None; 2],
+         pot_cycle: 0,
+      }
+   }
+
+   pub fn model(&self) -> SidModel {
+      self.model
+   }
+
+   pub fn read(&mut self, reg: u8) -> u8 {
+      match reg & 0x1F {
+         POTX        => self.pot_values[0],
+         POTY        => self.pot_values[1],
+         OSC3        => (self.voices[2].waveform >> 4) as u8,
+         ENV3        => self.voices[2].envelope.counter,
+         // write-only registers return what is left on the data bus
+         _           => self.bus_value
+      }
+   }
+
+   pub fn write(&mut self, reg: u8, value: u8) {
+      self.bus_value = value;
+      self.bus_ttl = self.model.bus_value_ttl();
+
+      let reg = reg & 0x1F;
+      let filter = &mut self.filter;
+      match reg {
+         FC_LO    => filter.cutoff = (filter.cutoff & 0x7F8) | (value as u16 & 0x07),
+         FC_HI    => filter.cutoff = (filter.cutoff & 0x007) | ((value as u16) << 3),
+         RES_FILT => filter.res_filt = value,
+         MODE_VOL => filter.mode_volume = value,
+         _        => {}
+      }
+      if reg >= VOICE_REGISTERS * 3 {
+         filter.update();
+         return;
+      }
+      let voice = &mut self.voices[(reg / VOICE_REGISTERS) as usize];
+      match reg % VOICE_REGISTERS {
+         FREQ_LO         => voice.frequency = (voice.frequency & 0xFF00) | value as u16,
+         FREQ_HI         => voice.frequency = (voice.frequency & 0x00FF) | ((value as u16) << 8),
+         PW_LO           => voice.pulse_width = (voice.pulse_width & 0x0F00) | value as u16,
+         PW_HI           => voice.pulse_width = (voice.pulse_width & 0x00FF) | ((value as u16 & 0x0F) << 8),
+         CONTROL         => voice.set_control(value),
+         ATTACK_DECAY    => voice.envelope.attack_decay = value,
+         SUSTAIN_RELEASE => voice.envelope.sustain_release = value,
+         _               => {}
+      }
+   }
+
+   // one φ2 cycle
+   pub fn clock(&mut self) {
+      if self.bus_ttl > 0 {
+         self.bus_ttl -= 1;
+         if self.bus_ttl == 0 {
+            self.bus_value = 0;
+         }
+      }
+
+      for voice in self.voices.iter_mut() {
+         voice.envelope.clock();
+         voice.clock_oscillator();
+      }
+
+      // hard sync: each voice is reset by the MSB of the voice before it
+      for n in 0..3 {
+         let source = (n + 2) % 3;
+         if self.voices[n].control & SYNC != 0 && self.voices[source].msb_rising {
+            self.voices[n].accumulator = 0;
+         }
+      }
+
+      for n in 0..3 {
+         let source = self.voices[(n + 2) % 3].accumulator;
+         self.voices[n].update_waveform(source, self.model);
+      }
+
+      self.clock_pots();
+      self.mix();
+   }
+
+   pub fn set_pot_inputs(&mut self, inputs: [u8; 2]) {
+      self.pot_inputs = inputs;
+   }
+
+   // the count latches when the capacitor crosses the threshold and the registers take it
+   // at the end of the period, so a change shows up within 512 cycles
+   fn clock_pots(&mut self) {
+      if self.pot_cycle >= POT_DISCHARGE {
+         let count = (self.pot_cycle - POT_DISCHARGE) as u8;
+         for (measured, &input) in self.pot_measured.iter_mut().zip(&self.pot_inputs) {
+            if measured.is_none() && count >= input {
+               *measured = Some(count);
+            }
+         }
+      }
+      self.pot_cycle += 1;
+      if self.pot_cycle == POT_PERIOD {
+         self.pot_cycle = 0;
+         for (value, measured) in self.pot_values.iter_mut().zip(self.pot_measured.iter_mut()) {
+            *value = measured.take().unwrap_or(0xFF);
+         }
+      }
+   }
+
+   fn mix(&mut self) {
+      let routing = self.filter.res_filt;
+      let mode_volume = self.filter.mode_volume;
+      let mut filtered = 0.0;
+      let mut direct = 0.0;
+
+      for (n, voice) in self.voices.iter().enumerate() {
+         let value = voice.output(self.model) as f32 / VOICE_SCALE;
+         if routing & (1 << n) != 0 {
+            filtered += value;
+         } else if n != 2 || mode_volume & VOICE3_OFF == 0 {
+            direct += value;
+         }
+      }
+
+      let mixed = direct + self.filter.clock(filtered) + self.model.mixer_dc();
+      let output = mixed * (mode_volume & 0x0F) as f32 / 15.0;
+
+      // the output stage removes DC, this is what makes volume register samples audible
+      self.dc_output = (1.0 - OUTPUT_HIGH_PASS) * (self.dc_output + output - self.dc_input);
+      self.dc_input = output;
+      self.sample = self.dc_output / 3.0;
+   }
+
+   // the last output sample, roughly -1.0 - 1.0
+   pub fn output(&self) -> f32 {
+      self.sample
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // cycles until the envelope counter changes
+   fn step(envelope: &mut Envelope) -> u32 {
+      let counter = envelope.counter;
+      let mut cycles = 0;
+      while envelope.counter == counter {
+         envelope.clock();
+         cycles += 1;
+      }
+      cycles
+   }
+
+   const PAL_CLOCK  : u32 = 985248;
+   const NTSC_CLOCK : u32 = 1022727;
+
+   // an 8580 filter at about 1 kHz with the outputs in mode
+   fn filter(cpu_clock: u32, mode: u8) -> Filter {
+      let mut filter = Filter::new(SidModel::Mos8580, cpu_clock);
+      filter.cutoff = 159;
+      filter.mode_volume = mode;
+      filter.update();
+      filter
+   }
+
+   // output over input level of a sine at frequency
+   fn gain(filter: &mut Filter, cpu_clock: u32, frequency: f32, amplitude: f32) -> f32 {
+      let (mut input, mut output) = (0.0, 0.0);
+      for cycle in 0..cpu_clock / 5 {
+         let x = amplitude * (2.0 * std::f32::consts::PI * frequency * cycle as f32 / cpu_clock as f32).sin();
+         let y = filter.clock(x);
+         // after the filter has settled
+         if cycle >= cpu_clock / 10 {
+            input += x * x;
+            output += y * y;
+         }
+      }
+      (output / input).sqrt()
+   }
+
+   #[test]
+   fn filter_cutoff_follows_the_cpu_clock() {
+      let cutoff = interpolate(&CUTOFF_8580, 159);
+      for cpu_clock in [PAL_CLOCK, NTSC_CLOCK] {
+         let w0 = 2.0 * std::f32::consts::PI * cutoff / cpu_clock as f32;
+         assert!((filter(cpu_clock, LOW_PASS).w0 - w0).abs() < 1e-7);
+      }
+      // the same cutoff on both machines
+      let pal_gain = gain(&mut filter(PAL_CLOCK, BAND_PASS), PAL_CLOCK, 1000.0, 1.0);
+      let ntsc_gain = gain(&mut filter(NTSC_CLOCK, BAND_PASS), NTSC_CLOCK, 1000.0, 1.0);
+      assert!((pal_gain - ntsc_gain).abs() < 0.01, "{} {}", pal_gain, ntsc_gain);
+   }
+
+   #[test]
+   fn low_and_high_pass_split_at_the_cutoff() {
+      assert!(gain(&mut filter(PAL_CLOCK, LOW_PASS), PAL_CLOCK, 100.0, 1.0) > 0.95);
+      assert!(gain(&mut filter(PAL_CLOCK, LOW_PASS), PAL_CLOCK, 8000.0, 1.0) < 0.05);
+      assert!(gain(&mut filter(PAL_CLOCK, HIGH_PASS), PAL_CLOCK, 100.0, 1.0) < 0.05);
+      assert!(gain(&mut filter(PAL_CLOCK, HIGH_PASS), PAL_CLOCK, 8000.0, 1.0) > 0.95);
+   }
+
+   // the cutoff in Hz an FC value sets on a model
+   fn cutoff(model: SidModel, fc: u16) -> f32 {
+      let mut filter = Filter::new(model, PAL_CLOCK);
+      filter.cutoff = fc;
+      filter.update();
+      filter.w0 * PAL_CLOCK as f32 / (2.0 * std::f32::consts::PI)
+   }
+
+   #[test]
+   fn cutoff_curves_differ_per_model() {
+      let close = |hz: f32, expected: f32| (hz - expected).abs() <= expected * 0.01 + 0.5;
+      for (fc, expected) in [(0, 220.0), (640, 780.0), (1023, 6000.0), (1024, 4600.0), (1280, 9500.0)] {
+         let hz = cutoff(SidModel::Mos6581, fc);
+         assert!(close(hz, expected), "6581 FC {}: {}", fc, hz);
+      }
+      for (fc, expected) in [(0, 0.0), (128, 800.0), (1024, 6300.0), (1536, 8700.0), (2047, 11000.0)] {
+         let hz = cutoff(SidModel::Mos8580, fc);
+         assert!(close(hz, expected), "8580 FC {}: {}", fc, hz);
+      }
+      // the 6581's curve bends up steeply through the middle, between the measured points too
+      let (low, middle, high) = (cutoff(SidModel::Mos6581, 768), cutoff(SidModel::Mos6581, 800), cutoff(SidModel::Mos6581, 832));
+      assert!(low < middle && middle < high, "{} {} {}", low, middle, high);
+      // its top end is more than the filter can integrate per cycle
+      assert!(close(cutoff(SidModel::Mos6581, 2047), 16000.0));
+   }
+
+   #[test]
+   fn only_the_6581_filter_distorts_loud_signals() {
+      let resonant_gain = |model: SidModel, amplitude: f32| {
+         let mut filter = Filter::new(model, PAL_CLOCK);
+         filter.cutoff = 1024;
+         filter.res_filt = 0xF0;
+         filter.mode_volume = BAND_PASS;
+         filter.update();
+         gain(&mut filter, PAL_CLOCK, cutoff(model, 1024), amplitude)
+      };
+      let (quiet, loud) = (resonant_gain(SidModel::Mos8580, 0.1), resonant_gain(SidModel::Mos8580, 3.0));
+      assert!((quiet - loud).abs() < quiet * 0.01, "{} {}", quiet, loud);
+      let (quiet, loud) = (resonant_gain(SidModel::Mos6581, 0.1), resonant_gain(SidModel::Mos6581, 3.0));
+      assert!(loud < quiet * 0.9, "{} {}", quiet, loud);
+   }
+
+   #[test]
+   fn attack_steps_at_the_rate_period() {
+      let mut envelope = Envelope::new();
+      envelope.attack_decay = 0x00;
+      envelope.set_gate(true);
+      assert_eq!(step(&mut envelope), 9);
+      assert_eq!(step(&mut envelope), 9);
+      assert_eq!(envelope.counter, 2);
+   }
+
+   #[test]
+   fn lowering_the_rate_below_the_count_delays_the_next_step() {
+      let mut envelope = Envelope::new();
+      envelope.attack_decay = 0xF0;
+      envelope.set_gate(true);
+      for _ in 0..20000 {
+         envelope.clock();
+      }
+      assert_eq!(envelope.counter, 0);
+      // the rate counter has to wrap around at 0x7FFF before it can match 9
+      envelope.attack_decay = 0x00;
+      assert_eq!(step(&mut envelope), 0x7FFF - 20000 + 9);
+      assert_eq!(step(&mut envelope), 9);
+   }
+
+   #[test]
+   fn release_slows_down_exponentially() {
+      let mut envelope = Envelope::new();
+      envelope.sustain_release = 0xF0;
+      envelope.set_gate(true);
+      while envelope.state == EnvelopeState::Attack {
+         envelope.clock();
+      }
+      envelope.set_gate(false);
+
+      let mut periods = Vec::new();
+      while envelope.counter != 0 {
+         let counter = envelope.counter;
+         periods.push((counter, step(&mut envelope)));
+      }
+      let period = |counter: u8| periods.iter().find(|&&(c, _)| c == counter).unwrap().1;
+      assert_eq!([0xFF, 0x5E, 0x5D, 0x37, 0x36, 0x1A, 0x0E, 0x06, 0x01].map(period),
+                 [9, 9, 18, 18, 36, 72, 144, 270, 270]);
+
+      // and stays at zero
+      for _ in 0..100000 {
+         envelope.clock();
+      }
+      assert_eq!(envelope.counter, 0);
+   }
+
+   #[test]
+   fn decay_stops_at_the_sustain_level() {
+      let mut envelope = Envelope::new();
+      envelope.sustain_release = 0xA0;
+      envelope.set_gate(true);
+      for _ in 0..1000000 {
+         envelope.clock();
+      }
+      assert_eq!(envelope.state, EnvelopeState::DecaySustain);
+      assert_eq!(envelope.counter, 0xAA);
+   }
+
+   #[test]
+   fn pots_are_measured_every_512_cycles() {
+      let mut sid = Sid::new(SidModel::Mos6581, PAL_CLOCK);
+      sid.set_pot_inputs([0x40, 0xFF]);
+      for _ in 0..POT_PERIOD - 1 {
+         sid.clock();
+      }
+      assert_eq!((sid.read(POTX), sid.read(POTY)), (0xFF, 0xFF));
+      sid.clock();
+      assert_eq!((sid.read(POTX), sid.read(POTY)), (0x40, 0xFF));
+
+      // the registers hold the last count until the window ends
+      for _ in 0..100 {
+         sid.clock();
+      }
+      sid.set_pot_inputs([0x20, 0x10]);
+      for _ in 100..POT_PERIOD - 1 {
+         sid.clock();
+         assert_eq!((sid.read(POTX), sid.read(POTY)), (0x40, 0xFF));
+      }
+      sid.clock();
+      assert_eq!((sid.read(POTX), sid.read(POTY)), (0x20, 0x10));
+   }
+
+   #[test]
+   fn pots_read_the_full_range() {
+      let mut sid = Sid::new(SidModel::Mos8580, PAL_CLOCK);
+      for inputs in [[0x00, 0xFE], [0x01, 0xFF]] {
+         sid.set_pot_inputs(inputs);
+         for _ in 0..POT_PERIOD {
+            sid.clock();
+         }
+         assert_eq!([sid.read(POTX), sid.read(POTY)], inputs);
+      }
+   }
+
+   // the waveform output with the accumulator at a 12-bit sawtooth value and the pulse high
+   fn waveform(model: SidModel, control: u8, saw: u16) -> u16 {
+      let mut voice = Voice::new();
+      voice.control = control;
+      voice.accumulator = (saw as u32) << 12;
+      voice.update_waveform(0, model);
+      voice.waveform
+   }
+
+   #[test]
+   fn combined_waveforms_differ_per_model() {
+      // the 6581's sawtooth + triangle only sets the bits below the lowest clear one, and
+      // never bit 11; in the sampled tables it peaks at $7F in the top byte
+      for (saw, output) in [(0x07F, 0x03C), (0x0FF, 0x07C), (0x3FF, 0x3FC), (0x7FF, 0x7FC), (0x800, 0x000), (0xFFF, 0x7FC)] {
+         assert_eq!(waveform(SidModel::Mos6581, SAWTOOTH | TRIANGLE, saw), output, "{:03X}", saw);
+      }
+      // the 8580's reaches full scale towards the top of the ramp
+      for (saw, output) in [(0x0FF, 0x07C), (0x7FF, 0x3FC), (0xFC0, 0xF00), (0xFFF, 0xFFC)] {
+         assert_eq!(waveform(SidModel::Mos8580, SAWTOOTH | TRIANGLE, saw), output, "{:03X}", saw);
+      }
+      // pulse + sawtooth on the 6581 keeps more of the low half than on the 8580
+      assert_eq!(waveform(SidModel::Mos6581, PULSE | SAWTOOTH, 0x3FF), 0x3FF);
+      assert_eq!(waveform(SidModel::Mos8580, PULSE | SAWTOOTH, 0x3FF), 0x1FF);
+      // pulse + triangle is only heard around the peak of the triangle
+      for model in [SidModel::Mos6581, SidModel::Mos8580] {
+         assert_eq!(waveform(model, PULSE | TRIANGLE, 0x7FF), 0xFFE);
+         assert_eq!(waveform(model, PULSE | TRIANGLE, 0xBFF), 0x000);
+      }
+   }
+
+   #[test]
+   fn combined_waveforms_are_silent_while_the_pulse_is_low() {
+      let mut voice = Voice::new();
+      voice.control = PULSE | SAWTOOTH;
+      voice.pulse_width = 0x800;
+      voice.accumulator = 0x7FF << 12;
+      voice.update_waveform(0, SidModel::Mos8580);
+      assert_eq!(voice.waveform, 0);
+      voice.accumulator = 0xFFF << 12;
+      voice.update_waveform(0, SidModel::Mos8580);
+      assert_eq!(voice.waveform, 0xFFF);
+   }
+
+   #[test]
+   fn noise_reads_the_shift_register_taps() {
+      let mut voice = Voice::new();
+      voice.control = NOISE;
+      for (bit, tap) in [22, 20, 16, 13, 11, 7, 4, 2].into_iter().enumerate() {
+         voice.shift_register = 1 << tap;
+         voice.update_waveform(0, SidModel::Mos6581);
+         assert_eq!(voice.waveform, 0x800 >> bit);
+      }
+      // the bits in between don't show
+      voice.shift_register = !0x512894 & 0x7FFFFF;
+      voice.update_waveform(0, SidModel::Mos6581);
+      assert_eq!(voice.waveform, 0);
+   }
+}
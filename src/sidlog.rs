@@ -0,0 +1,204 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+// $D400-$D418, the registers a player writes
+const SID_REGISTERS   : usize = 0x19;
+
+struct SidWrite {
+   cycle: u64,
+   address: u16,
+   value: u8,
+}
+
+struct Output {
+   file: BufWriter<File>,
+   file_name: String,
+}
+
+impl Output {
+   fn create(file_name: &str) -> Result<Self, String> {
+      let file = File::create(file_name).map_err(|e| format!("SID Log {}: {}", file_name, e))?;
+      Ok(Self {
+         file: BufWriter::new(file),
+         file_name: file_name.to_string(),
+      })
+   }
+
+   fn write(&mut self, data: &[u8]) -> Result<(), String> {
+      self.file.write_all(data).map_err(|e| format!("SID Log {}: {}", self.file_name, e))
+   }
+
+   fn finish(mut self) -> Result<(), String> {
+      self.file.flush().map_err(|e| format!("SID Log {}: {}", self.file_name, e))
+   }
+}
+
+// every SID register write with the cycle it happened on, written out a frame at a time:
+// as a text log, and/or as a dump of all registers at the end of each frame, 25 bytes
+// per SID
+pub struct SidLog {
+   log: Option<Output>,
+   dump: Option<Output>,
+   cycle: u64,
+   frame: u64,
+   frame_start: u64,
+   writes: Vec<SidWrite>,
+   registers: Vec<(u16, [u8; SID_REGISTERS])>,
+}
+
+impl SidLog {
+   // sids are the base addresses of the SIDs in the machine, in dump order
+   pub fn create(log: Option<&str>, dump: Option<&str>, sids: &[u16]) -> Result<Self, String> {
+      let mut sid_log = Self {
+         log: log.map(Output::create).transpose()?,
+         dump: dump.map(Output::create).transpose()?,
+         cycle: 0,
+         frame: 0,
+         frame_start: 0,
+         writes: Vec::new(),
+         registers: sids.iter().map(|&address| (address, [0; SID_REGISTERS])).collect(),
+      };
+      if let Some(log) = &mut sid_log.log {
+         log.write(b"; cycle      +frame  address value\n")?;
+      }
+      Ok(sid_log)
+   }
+
+   // one φ2 cycle
+   pub fn clock(&mut self) {
+      self.cycle += 1;
+   }
+
+   // base is the address of the SID that was written, reg the register within it
+   pub fn record(&mut self, base: u16, reg: u8, value: u8) {
+      self.writes.push(SidWrite {
+         cycle: self.cycle,
+         address: base + reg as u16,
+         value: value,
+      });
+
+      // a SID that appears later, e.g. for a tune that needs one, goes at the end of the dump
+      let index = match self.registers.iter().position(|(address, _)| *address == base) {
+         Some(index) => index,
+         None        => {
+            self.registers.push((base, [0; SID_REGISTERS]));
+            self.registers.len() - 1
+         }
+      };
+      if (reg as usize) < SID_REGISTERS {
+         self.registers[index].1[reg as usize] = value;
+      }
+   }
+
+   pub fn end_frame(&mut self) -> Result<(), String> {
+      if let Some(log) = &mut self.log {
+         let mut text = format!("frame {}\n", self.frame);
+         for write in &self.writes {
+            text += &format!("{:<12} {:<7} ${:04X}   ${:02X}\n",
+               write.cycle, write.cycle - self.frame_start, write.address, write.value);
+         }
+         log.write(text.as_bytes())?;
+      }
+      if let Some(dump) = &mut self.dump {
+         let data: Vec<u8> = self.registers.iter().flat_map(|(_, registers)| *registers).collect();
+         dump.write(&data)?;
+      }
+
+      self.writes.clear();
+      self.frame += 1;
+      self.frame_start = self.cycle;
+      Ok(())
+   }
+
+   pub fn finish(self) -> Result<(), String> {
+      if let Some(log) = self.log {
+         log.finish()?;
+      }
+      if let Some(dump) = self.dump {
+         dump.finish()?;
+      }
+      Ok(())
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn temp_file(name: &str) -> String {
+      std::env::temp_dir().join(format!("sidlog-{}-{}", std::process::id(), name)).to_string_lossy().into_owned()
+   }
+
+   fn run_cycles(sid_log: &mut SidLog, cycles: u64) {
+      for _ in 0..cycles {
+         sid_log.clock();
+      }
+   }
+
+   #[test]
+   fn log_lists_each_write_under_its_frame() {
+      let file_name = temp_file("log.txt");
+      let mut sid_log = SidLog::create(Some(&file_name), None, &[0xD400]).unwrap();
+      run_cycles(&mut sid_log, 100);
+      sid_log.record(0xD400, 0x18, 0x0F);
+      run_cycles(&mut sid_log, 19556);
+      sid_log.end_frame().unwrap();
+      run_cycles(&mut sid_log, 20);
+      sid_log.record(0xD400, 0x04, 0x41);
+      sid_log.record(0xD420, 0x1B, 0x00);
+      sid_log.end_frame().unwrap();
+      sid_log.finish().unwrap();
+
+      let text = std::fs::read_to_string(&file_name).unwrap();
+      std::fs::remove_file(&file_name).unwrap();
+      assert_eq!(text, concat!(
+         "; cycle      +frame  address value\n",
+         "frame 0\n",
+         "100          100     $D418   $0F\n",
+         "frame 1\n",
+         "19676        20      $D404   $41\n",
+         "19676        20      $D43B   $00\n"
+      ));
+   }
+
+   #[test]
+   fn dump_holds_the_registers_of_every_sid_per_frame() {
+      let file_name = temp_file("dump.bin");
+      let mut sid_log = SidLog::create(None, Some(&file_name), &[0xD400, 0xD420]).unwrap();
+      let frames: [&[(u16, u8, u8)]; 3] = [
+         &[(0xD400, 0x00, 0x11), (0xD420, 0x18, 0x0F), (0xD400, 0x00, 0x12)],
+         &[],
+         // a third SID joins at the end, registers past $18 aren't dumped
+         &[(0xDE00, 0x01, 0x22), (0xD400, 0x1B, 0x99)],
+      ];
+      for writes in frames {
+         for &(base, reg, value) in writes {
+            sid_log.record(base, reg, value);
+         }
+         sid_log.end_frame().unwrap();
+      }
+      sid_log.finish().unwrap();
+
+      let data = std::fs::read(&file_name).unwrap();
+      std::fs::remove_file(&file_name).unwrap();
+      assert_eq!(data.len(), (2 + 2 + 3) * SID_REGISTERS);
+      let blocks: Vec<&[u8]> = data.chunks(SID_REGISTERS).collect();
+      let registers = |block: &[u8]| -> Vec<(usize, u8)> {
+         block.iter().copied().enumerate().filter(|&(_, value)| value != 0).collect()
+      };
+      // frame 0, and frame 1 which keeps the registers as they were
+      for frame in 0..2 {
+         assert_eq!(registers(blocks[frame * 2]), [(0x00, 0x12)]);
+         assert_eq!(registers(blocks[frame * 2 + 1]), [(0x18, 0x0F)]);
+      }
+      assert_eq!(registers(blocks[4]), [(0x00, 0x12)]);
+      assert_eq!(registers(blocks[5]), [(0x18, 0x0F)]);
+      assert_eq!(registers(blocks[6]), [(0x01, 0x22)]);
+   }
+
+   #[test]
+   fn files_that_cannot_be_created_are_reported() {
+      let result = SidLog::create(Some("/nonexistent/dir/log.txt"), None, &[0xD400]);
+      assert!(result.err().unwrap().starts_with("SID Log /nonexistent/dir/log.txt: "));
+   }
+}
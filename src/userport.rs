@@ -0,0 +1,62 @@
+// what is plugged into the user port's serial lines
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UserPortDevice {
+   None,
+   // the diagnostic plug that ties CNT1/SP1 to CNT2/SP2
+   Loopback,
+}
+
+impl UserPortDevice {
+   pub fn from_name(name: &str) -> Result<UserPortDevice, String> {
+      match name.to_lowercase().as_str() {
+         "none"      => Ok(UserPortDevice::None),
+         "loopback"  => Ok(UserPortDevice::Loopback),
+         _           => Err(format!("Unknown User Port Device: {}", name))
+      }
+   }
+}
+
+// the levels on one CIA's CNT and SP lines, true is released (high)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SerialPins {
+   pub cnt: bool,
+   pub sp: bool,
+}
+
+impl SerialPins {
+   pub const RELEASED: SerialPins = SerialPins { cnt: true, sp: true };
+}
+
+// a device on CNT1/SP1 and CNT2/SP2, clocked once per cycle with what CIA 1 and CIA 2
+// drive and answering with what it drives on each pair; the lines are open collector,
+// so a CIA sees the AND of its own output and every device's
+pub trait SerialDevice {
+   fn clock(&mut self, pins: [SerialPins; 2]) -> [SerialPins; 2];
+}
+
+// each CIA sees what the other drives
+pub struct LoopbackPlug;
+
+impl SerialDevice for LoopbackPlug {
+   fn clock(&mut self, pins: [SerialPins; 2]) -> [SerialPins; 2] {
+      [pins[1], pins[0]]
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn device_names_parse() {
+      assert_eq!(UserPortDevice::from_name("Loopback"), Ok(UserPortDevice::Loopback));
+      assert_eq!(UserPortDevice::from_name("none"), Ok(UserPortDevice::None));
+      assert!(UserPortDevice::from_name("modem").is_err());
+   }
+
+   #[test]
+   fn loopback_crosses_the_lines() {
+      let pins = [SerialPins { cnt: false, sp: true }, SerialPins { cnt: true, sp: false }];
+      assert_eq!(LoopbackPlug.clock(pins), [pins[1], pins[0]]);
+   }
+}
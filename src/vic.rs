@@ -1,98 +1,1044 @@
+use crate::framebuffer::*;
 use crate::memory::*;
+use crate::model::*;
+use crate::palette::*;
 
-const SPRITE_LOC_PTR          : u16 = 0xD000;      
-const X_MSBS                  : u16 = 0xD010;
-const Y_SCROLL                : u16 = 0xD011;
-const RASTER_COUNTER          : u16 = 0xD012;      
-const LIGHT_PEN_X             : u16 = 0xD013;   
-const LIGHT_PEN_Y             : u16 = 0xD014;   
-const SPRITE_ENABLE           : u16 = 0xD015;      
-const X_SCROLL                : u16 = 0xD016;
-const SPRITE_Y_EXPANSION      : u16 = 0xD017;            
-const MEM_PTR                 : u16 = 0xD018;
-const INTERRUPT_REG           : u16 = 0xD019;      
-const INTERRUPT_ENABLE        : u16 = 0xD01A;         
-const SPRITE_DATA_PRIORITY    : u16 = 0xD01B;            
-const SPRITE_MULTY_COL        : u16 = 0xD01C;         
-const SPRITE_X_EXPANSION      : u16 = 0xD01D;            
-const SPRITE_SPRITE_COLLISION : u16 = 0xD01E;               
-const SPRITE_DATA_COLLISION   : u16 = 0xD01F;               
-const BORDER_COLOR            : u16 = 0xD020;      
-const BACKGROUND_COLOR_0      : u16 = 0xD021;            
-const BACKGROUND_COLOR_1      : u16 = 0xD022;            
-const BACKGROUND_COLOR_2      : u16 = 0xD023;            
-const BACKGROUND_COLOR_3      : u16 = 0xD024;            
-const SPRITE_MULTY_COL0       : u16 = 0xD025;         
-const SPRITE_MULTY_COL1       : u16 = 0xD026;         
-const COLOR_SPRITE_PTR        : u16 = 0xD027;         
+pub const SPRITE_LOC_PTR      : u16 = 0xD000;      
+pub const X_MSBS              : u16 = 0xD010;
+pub const Y_SCROLL            : u16 = 0xD011;
+pub const RASTER_COUNTER      : u16 = 0xD012;      
+pub const LIGHT_PEN_X         : u16 = 0xD013;   
+pub const LIGHT_PEN_Y         : u16 = 0xD014;   
+pub const SPRITE_ENABLE       : u16 = 0xD015;      
+pub const X_SCROLL            : u16 = 0xD016;
+pub const SPRITE_Y_EXPANSION  : u16 = 0xD017;            
+pub const MEM_PTR             : u16 = 0xD018;
+pub const INTERRUPT_REG       : u16 = 0xD019;      
+pub const INTERRUPT_ENABLE    : u16 = 0xD01A;         
+pub const SPRITE_DATA_PRIORITY    : u16 = 0xD01B;            
+pub const SPRITE_MULTY_COL    : u16 = 0xD01C;         
+pub const SPRITE_X_EXPANSION  : u16 = 0xD01D;            
+pub const SPRITE_SPRITE_COLLISION : u16 = 0xD01E;               
+pub const SPRITE_DATA_COLLISION   : u16 = 0xD01F;               
+pub const BORDER_COLOR        : u16 = 0xD020;      
+pub const BACKGROUND_COLOR_0  : u16 = 0xD021;            
+pub const BACKGROUND_COLOR_1  : u16 = 0xD022;            
+pub const BACKGROUND_COLOR_2  : u16 = 0xD023;            
+pub const BACKGROUND_COLOR_3  : u16 = 0xD024;            
+pub const SPRITE_MULTY_COL0   : u16 = 0xD025;         
+pub const SPRITE_MULTY_COL1   : u16 = 0xD026;         
+pub const COLOR_SPRITE_PTR    : u16 = 0xD027;         
+
+const FIRST_DMA_LINE          : u16 = 0x30;
+const LAST_DMA_LINE           : u16 = 0xF7;
+
+#[derive(Clone, Copy)]
+struct Sprite {
+   dma: bool,
+   display: bool,
+   exp_flop: bool,
+   mc: u8,
+   mc_base: u8,
+   data: u32,
+   armed: bool,
+   shift: u32,
+   bits: u8,
+   x_flop: bool,
+   mc_flop: bool,
+   mc_value: u8,
+}
+
+impl Sprite {
+   fn new() -> Self {
+      Self {
+         dma: false,
+         display: false,
+         exp_flop: true,
+         mc: 0,
+         mc_base: 0,
+         data: 0,
+         armed: false,
+         shift: 0,
+         bits: 0,
+         x_flop: false,
+         mc_flop: false,
+         mc_value: 0,
+      }
+   }
+}
 
 pub struct Vic {
-   palette: [u32; 16],
+   model: VicModel,
+   cycles_per_line: u16,
+   raster_lines: u16,
+   sprite_fetch: [u16; 8],
+   palette: Palette,
+   frame_done: bool,
+   raster: u16,
+   cycle: u16,
+   vc: u16,
+   vc_base: u16,
+   rc: u8,
+   vmli: usize,
+   display_state: bool,
+   bad_line: bool,
+   den_latch: bool,
+   matrix: [u8; 40],
+   colors: [u8; 40],
+   gfx_next: Option<(u8, u16)>,
+   gfx_shift: u8,
+   gfx_c: u16,
+   gfx_mc_flop: bool,
+   gfx_mc_value: u8,
+   sprites: [Sprite; 8],
+   main_border: bool,
+   vertical_border: bool,
+   light_pen: Option<(usize, usize)>,
+   light_pen_latched: bool,
 }
 
 impl Vic {
-   pub fn new() -> Self {
+   pub fn new(model: VicModel) -> Self {
+      let cycles_per_line = model.cycles_per_line();
+      let raster_lines = model.raster_lines();
+
       Self {
-         palette: [0u32; 16],
+         model: model,
+         cycles_per_line: cycles_per_line,
+         raster_lines: raster_lines,
+         sprite_fetch: model.sprite_fetch_cycles(),
+         palette: Palette::new(PaletteKind::Pepto),
+         frame_done: false,
+         raster: 0,
+         cycle: 1,
+         vc: 0,
+         vc_base: 0,
+         rc: 0,
+         vmli: 0,
+         display_state: false,
+         bad_line: false,
+         den_latch: false,
+         matrix: [0u8; 40],
+         colors: [0u8; 40],
+         gfx_next: None,
+         gfx_shift: 0,
+         gfx_c: 0,
+         gfx_mc_flop: false,
+         gfx_mc_value: 0,
+         sprites: [Sprite::new(); 8],
+         main_border: true,
+         vertical_border: true,
+         light_pen: None,
+         light_pen_latched: false,
       }
    }
 
    pub fn init(&mut self, mem: &mut dyn IOVic) {
-      let mut n = 0;
-      for i in &[
-         0x000000,
-         0xffffff,
-         0x68372b,
-         0x70a4b2,
-         0x6f3d86,
-         0x588d43,
-         0x352879,
-         0xb8c76f,
-         0x6f4f25,
-         0x433900,
-         0x9a6759,
-         0x444444,
-         0x6c6c6c,
-         0x9ad284,
-         0x6c5eb5,
-         0x959595
-      ] {
-         self.palette[n] = *i;
-         n += 1;
-      }
-
       // SET INITIAL REGISTER VALUES
       mem.write(X_SCROLL, 0b00001000);
       mem.write(Y_SCROLL, 0b10011011);
    }
 
-   fn get_rgb(&self, index: u8) -> (u8, u8, u8) {
-      match index {
-         0..=15 => (
-            (self.palette[index as usize+0] >> 16) as u8 & 0xff, 
-            (self.palette[index as usize+1] >>  8) as u8 & 0xff, 
-            (self.palette[index as usize+2] >>  0) as u8 & 0xff, 
-         ),
-         _      => panic!("Color Palette Out-Of-Range: {}", index)
+   pub fn set_palette(&mut self, palette: Palette) {
+      self.palette = palette;
+   }
+
+   pub fn palette(&self) -> &Palette {
+      &self.palette
+   }
+
+   pub fn raster(&self) -> u16 {
+      self.raster
+   }
+
+   pub fn cycle(&self) -> u16 {
+      self.cycle
+   }
+
+   pub fn bad_line(&self) -> bool {
+      self.bad_line
+   }
+
+   pub fn sprite_dma(&self) -> bool {
+      self.sprites.iter().any(|s| s.dma)
+   }
+
+   pub fn cycles_per_line(&self) -> u16 {
+      self.cycles_per_line
+   }
+
+   pub fn model(&self) -> VicModel {
+      self.model
+   }
+
+   pub fn frame_width(&self) -> usize {
+      self.cycles_per_line as usize * 8
+   }
+
+   pub fn frame_height(&self) -> usize {
+      self.raster_lines as usize
+   }
+
+   // (column, raster line) in frame coordinates while the pen is held against the screen
+   pub fn set_light_pen(&mut self, position: Option<(usize, usize)>) {
+      self.light_pen = position;
+   }
+
+   pub fn frame_ready(&mut self) -> bool {
+      let done = self.frame_done;
+      self.frame_done = false;
+      done
+   }
+
+   pub fn irq(&self, mem: &dyn IOVic) -> bool {
+      mem.read(INTERRUPT_REG) & 0x80 != 0
+   }
+
+   // BA is pulled low while the VIC needs the bus for c-accesses or sprite DMA
+   pub fn ba_low(&self) -> bool {
+      if self.bad_line && (12..=54).contains(&self.cycle) {
+         return true;
       }
+      (0..8).any(|n| {
+         let fetch = self.sprite_fetch[n];
+         self.sprites[n].dma && (self.cycle == fetch || self.cycle == fetch % self.cycles_per_line + 1)
+      })
    }
 
-   pub fn read_register(&self, mem: &dyn IOVic, address: u16) -> Result<u8, String> {
-      match address {
-         SPRITE_LOC_PTR..=0xD00F => {
-            let index = ((address & 0x000F) >> 1) as u16;
-            if address % 2 == 0 {
-               Ok(mem.read(index))        // x
-            } else {
-               Ok(mem.read(index + 1))    // y
+   pub fn clock(&mut self, mem: &mut dyn IOVic, frame: &mut FrameBuffer) {
+      if self.cycle == 1 {
+         self.start_line(mem);
+      }
+
+      let d011 = mem.read(Y_SCROLL);
+      if self.raster == FIRST_DMA_LINE && d011 & 0x10 != 0 {
+         self.den_latch = true;
+      }
+      self.bad_line = self.raster >= FIRST_DMA_LINE
+         && self.raster <= LAST_DMA_LINE
+         && (self.raster & 7) as u8 == d011 & 7
+         && self.den_latch;
+      if self.bad_line {
+         self.display_state = true;
+      }
+
+      match self.cycle {
+         14 => {
+            self.vc = self.vc_base;
+            self.vmli = 0;
+            if self.bad_line {
+               self.rc = 0;
             }
          },
-         Y_SCROLL                => {
-            let addr_val = mem.read(address);
-            Ok((addr_val & 0b01111111) | ((mem.read(address) as u16 & 0b100000000) >> 1) as u8)
+         55 => self.sprite_dma_check(mem, true),
+         56 => self.sprite_dma_check(mem, false),
+         58 => {
+            if self.rc == 7 {
+               self.vc_base = self.vc;
+               if !self.bad_line {
+                  self.display_state = false;
+               }
+            }
+            if self.display_state {
+               self.rc = (self.rc + 1) & 7;
+            }
+            for n in 0..8 {
+               let sprite = &mut self.sprites[n];
+               sprite.mc = sprite.mc_base;
+               if sprite.dma && mem.read(SPRITE_LOC_PTR + n as u16 * 2 + 1) == self.raster as u8 {
+                  sprite.display = true;
+               }
+            }
          },
-         RASTER_COUNTER          => Ok(mem.read(address)),
-         _                       => Ok(mem.read(address))
+         _  => {}
+      }
+
+      if self.bad_line && (15..=54).contains(&self.cycle) {
+         self.c_access(mem);
+      }
+      if (16..=55).contains(&self.cycle) {
+         self.g_access(mem);
+      }
+      self.sprite_mc_base(mem);
+      if let Some(n) = self.sprite_fetch.iter().position(|&fetch| fetch == self.cycle) {
+         self.s_access(mem, n);
+      }
+
+      self.draw(mem, frame);
+      self.light_pen_check(mem);
+
+      if self.cycle == self.cycles_per_line {
+         self.vertical_border_check(d011);
+      }
+
+      self.cycle += 1;
+      if self.cycle > self.cycles_per_line {
+         self.cycle = 1;
+         self.raster += 1;
+         if self.raster == self.raster_lines {
+            self.raster = 0;
+            self.frame_done = true;
+         }
+      }
+   }
+
+   fn start_line(&mut self, mem: &mut dyn IOVic) {
+      if self.raster == 0 {
+         self.vc_base = 0;
+         self.den_latch = false;
+         self.light_pen_latched = false;
+      }
+
+      let d011 = mem.read(Y_SCROLL);
+      mem.write(Y_SCROLL, (d011 & 0x7F) | ((self.raster >> 1) as u8 & 0x80));
+      mem.write(RASTER_COUNTER, self.raster as u8);
+
+      if self.raster == mem.raster_compare() {
+         self.set_irq(mem, 1 << 0);
+      }
+   }
+
+   // the pen sees the beam when it passes under it; only the first hit per frame is latched
+   fn light_pen_check(&mut self, mem: &mut dyn IOVic) {
+      let (column, line) = match self.light_pen {
+         Some(position) => position,
+         None           => return
+      };
+      let start = (self.cycle as usize - 1) * 8;
+      if self.light_pen_latched || line != self.raster as usize || column < start || column >= start + 8 {
+         return;
+      }
+
+      self.light_pen_latched = true;
+      let x = self.x_position((column - start) as u16);
+      mem.write(LIGHT_PEN_X, (x >> 1) as u8);
+      mem.write(LIGHT_PEN_Y, self.raster as u8);
+      self.set_irq(mem, 1 << 3);
+   }
+
+   fn set_irq(&self, mem: &mut dyn IOVic, flag: u8) {
+      let reg = mem.read(INTERRUPT_REG) | flag;
+      if reg & mem.read(INTERRUPT_ENABLE) & 0x0F != 0 {
+         mem.write(INTERRUPT_REG, reg | 0x80);
+      } else {
+         mem.write(INTERRUPT_REG, reg);
+      }
+   }
+
+   // $D018 bits 4-7: video matrix in 1K steps
+   pub fn video_matrix(mem: &dyn IOVic) -> u16 {
+      (mem.read(MEM_PTR) as u16 & 0xF0) << 6
+   }
+
+   // $D018 bits 1-3: character generator in 2K steps
+   pub fn char_base(mem: &dyn IOVic) -> u16 {
+      (mem.read(MEM_PTR) as u16 & 0x0E) << 10
+   }
+
+   // $D018 bit 3: bitmap in the lower or upper 8K
+   pub fn bitmap_base(mem: &dyn IOVic) -> u16 {
+      (mem.read(MEM_PTR) as u16 & 0x08) << 10
+   }
+
+   fn c_access(&mut self, mem: &mut dyn IOVic) {
+      let index = (self.cycle - 15) as usize;
+      let vc = (self.vc_base + index as u16) & 0x3FF;
+      self.matrix[index] = mem.fetch(Self::video_matrix(mem) | vc);
+      self.colors[index] = mem.fetch_color(vc);
+   }
+
+   fn g_access(&mut self, mem: &mut dyn IOVic) {
+      let d011 = mem.read(Y_SCROLL);
+      let ecm = d011 & 0x40 != 0;
+      let bmm = d011 & 0x20 != 0;
+      let mask = if ecm { 0x39FF } else { 0x3FFF };
+
+      if self.display_state {
+         let char_code = self.matrix[self.vmli];
+         let addr = if bmm {
+            Self::bitmap_base(mem) | ((self.vc & 0x3FF) << 3) | self.rc as u16
+         } else {
+            let code = if ecm { char_code & 0x3F } else { char_code };
+            Self::char_base(mem) | ((code as u16) << 3) | self.rc as u16
+         };
+         let c = ((self.colors[self.vmli] as u16) << 8) | char_code as u16;
+         self.gfx_next = Some((mem.fetch(addr & mask), c));
+         self.vc = (self.vc + 1) & 0x3FF;
+         self.vmli += 1;
+      } else {
+         self.gfx_next = Some((mem.fetch(0x3FFF & mask), 0));
+      }
+   }
+
+   fn sprite_dma_check(&mut self, mem: &mut dyn IOVic, toggle_expansion: bool) {
+      let enable = mem.read(SPRITE_ENABLE);
+      let y_expand = mem.read(SPRITE_Y_EXPANSION);
+      for n in 0..8 {
+         let bit = 1 << n;
+         let sprite = &mut self.sprites[n];
+         if toggle_expansion {
+            sprite.exp_flop = if y_expand & bit != 0 { !sprite.exp_flop } else { true };
+         }
+         if enable & bit != 0
+            && !sprite.dma
+            && mem.read(SPRITE_LOC_PTR + n as u16 * 2 + 1) == self.raster as u8 {
+            sprite.dma = true;
+            sprite.mc_base = 0;
+            if y_expand & bit != 0 {
+               sprite.exp_flop = false;
+            }
+         }
+      }
+   }
+
+   fn sprite_mc_base(&mut self, mem: &mut dyn IOVic) {
+      if self.cycle != 15 && self.cycle != 16 {
+         return;
+      }
+      let y_expand = mem.read(SPRITE_Y_EXPANSION);
+      for n in 0..8 {
+         let sprite = &mut self.sprites[n];
+         if y_expand & (1 << n) == 0 {
+            sprite.exp_flop = true;
+         }
+         if !sprite.exp_flop {
+            continue;
+         }
+         if self.cycle == 15 {
+            sprite.mc_base = (sprite.mc_base + 2) & 0x3F;
+         } else {
+            sprite.mc_base = (sprite.mc_base + 1) & 0x3F;
+            if sprite.mc_base == 63 {
+               sprite.dma = false;
+               sprite.display = false;
+            }
+         }
+      }
+   }
+
+   fn s_access(&mut self, mem: &mut dyn IOVic, n: usize) {
+      if !self.sprites[n].dma {
+         return;
+      }
+      let pointer = (mem.fetch(Self::video_matrix(mem) | 0x3F8 | n as u16) as u16) << 6;
+      let sprite = &mut self.sprites[n];
+      let mut data = 0u32;
+      for _ in 0..3 {
+         data = (data << 8) | mem.fetch(pointer | sprite.mc as u16) as u32;
+         sprite.mc = (sprite.mc + 1) & 0x3F;
+      }
+      if sprite.display {
+         sprite.data = data;
+         sprite.armed = true;
+      }
+   }
+
+   // RSEL selects 25 rows (51-251) or 24 rows (55-247)
+   fn vertical_compare(d011: u8) -> (u16, u16) {
+      if d011 & 0x08 != 0 { (51, 251) } else { (55, 247) }
+   }
+
+   // CSEL selects 40 columns (24-344) or 38 columns (31-335)
+   fn horizontal_compare(d016: u8) -> (u16, u16) {
+      if d016 & 0x08 != 0 { (24, 344) } else { (31, 335) }
+   }
+
+   fn vertical_border_check(&mut self, d011: u8) {
+      let (top, bottom) = Self::vertical_compare(d011);
+      if self.raster == bottom {
+         self.vertical_border = true;
+      } else if self.raster == top && d011 & 0x10 != 0 {
+         self.vertical_border = false;
+      }
+   }
+
+   fn border_unit(&mut self, x: u16, d011: u8, d016: u8) -> bool {
+      let (left, right) = Self::horizontal_compare(d016);
+      if x == right {
+         self.main_border = true;
+      }
+      if x == left {
+         self.vertical_border_check(d011);
+         if !self.vertical_border {
+            self.main_border = false;
+         }
+      }
+      self.main_border
+   }
+
+   fn x_position(&self, pixel: u16) -> u16 {
+      let cycles = self.cycles_per_line;
+      ((self.cycle + cycles - 13) % cycles * 8 + pixel) % (cycles * 8)
+   }
+
+   fn draw(&mut self, mem: &mut dyn IOVic, frame: &mut FrameBuffer) {
+      let d011 = mem.read(Y_SCROLL);
+      let d016 = mem.read(X_SCROLL);
+      let mode = ((d011 & 0x60) | (d016 & 0x10)) >> 4;
+      let x_scroll = (d016 & 7) as u16;
+      let border = mem.read(BORDER_COLOR) & 0x0F;
+      let background = background_colors(mem);
+
+      let sprites_active = self.sprites.iter().any(|s| s.display || s.bits > 0);
+      let column = (self.cycle as usize - 1) * 8;
+
+      for pixel in 0..8 {
+         if pixel == x_scroll {
+            if let Some((data, c)) = self.gfx_next.take() {
+               self.gfx_shift = data;
+               self.gfx_c = c;
+               self.gfx_mc_flop = false;
+            }
+         }
+
+         let (mut color, foreground) = self.gfx_pixel(mode, &background);
+         let x = self.x_position(pixel);
+
+         if sprites_active {
+            if let Some(sprite_color) = self.sprite_pixel(mem, x, foreground) {
+               color = sprite_color;
+            }
+         }
+
+         if self.border_unit(x, d011, d016) {
+            color = border;
+         }
+
+         frame.put_pixel(column + pixel as usize, self.raster as usize, self.palette.color(color));
+      }
+   }
+
+   fn gfx_pixel(&mut self, mode: u8, background: &[u8; 4]) -> (u8, bool) {
+      let c = self.gfx_c;
+      let multicolor = graphics_multicolor(mode, c);
+
+      let value = if multicolor {
+         if !self.gfx_mc_flop {
+            self.gfx_mc_value = self.gfx_shift >> 6;
+         }
+         self.gfx_mc_value
+      } else {
+         self.gfx_shift >> 7
+      };
+      let color = graphics_color(mode, multicolor, value, c, background);
+      let foreground = if multicolor { value & 2 != 0 } else { value != 0 };
+
+      self.gfx_shift <<= 1;
+      self.gfx_mc_flop = !self.gfx_mc_flop;
+      (color, foreground)
+   }
+
+   fn sprite_pixel(&mut self, mem: &mut dyn IOVic, x: u16, foreground: bool) -> Option<u8> {
+      let msb = mem.read(X_MSBS);
+      let x_expand = mem.read(SPRITE_X_EXPANSION);
+      let multicolor = mem.read(SPRITE_MULTY_COL);
+      let priority = mem.read(SPRITE_DATA_PRIORITY);
+
+      let mut output = None;
+      let mut hits = 0u8;
+
+      for n in (0..8).rev() {
+         let bit = 1 << n;
+         let sprite = &mut self.sprites[n];
+         let sprite_x = mem.read(SPRITE_LOC_PTR + n as u16 * 2) as u16 | if msb & bit != 0 { 0x100 } else { 0 };
+
+         if sprite.armed && x == sprite_x {
+            sprite.armed = false;
+            sprite.shift = sprite.data;
+            sprite.bits = 24;
+            sprite.x_flop = false;
+            sprite.mc_flop = false;
+         }
+         if sprite.bits == 0 {
+            continue;
+         }
+
+         let value = if multicolor & bit != 0 {
+            if !sprite.mc_flop {
+               sprite.mc_value = (sprite.shift >> 22) as u8 & 3;
+            }
+            sprite.mc_value
+         } else {
+            ((sprite.shift >> 23) as u8 & 1) << 1
+         };
+
+         sprite.x_flop = !sprite.x_flop;
+         if x_expand & bit == 0 || !sprite.x_flop {
+            sprite.shift <<= 1;
+            sprite.bits -= 1;
+            sprite.mc_flop = !sprite.mc_flop;
+         }
+
+         if value == 0 {
+            continue;
+         }
+         hits |= bit;
+         let color = sprite_color(mem, n, value);
+         if !(foreground && priority & bit != 0) {
+            output = Some(color);
+         } else {
+            output = None;
+         }
+      }
+
+      if hits != 0 && foreground {
+         let old = mem.read(SPRITE_DATA_COLLISION);
+         mem.write(SPRITE_DATA_COLLISION, old | hits);
+         if old == 0 {
+            self.set_irq(mem, 1 << 1);
+         }
+      }
+      if hits.count_ones() > 1 {
+         let old = mem.read(SPRITE_SPRITE_COLLISION);
+         mem.write(SPRITE_SPRITE_COLLISION, old | hits);
+         if old == 0 {
+            self.set_irq(mem, 1 << 2);
+         }
       }
+
+      output
    }
-}
\ No newline at end of file
+}
+
+// whether a cell is drawn with 2-bit multicolor pixels; c is color nibble << 8 | video matrix byte
+pub fn graphics_multicolor(mode: u8, c: u16) -> bool {
+   match mode {
+      1 | 5 => (c >> 8) & 0x08 != 0,
+      3 | 7 => true,
+      _     => false
+   }
+}
+
+// color of a graphics pixel for the ECM/BMM/MCM mode bits
+pub fn graphics_color(mode: u8, multicolor: bool, value: u8, c: u16, background: &[u8; 4]) -> u8 {
+   let color_nibble = (c >> 8) as u8 & 0x0F;
+   if multicolor {
+      match (mode, value) {
+         (1, 3) => color_nibble & 0x07,
+         (1, _) => background[value as usize],
+         (3, 0) => background[0],
+         (3, 1) => (c >> 4) as u8 & 0x0F,
+         (3, 2) => c as u8 & 0x0F,
+         (3, _) => color_nibble,
+         _      => 0
+      }
+   } else {
+      match (mode, value != 0) {
+         (0, true)  => color_nibble,
+         (0, false) => background[0],
+         (1, true)  => color_nibble & 0x07,
+         (1, false) => background[0],
+         (2, true)  => (c >> 4) as u8 & 0x0F,
+         (2, false) => c as u8 & 0x0F,
+         (4, true)  => color_nibble,
+         (4, false) => background[((c >> 6) & 3) as usize],
+         _          => 0
+      }
+   }
+}
+
+// color of sprite n for a pixel value of 1 (MC0), 2 (sprite color) or 3 (MC1)
+pub fn sprite_color(mem: &dyn IOVic, n: usize, value: u8) -> u8 {
+   let color = match value {
+      1 => mem.read(SPRITE_MULTY_COL0),
+      2 => mem.read(COLOR_SPRITE_PTR + n as u16),
+      _ => mem.read(SPRITE_MULTY_COL1),
+   };
+   color & 0x0F
+}
+
+pub fn background_colors(mem: &dyn IOVic) -> [u8; 4] {
+   [
+      mem.read(BACKGROUND_COLOR_0) & 0x0F,
+      mem.read(BACKGROUND_COLOR_1) & 0x0F,
+      mem.read(BACKGROUND_COLOR_2) & 0x0F,
+      mem.read(BACKGROUND_COLOR_3) & 0x0F,
+   ]
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::c64mem::*;
+
+   struct Machine {
+      vic: Vic,
+      mem: C64Mem,
+      frame: FrameBuffer,
+   }
+
+   impl Machine {
+      fn new(model: VicModel) -> Self {
+         let mut vic = Vic::new(model);
+         let mut mem = C64Mem::new();
+         vic.init(&mut mem);
+         let frame = FrameBuffer::new(vic.frame_width(), vic.frame_height());
+         Self { vic: vic, mem: mem, frame: frame }
+      }
+
+      fn clock(&mut self) {
+         self.vic.clock(&mut self.mem, &mut self.frame);
+      }
+
+      // clocks until the next clock is the given cycle of the given line
+      fn run_to(&mut self, raster: u16, cycle: u16) {
+         while self.vic.raster() != raster || self.vic.cycle() != cycle {
+            self.clock();
+         }
+      }
+
+      // the cycles of a line in which BA is low
+      fn ba_cycles(&mut self, raster: u16) -> Vec<u16> {
+         self.run_to(raster, 1);
+         let mut cycles = Vec::new();
+         while self.vic.raster() == raster {
+            if self.vic.ba_low() {
+               cycles.push(self.vic.cycle());
+            }
+            self.clock();
+         }
+         cycles
+      }
+
+      // sprite n at (x, y) with all its pixels set
+      fn solid_sprite(&mut self, n: u16, x: u8, y: u8) {
+         self.mem.write_u8(SPRITE_LOC_PTR + n * 2, x);
+         self.mem.write_u8(SPRITE_LOC_PTR + n * 2 + 1, y);
+         let enable = self.mem.read_u8(SPRITE_ENABLE);
+         self.mem.write_u8(SPRITE_ENABLE, enable | 1 << n);
+         // pointer 13 fetches the data from $0340 with the video matrix at $0000
+         self.mem.ram[0x03F8 + n as usize] = 13;
+         self.mem.ram[0x0340..0x0380].fill(0xFF);
+      }
+   }
+
+   #[test]
+   fn bad_lines_pull_ba_low_from_cycle_12_to_54() {
+      let mut machine = Machine::new(VicModel::Mos6569);
+      // YSCROLL is 3, so $33 is a bad line and $34 is not
+      assert_eq!(machine.ba_cycles(0x33), (12..=54).collect::<Vec<u16>>());
+      assert!(machine.ba_cycles(0x34).is_empty());
+
+      machine.run_to(0x33, 20);
+      assert!(machine.vic.bad_line());
+      machine.run_to(0x34, 20);
+      assert!(!machine.vic.bad_line());
+   }
+
+   #[test]
+   fn bad_lines_need_den_set_on_line_30() {
+      let mut machine = Machine::new(VicModel::Mos6569);
+      machine.run_to(0x2F, 1);
+      machine.mem.write_u8(Y_SCROLL, 0x0B);
+      machine.run_to(0x31, 1);
+      // setting DEN after line $30 leaves the whole frame without bad lines
+      machine.mem.write_u8(Y_SCROLL, 0x1B);
+      for raster in (0x33..=0xF3).step_by(8) {
+         assert!(machine.ba_cycles(raster).is_empty(), "{:X}", raster);
+      }
+   }
+
+   #[test]
+   fn sprite_dma_takes_the_bus_around_the_p_access() {
+      let mut machine = Machine::new(VicModel::Mos6569);
+      machine.solid_sprite(0, 100, 0x80);
+      machine.solid_sprite(3, 100, 0x80);
+      machine.run_to(0x80, 55);
+      assert!(!machine.vic.sprite_dma());
+      // DMA starts in cycle 55 of the line the sprite's Y matches
+      machine.clock();
+      assert!(machine.vic.sprite_dma());
+      machine.run_to(0x80, 58);
+      // sprite 0 fetches in 58 and 59, sprite 3 in 1 and 2 of the next line
+      let mut cycles = Vec::new();
+      while machine.vic.raster() != 0x81 || machine.vic.cycle() != 10 {
+         if machine.vic.ba_low() {
+            cycles.push(machine.vic.cycle());
+         }
+         machine.clock();
+      }
+      assert_eq!(cycles, [58, 59, 1, 2]);
+   }
+
+   #[test]
+   fn sprite_dma_ends_after_21_lines() {
+      let mut machine = Machine::new(VicModel::Mos6569);
+      machine.solid_sprite(0, 100, 0x80);
+      machine.run_to(0x80 + 21, 15);
+      assert!(machine.vic.sprite_dma());
+      machine.run_to(0x80 + 21, 17);
+      assert!(!machine.vic.sprite_dma());
+      assert!(machine.ba_cycles(0x80 + 21).is_empty());
+
+      // Y expansion doubles the lines
+      let mut machine = Machine::new(VicModel::Mos6569);
+      machine.solid_sprite(0, 100, 0x80);
+      machine.mem.write_u8(SPRITE_Y_EXPANSION, 0x01);
+      machine.run_to(0x80 + 41, 17);
+      assert!(machine.vic.sprite_dma());
+      machine.run_to(0x80 + 42, 17);
+      assert!(!machine.vic.sprite_dma());
+   }
+
+   #[test]
+   fn overlapping_sprites_latch_a_collision_until_read() {
+      let mut machine = Machine::new(VicModel::Mos6569);
+      machine.mem.write_u8(INTERRUPT_ENABLE, 0x04);
+      machine.solid_sprite(0, 100, 0x80);
+      machine.solid_sprite(1, 110, 0x80);
+      machine.solid_sprite(2, 200, 0x80);
+      machine.run_to(0x80, 1);
+      assert_eq!(machine.mem.read_u8(SPRITE_SPRITE_COLLISION), 0);
+      machine.run_to(0x82, 1);
+      // sprite 2 touches neither, the background is empty
+      assert_eq!(machine.mem.read_u8(SPRITE_DATA_COLLISION), 0);
+      assert_eq!(machine.mem.read_u8(INTERRUPT_REG) & 0x86, 0x84);
+      assert!(machine.vic.irq(&machine.mem));
+      assert_eq!(machine.mem.read_u8(SPRITE_SPRITE_COLLISION), 0x03);
+      // reading clears the register, it stays clear until the next overlap is drawn
+      assert_eq!(machine.mem.read_u8(SPRITE_SPRITE_COLLISION), 0);
+      machine.run_to(0x83, 1);
+      assert_eq!(machine.mem.read_u8(SPRITE_SPRITE_COLLISION), 0x03);
+   }
+
+   #[test]
+   fn sprites_over_foreground_pixels_collide_with_the_background() {
+      let mut machine = Machine::new(VicModel::Mos6569);
+      // video matrix at $0400, characters at $2000: the first row is a solid character 1
+      machine.mem.write_u8(MEM_PTR, 0x18);
+      machine.mem.ram[0x0400..0x0428].fill(1);
+      machine.mem.ram[0x2008..0x2010].fill(0xFF);
+      machine.mem.ram[0x07F8] = 13;
+      machine.mem.ram[0x0340..0x0380].fill(0xFF);
+      machine.mem.write_u8(SPRITE_ENABLE, 0x03);
+      // sprite 0 overlaps the first row, sprite 1 is below it
+      machine.mem.write_u8(SPRITE_LOC_PTR, 100);
+      machine.mem.write_u8(SPRITE_LOC_PTR + 1, 0x30);
+      machine.mem.write_u8(SPRITE_LOC_PTR + 2, 160);
+      machine.mem.write_u8(SPRITE_LOC_PTR + 3, 0x80);
+      machine.mem.write_u8(INTERRUPT_ENABLE, 0x02);
+      machine.run_to(0x30 + 21 + 1, 1);
+      assert!(machine.vic.irq(&machine.mem));
+      machine.run_to(0x80 + 22, 1);
+      assert_eq!(machine.mem.read_u8(SPRITE_DATA_COLLISION), 0x01);
+      assert_eq!(machine.mem.read_u8(SPRITE_SPRITE_COLLISION), 0);
+   }
+
+   // the (line, cycle) the raster interrupt is raised in, within the next frame
+   fn raster_irq(machine: &mut Machine, compare: u16) -> Option<(u16, u16)> {
+      machine.run_to(1, 1);
+      machine.mem.write_u8(Y_SCROLL, 0x1B | ((compare >> 1) as u8 & 0x80));
+      machine.mem.write_u8(RASTER_COUNTER, compare as u8);
+      machine.mem.write_u8(INTERRUPT_ENABLE, 0x01);
+      machine.mem.write_u8(INTERRUPT_REG, 0x0F);
+      for _ in 0..machine.vic.model().frame_cycles() {
+         let position = (machine.vic.raster(), machine.vic.cycle());
+         machine.clock();
+         if machine.vic.irq(&machine.mem) {
+            return Some(position);
+         }
+      }
+      None
+   }
+
+   #[test]
+   fn raster_irq_fires_in_cycle_1_of_the_compare_line() {
+      for model in [VicModel::Mos6569, VicModel::Mos6567R8, VicModel::Mos6567R56A] {
+         let mut machine = Machine::new(model);
+         let last = model.raster_lines() - 1;
+         assert_eq!(raster_irq(&mut machine, 0), Some((0, 1)), "{:?}", model);
+         assert_eq!(raster_irq(&mut machine, 0x80), Some((0x80, 1)), "{:?}", model);
+         assert_eq!(raster_irq(&mut machine, last), Some((last, 1)), "{:?}", model);
+         assert_eq!(machine.mem.read_u8(INTERRUPT_REG), 0xF1);
+         // past the last line the compare never matches
+         assert_eq!(raster_irq(&mut machine, last + 1), None, "{:?}", model);
+      }
+   }
+
+   #[test]
+   fn raster_register_reads_the_current_line_with_bit_8_in_d011() {
+      let mut machine = Machine::new(VicModel::Mos6569);
+      machine.run_to(311, 2);
+      assert_eq!(machine.mem.read_u8(RASTER_COUNTER), (311 - 256) as u8);
+      assert_eq!(machine.mem.read_u8(Y_SCROLL) & 0x80, 0x80);
+      machine.run_to(0, 2);
+      assert_eq!(machine.mem.read_u8(RASTER_COUNTER), 0);
+      assert_eq!(machine.mem.read_u8(Y_SCROLL) & 0x80, 0);
+   }
+
+   // whether the pixel at sprite coordinate x on a line was drawn in the border color,
+   // with a red border around a blue background
+   fn border_at(machine: &Machine, x: usize, line: usize) -> bool {
+      let color = machine.frame.get_pixel(x + 96, line);
+      assert!(color == machine.vic.palette().color(2) || color == machine.vic.palette().color(6));
+      color == machine.vic.palette().color(2)
+   }
+
+   fn bordered_machine() -> Machine {
+      let mut machine = Machine::new(VicModel::Mos6569);
+      machine.mem.write_u8(BORDER_COLOR, 2);
+      machine.mem.write_u8(BACKGROUND_COLOR_0, 6);
+      // empty characters from RAM
+      machine.mem.write_u8(MEM_PTR, 0x18);
+      machine
+   }
+
+   #[test]
+   fn csel_narrows_the_display_to_38_columns() {
+      let mut machine = bordered_machine();
+      machine.run_to(100, 1);
+      machine.run_to(101, 1);
+      assert_eq!([23, 24, 30, 31, 334, 335, 343, 344].map(|x| border_at(&machine, x, 100)),
+                 [true, false, false, false, false, false, false, true]);
+      machine.mem.write_u8(X_SCROLL, 0x00);
+      machine.run_to(102, 1);
+      assert_eq!([23, 24, 30, 31, 334, 335, 343, 344].map(|x| border_at(&machine, x, 101)),
+                 [true, true, true, false, false, true, true, true]);
+   }
+
+   #[test]
+   fn rsel_narrows_the_display_to_24_rows() {
+      let mut machine = bordered_machine();
+      machine.run_to(260, 1);
+      assert_eq!([50, 51, 54, 55, 246, 247, 250, 251].map(|line| border_at(&machine, 100, line)),
+                 [true, false, false, false, false, false, false, true]);
+      machine.mem.write_u8(Y_SCROLL, 0x13);
+      machine.run_to(0, 1);
+      machine.run_to(260, 1);
+      assert_eq!([50, 51, 54, 55, 246, 247, 250, 251].map(|line| border_at(&machine, 100, line)),
+                 [true, true, true, false, false, true, true, true]);
+   }
+
+   #[test]
+   fn switching_to_24_rows_below_line_247_opens_the_border() {
+      let mut machine = bordered_machine();
+      // the bottom compare of 24 rows has passed and the one of 25 rows is never seen
+      machine.run_to(249, 1);
+      machine.mem.write_u8(Y_SCROLL, 0x13);
+      machine.run_to(260, 1);
+      assert!(!border_at(&machine, 100, 255));
+      machine.run_to(20, 1);
+      assert!(!border_at(&machine, 100, 300));
+      assert!(!border_at(&machine, 100, 10));
+      // back to 25 rows the border closes again at 251 on the next frame
+      machine.mem.write_u8(Y_SCROLL, 0x1B);
+      machine.run_to(260, 1);
+      assert!(!border_at(&machine, 100, 250));
+      assert!(border_at(&machine, 100, 255));
+   }
+
+   #[test]
+   fn clearing_den_keeps_the_top_border_closed() {
+      let mut machine = bordered_machine();
+      machine.run_to(0, 1);
+      machine.mem.write_u8(Y_SCROLL, 0x0B);
+      machine.run_to(100, 1);
+      assert!(border_at(&machine, 100, 60));
+   }
+
+   // the colors of the first two pixels of the display window on its first line
+   fn first_pixels(machine: &mut Machine) -> [u32; 2] {
+      machine.run_to(0x34, 1);
+      [machine.frame.get_pixel(24 + 96, 0x33), machine.frame.get_pixel(25 + 96, 0x33)]
+   }
+
+   // a machine with the VIC in a bank, white on blue and the top left pixel of
+   // character 1 set at $1008 in the RAM of every bank
+   fn banked_machine(bank: u8, mem_ptr: u8) -> Machine {
+      let mut machine = Machine::new(VicModel::Mos6569);
+      machine.mem.write_u8(0xDD02, 0x03);
+      machine.mem.write_u8(0xDD00, !bank & 3);
+      machine.mem.write_u8(MEM_PTR, mem_ptr);
+      machine.mem.write_u8(BACKGROUND_COLOR_0, 6);
+      machine.mem.write_u8(0xD800, 1);
+      for base in [0x0000, 0x4000, 0x8000, 0xC000] {
+         machine.mem.ram[base + 0x0400] = 1;
+         machine.mem.ram[base + 0x1008] = 0x80;
+      }
+      machine
+   }
+
+   #[test]
+   fn the_vic_fetches_from_the_bank_cia2_selects() {
+      let mut machine = banked_machine(1, 0x14);
+      let (white, blue) = (machine.vic.palette().color(1), machine.vic.palette().color(6));
+      assert_eq!(first_pixels(&mut machine), [white, blue]);
+      // the character only exists in bank 1
+      let mut machine = banked_machine(1, 0x14);
+      machine.mem.ram[0x5008] = 0;
+      assert_eq!(first_pixels(&mut machine), [blue, blue]);
+      let mut machine = banked_machine(3, 0x14);
+      assert_eq!(first_pixels(&mut machine), [white, blue]);
+   }
+
+   #[test]
+   fn banks_0_and_2_see_the_character_rom_at_1000() {
+      let mut rom = vec![0u8; 0x1000];
+      rom[0x008] = 0x40;
+      for bank in 0..4 {
+         let mut machine = banked_machine(bank, 0x14);
+         machine.mem.load_rom(&rom, 0xD000);
+         let (white, blue) = (machine.vic.palette().color(1), machine.vic.palette().color(6));
+         let expected = if bank % 2 == 0 { [blue, white] } else { [white, blue] };
+         assert_eq!(first_pixels(&mut machine), expected, "bank {}", bank);
+      }
+   }
+
+   #[test]
+   fn d018_points_at_the_matrix_characters_and_bitmap() {
+      let mut mem = C64Mem::new();
+      mem.write_u8(MEM_PTR, 0x3E);
+      assert_eq!(Vic::video_matrix(&mem), 0x0C00);
+      assert_eq!(Vic::char_base(&mem), 0x3800);
+      assert_eq!(Vic::bitmap_base(&mem), 0x2000);
+
+      // a bitmap takes its colors from the video matrix
+      let mut machine = banked_machine(2, 0x18);
+      machine.mem.write_u8(Y_SCROLL, 0x3B);
+      machine.mem.ram[0x8400] = 0x16;
+      machine.mem.ram[0xA000] = 0x80;
+      let (white, blue) = (machine.vic.palette().color(1), machine.vic.palette().color(6));
+      assert_eq!(first_pixels(&mut machine), [white, blue]);
+   }
+
+   #[test]
+   fn light_pen_latches_the_beam_position_once_per_frame() {
+      let mut machine = Machine::new(VicModel::Mos6569);
+      machine.mem.write_u8(INTERRUPT_ENABLE, 0x08);
+      // frame column 200 is sprite coordinate 104, $D013 holds it halved
+      machine.vic.set_light_pen(Some((200, 100)));
+      machine.run_to(100, 26);
+      assert!(!machine.vic.irq(&machine.mem));
+      machine.run_to(100, 27);
+      assert_eq!(machine.mem.read_u8(LIGHT_PEN_X), 52);
+      assert_eq!(machine.mem.read_u8(LIGHT_PEN_Y), 100);
+      assert_eq!(machine.mem.read_u8(INTERRUPT_REG) & 0x88, 0x88);
+
+      // moving the pen later in the frame doesn't latch again
+      machine.mem.write_u8(INTERRUPT_REG, 0x0F);
+      machine.vic.set_light_pen(Some((300, 200)));
+      machine.run_to(0, 1);
+      assert_eq!(machine.mem.read_u8(LIGHT_PEN_X), 52);
+      assert!(!machine.vic.irq(&machine.mem));
+      // the next frame does
+      machine.run_to(201, 1);
+      assert_eq!(machine.mem.read_u8(LIGHT_PEN_X), 102);
+      assert_eq!(machine.mem.read_u8(LIGHT_PEN_Y), 200);
+      assert!(machine.vic.irq(&machine.mem));
+   }
+
+   #[test]
+   fn light_pen_registers_ignore_writes_and_stay_without_a_pen() {
+      let mut machine = Machine::new(VicModel::Mos6569);
+      machine.vic.set_light_pen(Some((200, 100)));
+      machine.run_to(101, 1);
+      machine.mem.write_u8(LIGHT_PEN_X, 0);
+      machine.mem.write_u8(LIGHT_PEN_Y, 0);
+      assert_eq!((machine.mem.read_u8(LIGHT_PEN_X), machine.mem.read_u8(LIGHT_PEN_Y)), (52, 100));
+      machine.vic.set_light_pen(None);
+      machine.run_to(101, 1);
+      machine.run_to(102, 1);
+      assert_eq!((machine.mem.read_u8(LIGHT_PEN_X), machine.mem.read_u8(LIGHT_PEN_Y)), (52, 100));
+      assert_eq!(machine.mem.read_u8(INTERRUPT_REG) & 0x08, 0x08);
+   }
+}
@@ -0,0 +1,83 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+const HEADER_SIZE     : u32 = 44;
+const BITS_PER_SAMPLE : u16 = 16;
+
+// 16-bit PCM .wav file; the sizes in the header are filled in by finish()
+pub struct WavWriter {
+   file: BufWriter<File>,
+   file_name: String,
+   sample_rate: u32,
+   channels: u16,
+   frames: u32,
+   max_frames: Option<u32>,
+}
+
+impl WavWriter {
+   // seconds limits the recording, later samples are dropped
+   pub fn create(file_name: &str, sample_rate: u32, channels: u16, seconds: Option<f64>) -> Result<Self, String> {
+      let file = File::create(file_name).map_err(|e| format!("Recording {}: {}", file_name, e))?;
+      let mut writer = Self {
+         file: BufWriter::new(file),
+         file_name: file_name.to_string(),
+         sample_rate: sample_rate,
+         channels: channels,
+         frames: 0,
+         max_frames: seconds.map(|seconds| (seconds * sample_rate as f64).round() as u32),
+      };
+      writer.write_header().map_err(|e| format!("Recording {}: {}", file_name, e))?;
+      Ok(writer)
+   }
+
+   fn write_header(&mut self) -> io::Result<()> {
+      let sample_rate = self.sample_rate;
+      let block_align = self.channels * BITS_PER_SAMPLE / 8;
+      let data_size = self.frames * block_align as u32;
+
+      let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+      header.extend_from_slice(b"RIFF");
+      header.extend_from_slice(&(HEADER_SIZE - 8 + data_size).to_le_bytes());
+      header.extend_from_slice(b"WAVEfmt ");
+      header.extend_from_slice(&16u32.to_le_bytes());
+      header.extend_from_slice(&1u16.to_le_bytes());
+      header.extend_from_slice(&self.channels.to_le_bytes());
+      header.extend_from_slice(&sample_rate.to_le_bytes());
+      header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+      header.extend_from_slice(&block_align.to_le_bytes());
+      header.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+      header.extend_from_slice(b"data");
+      header.extend_from_slice(&data_size.to_le_bytes());
+      self.file.write_all(&header)
+   }
+
+   // whether the time limit has been reached
+   pub fn is_full(&self) -> bool {
+      self.max_frames.is_some_and(|max| self.frames >= max)
+   }
+
+   // interleaved samples, -1.0 - 1.0
+   pub fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+      let channels = self.channels as usize;
+      let mut frames = samples.len() / channels;
+      if let Some(max) = self.max_frames {
+         frames = frames.min(max.saturating_sub(self.frames) as usize);
+      }
+
+      let mut data = Vec::with_capacity(frames * channels * 2);
+      for sample in &samples[..frames * channels] {
+         let value = (sample.clamp(-1.0, 1.0) * 32767.0).round() as i16;
+         data.extend_from_slice(&value.to_le_bytes());
+      }
+      self.frames += frames as u32;
+      self.file.write_all(&data).map_err(|e| format!("Recording {}: {}", self.file_name, e))
+   }
+
+   pub fn finish(mut self) -> Result<(), String> {
+      let result = self.file.seek(SeekFrom::Start(0))
+         .and_then(|_| self.write_header())
+         .and_then(|_| self.file.flush());
+      result.map_err(|e| format!("Recording {}: {}", self.file_name, e))
+   }
+}